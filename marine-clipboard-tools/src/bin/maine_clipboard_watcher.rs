@@ -1,8 +1,31 @@
+use wayland_clipboard_listener::ContentRules;
 use wayland_clipboard_listener::WlClipboardPasteStream;
 use wayland_clipboard_listener::WlListenType;
 
 fn main() {
+    let mut rules = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--rules" => match args.next().map(ContentRules::load) {
+                Some(Ok(loaded)) => rules = Some(loaded),
+                Some(Err(e)) => {
+                    eprintln!("cannot read the rules: {e}");
+                    return;
+                }
+                None => {
+                    eprintln!("--rules needs a file");
+                    return;
+                }
+            },
+            _ => {
+                eprintln!("unknown argument {arg}");
+                return;
+            }
+        }
+    }
     let mut stream = WlClipboardPasteStream::init(WlListenType::ListenOnCopy).unwrap();
+    stream.set_rules(rules);
     for context in stream.paste_stream().flatten() {
         println!("{context:?}");
     }
@@ -0,0 +1,276 @@
+use wayland_clipboard_listener::{
+    daemon_socket_path, default_history_dir, detect_mime_types, ClipboardClient, ClipboardEvent,
+    ContentRules, HistoryStore, SubscriptionFilter, WlClipboardDaemonStream, WlCopyTarget,
+};
+
+use std::io::{stdin, stdout, Read, Write};
+use std::path::PathBuf;
+
+const USAGE: &str = "usage: marine [--socket PATH] COMMAND
+the socket is $XDG_RUNTIME_DIR/marine-clipboard.sock by default
+    daemon [--dir DIR] [--no-history] [--persist] [--rules FILE] [--backlog BYTES]
+                       own the clipboard, and serve the other commands, it holds the
+                       history, so marine_register and marine_history go through it, an
+                       encrypted history is locked until unlock
+    copy [--primary|--both] [--type MIMETYPE] [TEXT]
+                       copy TEXT, or stdin
+    paste [--primary] [--type MIMETYPE]
+                       print the selection
+    clear [--primary|--both]
+                       clear the selection
+    history [--limit N] [QUERY]
+                       search the history, or list it
+    select [--primary|--both] ID
+                       copy the history entry ID again
+    lock               lock the encrypted history of the daemon
+    unlock [--key-file FILE|--keyring NAME]
+                       unlock the encrypted history of the daemon, with the passphrase on the
+                       first line of stdin, or the key of FILE, or of NAME in the keyring, it
+                       needs the encryption feature
+    dbus [--address ADDRESS]
+                       serve the daemon on the session bus, or on the bus at ADDRESS, it
+                       needs the dbus feature
+    watch [--primary|--both] [--type MIMETYPE]...
+                       print the changes of the selections, both by default, only the ones
+                       offering MIMETYPE if it is passed, it can end with \"*\", like image/*";
+
+fn daemon(socket: PathBuf, args: Vec<String>) -> Result<(), String> {
+    let mut dir = Some(default_history_dir());
+    let mut persist = None;
+    let mut rules = None;
+    let mut backlog = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dir" => dir = Some(args.next().ok_or("--dir needs a directory")?.into()),
+            "--no-history" => dir = None,
+            "--persist" => persist = Some(WlCopyTarget::Both),
+            "--rules" => {
+                let file = args.next().ok_or("--rules needs a file")?;
+                rules = Some(
+                    ContentRules::load(&file).map_err(|e| format!("cannot read the rules: {e}"))?,
+                );
+            }
+            "--backlog" => {
+                backlog = Some(
+                    args.next()
+                        .and_then(|bytes| bytes.parse().ok())
+                        .ok_or("--backlog needs a number")?,
+                )
+            }
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let history = match dir {
+        Some(dir) => {
+            let store =
+                HistoryStore::open(&dir).map_err(|e| format!("cannot open {dir:?}: {e}"))?;
+            if store.is_locked() {
+                eprintln!("the history is encrypted, nothing is kept until marine unlock");
+            }
+            Some(store)
+        }
+        None => None,
+    };
+    let mut stream = WlClipboardDaemonStream::init(&socket).map_err(|e| format!("{e:?}"))?;
+    stream.set_persist(persist);
+    stream.set_rules(rules);
+    stream.set_history(history);
+    if let Some(backlog) = backlog {
+        stream.set_backlog(backlog);
+    }
+    stream.run().map_err(|e| format!("{e:?}"))
+}
+
+#[cfg(feature = "dbus")]
+fn dbus(socket: PathBuf, args: Vec<String>) -> Result<(), String> {
+    use wayland_clipboard_listener::ClipboardDBusService;
+
+    let mut address = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--address" => address = Some(args.next().ok_or("--address needs an address")?),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let service = match address {
+        Some(address) => ClipboardDBusService::init_on_bus(&socket, &address),
+        None => ClipboardDBusService::init(&socket),
+    }
+    .map_err(|e| format!("cannot serve the daemon at {socket:?} on D-Bus: {e}"))?;
+    service.run().map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "dbus"))]
+fn dbus(_: PathBuf, _: Vec<String>) -> Result<(), String> {
+    Err("marine is built without the dbus feature".to_string())
+}
+
+/// unlock the history of the daemon with the key file, or the key in the keyring, or the
+/// passphrase on the first line of stdin
+#[cfg(feature = "encryption")]
+fn unlock(
+    client: &mut ClipboardClient,
+    key_file: Option<String>,
+    keyring: Option<String>,
+) -> Result<(), String> {
+    use wayland_clipboard_listener::HistoryKey;
+
+    let key = match (key_file, keyring) {
+        (Some(path), _) => HistoryKey::KeyFile(path.into()),
+        (None, Some(description)) => HistoryKey::Keyring(description),
+        (None, None) => {
+            let mut passphrase = String::new();
+            stdin()
+                .read_line(&mut passphrase)
+                .map_err(|e| e.to_string())?;
+            let len = passphrase.trim_end_matches(['\r', '\n']).len();
+            passphrase.truncate(len);
+            HistoryKey::Passphrase(passphrase.into())
+        }
+    };
+    client.unlock(&key).map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "encryption"))]
+fn unlock(_: &mut ClipboardClient, _: Option<String>, _: Option<String>) -> Result<(), String> {
+    Err("marine is built without the encryption feature".to_string())
+}
+
+fn client(socket: PathBuf, command: &str, args: Vec<String>) -> Result<(), String> {
+    let mut target = None;
+    let mut mimetypes = Vec::new();
+    let mut limit = None;
+    let mut key_file = None;
+    let mut keyring = None;
+    let mut rest = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--primary" => target = Some(WlCopyTarget::Primary),
+            "--both" => target = Some(WlCopyTarget::Both),
+            "--type" => mimetypes.push(args.next().ok_or("--type needs a mimetype")?),
+            "--key-file" => key_file = Some(args.next().ok_or("--key-file needs a file")?),
+            "--keyring" => keyring = Some(args.next().ok_or("--keyring needs a key name")?),
+            "--limit" => {
+                limit = Some(
+                    args.next()
+                        .and_then(|limit| limit.parse().ok())
+                        .ok_or("--limit needs a number")?,
+                )
+            }
+            _ => rest.push(arg),
+        }
+    }
+    let mut client = ClipboardClient::connect(&socket)
+        .map_err(|e| format!("cannot connect to the daemon at {socket:?}: {e}"))?;
+    let error = |e: std::io::Error| e.to_string();
+    // the last one is used if --type is passed more than once, except by watch
+    let mimetype = mimetypes.last().cloned();
+    let selection = target.unwrap_or(WlCopyTarget::Clipboard);
+    match command {
+        "copy" => {
+            let data = match rest.pop() {
+                Some(text) => text.into_bytes(),
+                None => {
+                    let mut data = Vec::new();
+                    stdin().read_to_end(&mut data).map_err(error)?;
+                    data
+                }
+            };
+            if data.is_empty() {
+                return Err("You need to pass something in".to_string());
+            }
+            let mimetypes = match &mimetype {
+                Some(mimetype) => vec![mimetype.as_str()],
+                None => detect_mime_types(&data),
+            };
+            let data: std::sync::Arc<[u8]> = data.into();
+            let payloads: Vec<_> = mimetypes
+                .into_iter()
+                .map(|mimetype| (mimetype.to_string(), data.clone()))
+                .collect();
+            client.set(payloads, selection).map_err(error)?;
+        }
+        "paste" => {
+            let primary = selection == WlCopyTarget::Primary;
+            let payloads = client
+                .get(primary, mimetype.as_deref())
+                .map_err(error)?
+                .ok_or("the selection is empty")?;
+            stdout().write_all(&payloads[0].1).map_err(error)?;
+        }
+        "clear" => client.clear(selection).map_err(error)?,
+        "history" => {
+            let query = rest.join(" ");
+            for item in client.history(&query, limit).map_err(error)? {
+                let summary = match &item.text {
+                    Some(text) => text.lines().next().unwrap_or_default().to_string(),
+                    None => format!("[{}]", item.mime_types.join(", ")),
+                };
+                println!("{}\t{summary}", item.id);
+            }
+        }
+        "select" => {
+            let id = rest
+                .pop()
+                .and_then(|id| id.parse().ok())
+                .ok_or("an entry id is needed")?;
+            client.select(id, selection).map_err(error)?;
+        }
+        "lock" => client.lock().map_err(error)?,
+        "unlock" => unlock(&mut client, key_file, keyring)?,
+        "watch" => {
+            let mut filter = SubscriptionFilter::new();
+            filter.set_target(target.unwrap_or(WlCopyTarget::Both));
+            filter.set_mime_types(mimetypes);
+            for event in client.subscribe_filtered(&filter).map_err(error)? {
+                match event.map_err(error)? {
+                    ClipboardEvent::Changed {
+                        primary,
+                        mime_types,
+                        ..
+                    } => println!("changed\t{primary}\t{}", mime_types.join(", ")),
+                    ClipboardEvent::Cleared { primary } => println!("cleared\t{primary}"),
+                    ClipboardEvent::Lagged { missed } => println!("lagged\t{missed}"),
+                }
+            }
+        }
+        _ => return Err(USAGE.to_string()),
+    }
+    Ok(())
+}
+
+fn run() -> Result<(), String> {
+    let mut socket = None;
+    let mut command = None;
+    let mut rest = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--socket" if command.is_none() => {
+                socket = Some(args.next().ok_or("--socket needs a path")?.into())
+            }
+            _ if command.is_none() => command = Some(arg),
+            _ => rest.push(arg),
+        }
+    }
+    let socket = match socket {
+        Some(socket) => socket,
+        None => daemon_socket_path().map_err(|e| format!("{e}, pass --socket"))?,
+    };
+    match command.as_deref() {
+        Some("daemon") => daemon(socket, rest),
+        Some("dbus") => dbus(socket, rest),
+        Some(command) => client(socket, command, rest),
+        None => Err(USAGE.to_string()),
+    }
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+}
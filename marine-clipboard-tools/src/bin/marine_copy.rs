@@ -2,35 +2,189 @@ use nix::{
     fcntl::OFlag,
     unistd::{close, dup2_stdin, dup2_stdout, fork, ForkResult},
 };
-use wayland_clipboard_listener::{WlClipboardCopyStream, WlClipboardListenerError};
+use wayland_clipboard_listener::{
+    detect_mime_types, CopySource, WlClipboardCopyStream, WlClipboardListenerError, WlCopyTarget,
+    TEXT_MIME_TYPES,
+};
 
+use std::fs::File;
 use std::io::{stdin, Read};
+use std::os::fd::AsFd;
+use std::os::unix::fs::FileExt;
+use std::time::Duration;
+
+/// the mimetypes are guessed from the content, unless --type is passed
+/// plain text is converted to the encoding requested by the paste side, and to CRLF line endings
+/// if crlf is true
+/// an image is offered in the other formats too if convert_image is true, it is converted when
+/// pasted
+fn data_source(
+    data: Vec<u8>,
+    mimetype: &Option<String>,
+    crlf: bool,
+    convert_image: bool,
+) -> CopySource {
+    let mimetypes = match mimetype {
+        Some(mimetype) => vec![mimetype.as_str()],
+        None => detect_mime_types(&data),
+    };
+    if convert_image && mimetypes[0].starts_with("image/") {
+        return image_source(data, mimetypes[0]);
+    }
+    if mimetype.is_some() || mimetypes[0] != TEXT_MIME_TYPES[0] {
+        return CopySource::new(data, mimetypes);
+    }
+    match String::from_utf8(data) {
+        Ok(text) => CopySource::text(text, crlf),
+        Err(e) => CopySource::new(e.into_bytes(), mimetypes),
+    }
+}
+
+#[cfg(feature = "image-convert")]
+fn image_source(data: Vec<u8>, mimetype: &str) -> CopySource {
+    CopySource::image(data, mimetype)
+}
+
+#[cfg(not(feature = "image-convert"))]
+fn image_source(data: Vec<u8>, mimetype: &str) -> CopySource {
+    CopySource::new(data, vec![mimetype])
+}
+
+/// stdin as a file, if it is a regular file
+fn regular_stdin() -> Option<File> {
+    let file = File::from(stdin().as_fd().try_clone_to_owned().ok()?);
+    file.metadata().ok()?.is_file().then_some(file)
+}
 
 fn main() -> Result<(), WlClipboardListenerError> {
-    let args = std::env::args();
-    let context = {
-        let len = args.len();
-        if len != 2 {
-            let mut context = vec![];
-            stdin().lock().read_to_end(&mut context).unwrap();
-            context
-        } else {
-            args.last().unwrap().as_bytes().to_vec()
+    let mut clear = false;
+    let mut target = WlCopyTarget::Clipboard;
+    let mut mimetype = None;
+    let mut paste_limit = None;
+    let mut timeout = None;
+    let mut crlf = false;
+    let mut secret = false;
+    let mut convert_image = false;
+    let mut text = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--clear" => clear = true,
+            "--primary" => target = WlCopyTarget::Primary,
+            "--both" => target = WlCopyTarget::Both,
+            "--crlf" => crlf = true,
+            "--secret" => secret = true,
+            "--convert-image" => {
+                if !cfg!(feature = "image-convert") {
+                    eprintln!("marine_copy is built without the image-convert feature");
+                    std::process::exit(1);
+                }
+                convert_image = true;
+            }
+            "--type" => match args.next() {
+                Some(arg) => mimetype = Some(arg),
+                None => {
+                    eprintln!("--type needs a mimetype");
+                    std::process::exit(1);
+                }
+            },
+            "--paste-once" => paste_limit = Some(1),
+            "--paste-count" => match args
+                .next()
+                .and_then(|count| count.parse().ok())
+                .filter(|&count| count > 0)
+            {
+                Some(count) => paste_limit = Some(count),
+                None => {
+                    eprintln!("--paste-count needs a number above 0");
+                    std::process::exit(1);
+                }
+            },
+            "--timeout" => match args
+                .next()
+                .and_then(|secs| secs.parse().ok())
+                .filter(|&secs| secs > 0)
+            {
+                Some(secs) => timeout = Some(Duration::from_secs(secs)),
+                None => {
+                    eprintln!("--timeout needs a number of seconds above 0");
+                    std::process::exit(1);
+                }
+            },
+            // the rest is the text, even if it starts with -
+            "--" => {
+                text = args.next();
+                break;
+            }
+            _ if arg.starts_with('-') => {
+                eprintln!("unknown option {arg}");
+                std::process::exit(1);
+            }
+            _ => text = Some(arg),
         }
+    }
+
+    if clear {
+        let mut stream = WlClipboardCopyStream::init()?;
+        match target {
+            WlCopyTarget::Clipboard => stream.clear()?,
+            WlCopyTarget::Primary => stream.clear_primary()?,
+            WlCopyTarget::Both => {
+                stream.clear()?;
+                stream.clear_primary()?;
+            }
+        }
+        return Ok(());
+    }
+
+    let (len, source) = match text {
+        Some(text) => (
+            text.len() as u64,
+            data_source(text.into_bytes(), &mimetype, crlf, convert_image),
+        ),
+        // a regular file redirected to stdin is served from the disk, not read into memory,
+        // except the text and the images to convert, they are converted when pasted like the
+        // ones read from a pipe
+        None => match regular_stdin() {
+            Some(mut file) => {
+                let mimetypes = match &mimetype {
+                    Some(mimetype) => vec![mimetype.as_str()],
+                    None => {
+                        let mut head = vec![0; 4096];
+                        let read = file.read_at(&mut head, 0).unwrap_or_default();
+                        detect_mime_types(&head[..read])
+                    }
+                };
+                let text = mimetype.is_none() && mimetypes[0] == TEXT_MIME_TYPES[0];
+                if text || (convert_image && mimetypes[0].starts_with("image/")) {
+                    let mut context = vec![];
+                    file.read_to_end(&mut context).unwrap();
+                    let len = context.len() as u64;
+                    (len, data_source(context, &mimetype, crlf, convert_image))
+                } else {
+                    (
+                        file.metadata().map(|meta| meta.len()).unwrap_or_default(),
+                        CopySource::from_file(file, mimetypes),
+                    )
+                }
+            }
+            None => {
+                let mut context = vec![];
+                stdin().lock().read_to_end(&mut context).unwrap();
+                let len = context.len() as u64;
+                (len, data_source(context, &mimetype, crlf, convert_image))
+            }
+        },
     };
-    if context.is_empty() {
+    if len == 0 {
         eprintln!("You need to pass something in");
         return Ok(());
     }
 
-    let mimetypes = vec![
-        "text/plain",
-        "TEXT",
-        "UTF8_STRING",
-        "text/plain;charset=utf-8",
-        "image/png",
-    ];
     let mut stream = WlClipboardCopyStream::init()?;
+    stream.set_paste_limit(paste_limit);
+    stream.set_timeout(timeout);
+    stream.set_secret(secret);
 
     if let Ok(ForkResult::Child) = unsafe { fork() } {
         if let Ok(dev_null) =
@@ -39,7 +193,7 @@ fn main() -> Result<(), WlClipboardListenerError> {
             let _ = dup2_stdin(&dev_null);
             let _ = dup2_stdout(&dev_null);
             let _ = close(dev_null);
-            stream.copy_to_clipboard(context, mimetypes, false)?;
+            stream.copy_source_to_clipboard(source, target)?;
         }
     }
 
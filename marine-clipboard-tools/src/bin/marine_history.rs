@@ -0,0 +1,218 @@
+use wayland_clipboard_listener::{
+    daemon_socket_path, default_history_dir, ClipboardClient, ClipboardHistory, HistoryStore,
+};
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, stdin, stdout, BufRead, BufReader, BufWriter, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+const USAGE: &str = "usage: marine_history [--dir DIR] [KEY] COMMAND
+    export [FILE]      write the history as JSON Lines to FILE, or to stdout
+    import [FILE]      read the history from JSON Lines in FILE, or from stdin
+    cliphist [DB]      import the history of cliphist, from ~/.cache/cliphist/db by default
+    encrypt            encrypt the history with KEY, marine daemon must not run
+the history is reached through marine daemon while it runs, it is unlocked by marine unlock
+KEY unlocks an encrypted history, it needs the encryption feature
+    --passphrase       the passphrase on the first line of stdin
+    --key-file FILE    the key in FILE, at least 32 random bytes
+    --keyring NAME     the user key NAME in the kernel keyring";
+
+/// where the key of the history comes from, see HistoryKey
+#[derive(Default)]
+struct KeyArgs {
+    passphrase: bool,
+    key_file: Option<PathBuf>,
+    keyring: Option<String>,
+}
+
+#[cfg(feature = "encryption")]
+impl KeyArgs {
+    fn key(&self) -> Result<wayland_clipboard_listener::HistoryKey, String> {
+        use wayland_clipboard_listener::HistoryKey;
+
+        if let Some(path) = &self.key_file {
+            return Ok(HistoryKey::KeyFile(path.clone()));
+        }
+        if let Some(description) = &self.keyring {
+            return Ok(HistoryKey::Keyring(description.clone()));
+        }
+        if !self.passphrase {
+            return Err("a key is needed, pass --passphrase, --key-file or --keyring".to_string());
+        }
+        let mut passphrase = String::new();
+        stdin()
+            .read_line(&mut passphrase)
+            .map_err(|e| e.to_string())?;
+        let len = passphrase.trim_end_matches(['\r', '\n']).len();
+        passphrase.truncate(len);
+        Ok(HistoryKey::Passphrase(passphrase.into()))
+    }
+
+    fn unlock(&self, store: &mut HistoryStore) -> Result<(), String> {
+        store
+            .unlock(&self.key()?)
+            .map_err(|e| format!("cannot unlock the history: {e}"))
+    }
+
+    fn encrypt(&self, store: &mut HistoryStore) -> Result<(), String> {
+        store
+            .encrypt(&self.key()?)
+            .map_err(|e| format!("cannot encrypt the history: {e}"))
+    }
+}
+
+#[cfg(not(feature = "encryption"))]
+impl KeyArgs {
+    fn unlock(&self, _: &mut HistoryStore) -> Result<(), String> {
+        Err("marine_history is built without the encryption feature".to_string())
+    }
+
+    fn encrypt(&self, _: &mut HistoryStore) -> Result<(), String> {
+        Err("marine_history is built without the encryption feature".to_string())
+    }
+}
+
+/// the history, or the daemon holding it
+enum History {
+    Store(HistoryStore),
+    Daemon(ClipboardClient),
+}
+
+impl History {
+    /// open the store in dir, and unlock it with key if it is encrypted, or talk to the daemon if
+    /// it holds the store
+    fn open(dir: &Path, key: &KeyArgs) -> Result<Self, String> {
+        match HistoryStore::open(dir) {
+            Ok(mut store) => {
+                if store.is_locked() {
+                    key.unlock(&mut store)?;
+                }
+                Ok(Self::Store(store))
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => daemon_socket_path()
+                .and_then(ClipboardClient::connect)
+                .map(Self::Daemon)
+                .map_err(|e| format!("{dir:?} is in use, and no daemon answers: {e}")),
+            Err(e) => Err(format!("cannot open {dir:?}: {e}")),
+        }
+    }
+
+    fn export(&mut self, writer: impl Write) -> io::Result<usize> {
+        match self {
+            Self::Store(store) => store.export(writer),
+            Self::Daemon(client) => client.export(writer),
+        }
+    }
+
+    fn import(&mut self, reader: impl BufRead) -> io::Result<usize> {
+        match self {
+            Self::Store(store) => store.import(reader),
+            Self::Daemon(client) => client.import(reader),
+        }
+    }
+
+    fn import_cliphist(&mut self, db: &Path) -> io::Result<usize> {
+        match self {
+            Self::Store(store) => store.import_cliphist(db),
+            Self::Daemon(client) => {
+                // the daemon has no request for cliphist, so the database is sent as an export
+                let mut history = ClipboardHistory::new(usize::MAX);
+                history.import_cliphist(db)?;
+                let mut lines = Vec::new();
+                history.export(&mut lines)?;
+                client.import(lines.as_slice())
+            }
+        }
+    }
+}
+
+/// $XDG_CACHE_HOME/cliphist/db, or ~/.cache/cliphist/db
+fn default_cliphist_db() -> PathBuf {
+    std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_default()
+        .join("cliphist/db")
+}
+
+fn run() -> Result<(), String> {
+    let mut dir = default_history_dir();
+    let mut key = KeyArgs::default();
+    let mut command = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dir" => dir = args.next().ok_or("--dir needs a directory")?.into(),
+            "--passphrase" => key.passphrase = true,
+            "--key-file" => {
+                key.key_file = Some(args.next().ok_or("--key-file needs a file")?.into())
+            }
+            "--keyring" => key.keyring = Some(args.next().ok_or("--keyring needs a key name")?),
+            _ => command.push(arg),
+        }
+    }
+    let mut command = command.into_iter();
+    let Some(action) = command.next() else {
+        return Err(USAGE.to_string());
+    };
+    let file = command.next().map(PathBuf::from);
+
+    let mut store = History::open(&dir, &key)?;
+    match action.as_str() {
+        "encrypt" => match &mut store {
+            History::Store(store) => {
+                key.encrypt(store)?;
+                eprintln!("encrypted the history");
+            }
+            History::Daemon(_) => {
+                return Err("marine daemon holds the history, stop it to encrypt it".to_string())
+            }
+        },
+        "export" => {
+            let count = match file {
+                Some(file) => {
+                    // the history may hold passwords, only the user can read the export
+                    let file = OpenOptions::new()
+                        .write(true)
+                        .create(true)
+                        .truncate(true)
+                        .mode(0o600)
+                        .open(&file)
+                        .map_err(|e| format!("{file:?}: {e}"))?;
+                    store.export(BufWriter::new(file))
+                }
+                None => store.export(stdout().lock()),
+            }
+            .map_err(|e| e.to_string())?;
+            eprintln!("exported {count} entries");
+        }
+        "import" => {
+            let count = match file {
+                Some(file) => {
+                    let file = File::open(&file).map_err(|e| format!("{file:?}: {e}"))?;
+                    store.import(BufReader::new(file))
+                }
+                None => store.import(stdin().lock()),
+            }
+            .map_err(|e| e.to_string())?;
+            eprintln!("imported {count} entries");
+        }
+        "cliphist" => {
+            let db = file.unwrap_or_else(default_cliphist_db);
+            let count = store
+                .import_cliphist(&db)
+                .map_err(|e| format!("{db:?}: {e}"))?;
+            eprintln!("imported {count} entries");
+        }
+        _ => return Err(USAGE.to_string()),
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,244 @@
+use nix::{
+    fcntl::OFlag,
+    unistd::{close, dup2_stdin, dup2_stdout, fork, ForkResult},
+};
+use wayland_clipboard_listener::{
+    daemon_socket_path, default_history_dir, is_register_name, ClipboardClient, HistoryStore,
+    WlClipboardCopyStream, WlClipboardSelectionStream, WlCopyTarget,
+};
+
+use std::io::{self, stdout, Write};
+
+const USAGE: &str = "usage: marine_register [--dir DIR] [--primary] COMMAND
+    save NAME      save the current selection in the register NAME, a to z
+    paste NAME     copy the register NAME to the clipboard
+    show NAME      print the register NAME
+    list           list the registers
+    delete NAME    empty the register NAME
+    pin ID         pin the history entry ID, so it never expires
+    unpin ID       unpin the history entry ID
+    pinned         list the pinned entries
+the history is reached through marine daemon while it runs";
+
+fn register_name(arg: Option<String>) -> Result<char, String> {
+    let arg = arg.ok_or("a register name is needed")?;
+    let mut chars = arg.chars();
+    match (chars.next(), chars.next()) {
+        (Some(name), None) if is_register_name(name) => Ok(name),
+        _ => Err(format!("{arg} is not a register, they are a to z")),
+    }
+}
+
+fn entry_id(arg: Option<String>) -> Result<u64, String> {
+    arg.and_then(|id| id.parse().ok())
+        .ok_or_else(|| "an entry id is needed".to_string())
+}
+
+/// the first line of the text, or the mimetypes
+fn summary(text: Option<&str>, mime_types: &[impl AsRef<str>]) -> String {
+    match text {
+        Some(text) => text.lines().next().unwrap_or_default().to_string(),
+        None => {
+            let mime_types: Vec<&str> = mime_types.iter().map(AsRef::as_ref).collect();
+            format!("[{}]", mime_types.join(", "))
+        }
+    }
+}
+
+/// run the command through the daemon holding the history
+fn run_daemon(
+    mut client: ClipboardClient,
+    action: &str,
+    mut command: impl Iterator<Item = String>,
+    target: WlCopyTarget,
+) -> Result<(), String> {
+    match action {
+        "save" => {
+            let name = register_name(command.next())?;
+            let payloads = client
+                .get(target == WlCopyTarget::Primary, None)
+                .map_err(|e| e.to_string())?
+                .ok_or("the selection is empty")?;
+            client
+                .set_register(name, payloads)
+                .map_err(|e| e.to_string())?;
+        }
+        "paste" => {
+            let name = register_name(command.next())?;
+            let payloads = client
+                .register(name)
+                .map_err(|e| e.to_string())?
+                .ok_or(format!("the register {name} is empty"))?;
+            // the daemon serves the paste
+            client.set(payloads, target).map_err(|e| e.to_string())?;
+        }
+        "show" => {
+            let name = register_name(command.next())?;
+            let registers = client.registers().map_err(|e| e.to_string())?;
+            let register = registers
+                .get(&name)
+                .ok_or(format!("the register {name} is empty"))?;
+            let data = match &register.text {
+                Some(text) => text.as_bytes().to_vec(),
+                None => client
+                    .register(name)
+                    .map_err(|e| e.to_string())?
+                    .and_then(|payloads| payloads.into_iter().next())
+                    .ok_or(format!("the register {name} is empty"))?
+                    .1
+                    .to_vec(),
+            };
+            stdout().write_all(&data).map_err(|e| e.to_string())?;
+        }
+        "list" => {
+            for (name, register) in client.registers().map_err(|e| e.to_string())? {
+                println!(
+                    "{name}\t{}",
+                    summary(register.text.as_deref(), &register.mime_types)
+                );
+            }
+        }
+        "delete" => {
+            let name = register_name(command.next())?;
+            client.remove_register(name).map_err(|e| e.to_string())?;
+        }
+        "pin" | "unpin" => {
+            let id = entry_id(command.next())?;
+            client.pin(id, action == "pin").map_err(|e| e.to_string())?;
+        }
+        "pinned" => {
+            let entries = client.history("", None).map_err(|e| e.to_string())?;
+            for entry in entries.iter().filter(|entry| entry.pinned) {
+                println!(
+                    "{}\t{}",
+                    entry.id,
+                    summary(entry.text.as_deref(), &entry.mime_types)
+                );
+            }
+        }
+        _ => return Err(USAGE.to_string()),
+    }
+    Ok(())
+}
+
+fn run() -> Result<(), String> {
+    let mut dir = default_history_dir();
+    let mut primary = false;
+    let mut command = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dir" => dir = args.next().ok_or("--dir needs a directory")?.into(),
+            "--primary" => primary = true,
+            _ => command.push(arg),
+        }
+    }
+    let mut command = command.into_iter();
+    let Some(action) = command.next() else {
+        return Err(USAGE.to_string());
+    };
+    let target = if primary {
+        WlCopyTarget::Primary
+    } else {
+        WlCopyTarget::Clipboard
+    };
+
+    let mut store = match HistoryStore::open(&dir) {
+        Ok(store) => store,
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+            let client = daemon_socket_path()
+                .and_then(ClipboardClient::connect)
+                .map_err(|e| format!("{dir:?} is in use, and no daemon answers: {e}"))?;
+            return run_daemon(client, &action, command, target);
+        }
+        Err(e) => return Err(format!("cannot open {dir:?}: {e}")),
+    };
+    if store.is_locked() {
+        return Err("the history is encrypted, it can not be opened here".to_string());
+    }
+    match action.as_str() {
+        "save" => {
+            let name = register_name(command.next())?;
+            let mut stream = WlClipboardSelectionStream::init(target).map_err(|e| e.to_string())?;
+            let selection = stream
+                .get_current_selection()
+                .map_err(|e| e.to_string())?
+                .ok_or("the selection is empty")?;
+            store
+                .save_register(name, selection)
+                .map_err(|e| e.to_string())?;
+        }
+        "paste" => {
+            let name = register_name(command.next())?;
+            let source = store
+                .history()
+                .register(name)
+                .ok_or(format!("the register {name} is empty"))?
+                .source();
+            // the store is not needed to serve the paste, let others open it
+            drop(store);
+            let mut stream = WlClipboardCopyStream::init().map_err(|e| e.to_string())?;
+            // the parent returns, and the child serves the paste
+            let forked = unsafe { fork() }.map_err(|e| format!("failed to fork: {e}"))?;
+            if let ForkResult::Child = forked {
+                let dev_null =
+                    nix::fcntl::open("/dev/null", OFlag::O_RDWR, nix::sys::stat::Mode::empty())
+                        .map_err(|e| format!("failed to open /dev/null: {e}"))?;
+                let _ = dup2_stdin(&dev_null);
+                let _ = dup2_stdout(&dev_null);
+                let _ = close(dev_null);
+                stream
+                    .copy_source_to_clipboard(source, target)
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+        "show" => {
+            let name = register_name(command.next())?;
+            let register = store
+                .history()
+                .register(name)
+                .ok_or(format!("the register {name} is empty"))?;
+            let data = match register.text() {
+                Some(text) => text.as_bytes(),
+                None => &register.payloads()[0].1,
+            };
+            stdout().write_all(data).map_err(|e| e.to_string())?;
+        }
+        "list" => {
+            for (name, register) in store.history().registers() {
+                println!(
+                    "{name}\t{}",
+                    summary(register.text(), &register.mime_types())
+                );
+            }
+        }
+        "delete" => {
+            let name = register_name(command.next())?;
+            store.remove_register(name).map_err(|e| e.to_string())?;
+        }
+        "pin" | "unpin" => {
+            let id = entry_id(command.next())?;
+            if !store.pin(id, action == "pin").map_err(|e| e.to_string())? {
+                return Err(format!("no entry {id} in the history"));
+            }
+        }
+        "pinned" => {
+            for entry in store.history().pinned() {
+                println!(
+                    "{}\t{}",
+                    entry.id(),
+                    summary(entry.text(), &entry.mime_types())
+                );
+            }
+        }
+        _ => return Err(USAGE.to_string()),
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,360 @@
+//! the client of the daemon, see [crate::WlClipboardDaemonStream]
+
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::copy::Payloads;
+use crate::daemon::{check_peer, ClipboardEvent, HistoryItem, Reply, Request, SubscriptionFilter};
+use crate::export::MimeMap;
+use crate::WlCopyTarget;
+
+/// the time the daemon has to answer, or to go on with a line it started, so a daemon hung
+/// does not hang its clients
+const REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// a reply or an event longer than it is refused, it is larger than the replies of the daemon,
+/// even a large export
+const MAX_LINE: u64 = 1 << 30;
+
+/// a client of the daemon, it talks to the daemon instead of wayland, so it needs no wayland
+/// connection, and the copies are served by the daemon
+/// ```rust, no_run
+/// use wayland_clipboard_listener::{daemon_socket_path, ClipboardClient};
+/// let mut client = ClipboardClient::connect(daemon_socket_path().unwrap()).unwrap();
+/// client
+///     .set(vec![("text/plain".to_string(), b"hello".to_vec())], false)
+///     .unwrap();
+/// if let Some(payloads) = client.get(false, Some("text/plain")).unwrap() {
+///     println!("{:?}", String::from_utf8_lossy(&payloads[0].1));
+/// }
+/// for event in client.subscribe().unwrap() {
+///     println!("{:?}", event.unwrap());
+/// }
+/// ```
+pub struct ClipboardClient {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+    timeout: Option<Duration>,
+}
+
+impl ClipboardClient {
+    /// connect to the daemon listening on path, see [crate::daemon_socket_path]
+    /// a daemon run by another user is refused
+    /// a request fails with TimedOut if the daemon does not answer in 30 seconds, see
+    /// [ClipboardClient::set_timeout]
+    pub fn connect(path: impl AsRef<Path>) -> io::Result<Self> {
+        let writer = UnixStream::connect(path)?;
+        check_peer(&writer)?;
+        let mut client = Self {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+            timeout: None,
+        };
+        client.set_timeout(Some(REPLY_TIMEOUT))?;
+        Ok(client)
+    }
+
+    /// the time the daemon has to answer a request, or to send the rest of an event, it waits
+    /// forever if it is None
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.writer.set_read_timeout(timeout)?;
+        self.writer.set_write_timeout(timeout)?;
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    /// the selection last seen by the daemon, the clipboard, or the primary selection if primary
+    /// is true, with the data of mime_type, or of all its mimetypes
+    /// return None if the selection is empty, and an error if mime_type is not offered
+    pub fn get(&mut self, primary: bool, mime_type: Option<&str>) -> io::Result<Option<Payloads>> {
+        let reply = self.request(&Request::Get {
+            primary,
+            mime_type: mime_type.map(str::to_string),
+        })?;
+        Ok(reply.payloads.map(|payloads| payloads.0))
+    }
+
+    /// copy the payloads to target, like
+    /// [crate::WlClipboardCopyStream::copy_payloads_to_clipboard], but it returns at once, the
+    /// daemon serves the pastes
+    pub fn set<P: Into<Arc<[u8]>>>(
+        &mut self,
+        payloads: impl IntoIterator<Item = (String, P)>,
+        target: impl Into<WlCopyTarget>,
+    ) -> io::Result<()> {
+        let payloads = payloads
+            .into_iter()
+            .map(|(mimetype, data)| (mimetype, data.into()))
+            .collect();
+        self.request(&Request::Set {
+            target: target.into(),
+            payloads: MimeMap(payloads),
+        })?;
+        Ok(())
+    }
+
+    /// clear the selections of target
+    pub fn clear(&mut self, target: impl Into<WlCopyTarget>) -> io::Result<()> {
+        self.request(&Request::Clear {
+            target: target.into(),
+        })?;
+        Ok(())
+    }
+
+    /// search the history of the daemon, the best matches first, like [crate::HistoryQuery::new]
+    /// an empty query lists the entries, the newest first
+    pub fn history(&mut self, query: &str, limit: Option<usize>) -> io::Result<Vec<HistoryItem>> {
+        let reply = self.request(&Request::History {
+            query: query.to_string(),
+            limit,
+        })?;
+        Ok(reply.entries.unwrap_or_default())
+    }
+
+    /// copy the history entry id to target again
+    pub fn select(&mut self, id: u64, target: impl Into<WlCopyTarget>) -> io::Result<()> {
+        self.request(&Request::Select {
+            id,
+            target: target.into(),
+        })?;
+        Ok(())
+    }
+
+    /// pin the history entry id, or unpin it if pinned is false, see
+    /// [crate::ClipboardHistory::pin]
+    pub fn pin(&mut self, id: u64, pinned: bool) -> io::Result<()> {
+        self.request(&Request::Pin { id, pinned })?;
+        Ok(())
+    }
+
+    /// put the payloads in the register name, see [crate::ClipboardHistory::set_register]
+    pub fn set_register<P: Into<Arc<[u8]>>>(
+        &mut self,
+        name: char,
+        payloads: impl IntoIterator<Item = (String, P)>,
+    ) -> io::Result<()> {
+        let payloads = payloads
+            .into_iter()
+            .map(|(mimetype, data)| (mimetype, data.into()))
+            .collect();
+        self.request(&Request::SetRegister {
+            name,
+            payloads: MimeMap(payloads),
+        })?;
+        Ok(())
+    }
+
+    /// the registers of the history of the daemon, without their data
+    pub fn registers(&mut self) -> io::Result<BTreeMap<char, HistoryItem>> {
+        let reply = self.request(&Request::Registers)?;
+        Ok(reply.registers.unwrap_or_default())
+    }
+
+    /// the data of the register name, None if it is empty
+    pub fn register(&mut self, name: char) -> io::Result<Option<Payloads>> {
+        let reply = self.request(&Request::Register { name })?;
+        Ok(reply.payloads.map(|payloads| payloads.0))
+    }
+
+    /// empty the register name
+    pub fn remove_register(&mut self, name: char) -> io::Result<()> {
+        self.request(&Request::RemoveRegister { name })?;
+        Ok(())
+    }
+
+    /// write the history of the daemon like [crate::ClipboardHistory::export]
+    /// return the count of lines written
+    pub fn export(&mut self, mut writer: impl Write) -> io::Result<usize> {
+        let reply = self.request(&Request::Export)?;
+        writer.write_all(reply.lines.unwrap_or_default().as_bytes())?;
+        Ok(reply.count.unwrap_or_default())
+    }
+
+    /// read an export into the history of the daemon, like [crate::ClipboardHistory::import]
+    /// return the count of entries and registers imported
+    pub fn import(&mut self, mut reader: impl Read) -> io::Result<usize> {
+        let mut lines = String::new();
+        reader.read_to_string(&mut lines)?;
+        let reply = self.request(&Request::Import { lines })?;
+        Ok(reply.count.unwrap_or_default())
+    }
+
+    /// lock the encrypted history of the daemon, it is not read nor written until it is unlocked
+    pub fn lock(&mut self) -> io::Result<()> {
+        self.request(&Request::Lock)?;
+        Ok(())
+    }
+
+    /// unlock the encrypted history of the daemon with key, see [crate::HistoryStore::unlock]
+    #[cfg(feature = "encryption")]
+    pub fn unlock(&mut self, key: &crate::HistoryKey) -> io::Result<()> {
+        self.request(&Request::Unlock { key: key.into() })?;
+        Ok(())
+    }
+
+    /// wait for the changes of the selections, the connection is only used for them since then
+    pub fn subscribe(self) -> io::Result<ClipboardSubscription> {
+        self.subscribe_filtered(&SubscriptionFilter::default())
+    }
+
+    /// like subscribe, but only the changes matching filter are sent
+    /// ```rust, no_run
+    /// use wayland_clipboard_listener::{
+    ///     daemon_socket_path, ClipboardClient, ClipboardEvent, SubscriptionFilter,
+    /// };
+    /// let client = ClipboardClient::connect(daemon_socket_path().unwrap()).unwrap();
+    /// let mut filter = SubscriptionFilter::new();
+    /// filter.set_target(false);
+    /// filter.set_mime_types(vec!["text/plain;charset=utf-8".to_string()]);
+    /// filter.set_data(true);
+    /// for event in client.subscribe_filtered(&filter).unwrap() {
+    ///     if let ClipboardEvent::Changed { payloads, .. } = event.unwrap() {
+    ///         println!("{}", String::from_utf8_lossy(&payloads[0].1));
+    ///     }
+    /// }
+    /// ```
+    pub fn subscribe_filtered(
+        mut self,
+        filter: &SubscriptionFilter,
+    ) -> io::Result<ClipboardSubscription> {
+        self.request(&Request::Subscribe(filter.clone()))?;
+        Ok(ClipboardSubscription {
+            reader: self.reader,
+            _writer: self.writer,
+            timeout: self.timeout,
+        })
+    }
+
+    fn request(&mut self, request: &Request) -> io::Result<Reply> {
+        send_line(&mut self.writer, request)?;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if read_line(&mut self.reader, &mut line, MAX_LINE)? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            // the replies never have the event field
+            if serde_json::from_str::<ClipboardEvent>(&line).is_err() {
+                break;
+            }
+        }
+        let reply: Reply = serde_json::from_str(&line)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        match reply.error {
+            Some(error) => Err(io::Error::other(error)),
+            None => Ok(reply),
+        }
+    }
+}
+
+/// read a line like read_line, but fail if it is longer than max
+fn read_line(reader: &mut impl BufRead, line: &mut String, max: u64) -> io::Result<usize> {
+    let len = reader.take(max).read_line(line)?;
+    if len as u64 == max && !line.ends_with('\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the line of the daemon is too long",
+        ));
+    }
+    Ok(len)
+}
+
+fn send_line(mut writer: impl Write, line: &impl Serialize) -> io::Result<()> {
+    let mut line = serde_json::to_vec(line).map_err(io::Error::other)?;
+    line.push(b'\n');
+    writer.write_all(&line)
+}
+
+/// the changes of the selections, see [ClipboardClient::subscribe]
+/// the iter ends when the daemon exits
+/// it waits as long as it takes for the next event, but an event started must be finished in the
+/// timeout of the client, or it fails with TimedOut, see [ClipboardClient::set_timeout]
+pub struct ClipboardSubscription {
+    reader: BufReader<UnixStream>,
+    // the daemon drops the clients that close the connection
+    _writer: UnixStream,
+    timeout: Option<Duration>,
+}
+
+impl Iterator for ClipboardSubscription {
+    type Item = io::Result<ClipboardEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let started = self
+            .reader
+            .get_ref()
+            .set_read_timeout(None)
+            .and_then(|()| self.reader.fill_buf().map(|buf| !buf.is_empty()));
+        match started {
+            Ok(true) => {}
+            Ok(false) => return None,
+            Err(e) => return Some(Err(e)),
+        }
+        if let Err(e) = self.reader.get_ref().set_read_timeout(self.timeout) {
+            return Some(Err(e));
+        }
+        let mut line = String::new();
+        match read_line(&mut self.reader, &mut line, MAX_LINE) {
+            Ok(0) => None,
+            Ok(_) => Some(
+                serde_json::from_str(&line)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            ),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixListener;
+
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn a_long_line_is_refused() {
+        let mut line = String::new();
+        let mut reader = io::Cursor::new(b"{}\n{\"error\":\"too long\"}\n".to_vec());
+        assert_eq!(read_line(&mut reader, &mut line, 8).unwrap(), 3);
+        let e = read_line(&mut reader, &mut line, 8).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn a_daemon_not_answering_times_out() {
+        let tmp = TempDir::new();
+        let path = tmp.path().join("socket");
+        let listener = UnixListener::bind(&path).unwrap();
+        let mut client = ClipboardClient::connect(&path).unwrap();
+        let (_daemon, _) = listener.accept().unwrap();
+        client
+            .set_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let e = client.clear(WlCopyTarget::Clipboard).unwrap_err();
+        assert!(matches!(
+            e.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        ));
+
+        // a subscriber waits for the next event, but not for the rest of one
+        let mut client = ClipboardClient::connect(&path).unwrap();
+        client
+            .set_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let (mut daemon, _) = listener.accept().unwrap();
+        daemon.write_all(b"{}\n").unwrap();
+        let mut events = client.subscribe().unwrap();
+        let waiting = std::thread::spawn(move || events.next().map(|event| event.is_err()));
+        std::thread::sleep(Duration::from_millis(300));
+        assert!(!waiting.is_finished());
+        daemon.write_all(br#"{"event":"#).unwrap();
+        assert_eq!(waiting.join().unwrap(), Some(true));
+    }
+}
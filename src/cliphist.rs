@@ -0,0 +1,230 @@
+//! read the history of cliphist
+//! cliphist keeps its history in a bbolt database, `~/.cache/cliphist/db`, the data of every entry
+//! is in the bucket "b", with its id in big endian as the key, so the oldest entry is the first
+//!
+//! only the parts of the bbolt format needed to read a bucket are here: the meta pages, the
+//! branch and leaf pages, and the buckets inlined in their parent page
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const MAGIC: u32 = 0xED0C_DAED;
+const VERSION: u32 = 2;
+const PAGE_HEADER_SIZE: usize = 16;
+const ELEMENT_SIZE: usize = 16;
+/// the offset of the checksum in the meta, it is the FNV-1a of the bytes before it
+const META_CHECKSUM: usize = 56;
+const BRANCH_PAGE: u16 = 0x01;
+const LEAF_PAGE: u16 = 0x02;
+const BUCKET_LEAF: u32 = 0x01;
+const BUCKET: &[u8] = b"b";
+
+fn invalid_data(e: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+fn bytes(data: &[u8], offset: usize, len: usize) -> io::Result<&[u8]> {
+    offset
+        .checked_add(len)
+        .and_then(|end| data.get(offset..end))
+        .ok_or_else(|| invalid_data("the cliphist database is cut"))
+}
+
+fn u16_at(data: &[u8], offset: usize) -> io::Result<u16> {
+    Ok(u16::from_le_bytes(
+        bytes(data, offset, 2)?.try_into().unwrap(),
+    ))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> io::Result<u32> {
+    Ok(u32::from_le_bytes(
+        bytes(data, offset, 4)?.try_into().unwrap(),
+    ))
+}
+
+fn u64_at(data: &[u8], offset: usize) -> io::Result<u64> {
+    Ok(u64::from_le_bytes(
+        bytes(data, offset, 8)?.try_into().unwrap(),
+    ))
+}
+
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// the meta at offset, its page size, root page and transaction id, if it is valid
+fn meta(data: &[u8], offset: usize) -> Option<(usize, u64, u64)> {
+    let meta = data.get(offset + PAGE_HEADER_SIZE..)?;
+    let valid = u32_at(meta, 0).ok()? == MAGIC
+        && u32_at(meta, 4).ok()? == VERSION
+        && u64_at(meta, META_CHECKSUM).ok()? == fnv1a(meta.get(..META_CHECKSUM)?);
+    if !valid {
+        return None;
+    }
+    let page_size = u32_at(meta, 8).ok()? as usize;
+    let root = u64_at(meta, 16).ok()?;
+    let txid = u64_at(meta, 48).ok()?;
+    Some((page_size, root, txid))
+}
+
+struct Db<'a> {
+    data: &'a [u8],
+    page_size: usize,
+    /// the pages already read, a page is only in one place of the tree
+    visited: HashSet<u64>,
+}
+
+impl<'a> Db<'a> {
+    fn page(&mut self, id: u64) -> io::Result<&'a [u8]> {
+        if !self.visited.insert(id) {
+            return Err(invalid_data("the cliphist database has a loop"));
+        }
+        let offset = usize::try_from(id)
+            .ok()
+            .and_then(|id| id.checked_mul(self.page_size))
+            .ok_or_else(|| invalid_data("bad page id in the cliphist database"))?;
+        self.data
+            .get(offset..)
+            .ok_or_else(|| invalid_data("the cliphist database is cut"))
+    }
+
+    /// call f with the flags, key and value of every element in the tree of page, in order
+    fn walk(
+        &mut self,
+        page: &'a [u8],
+        depth: usize,
+        f: &mut impl FnMut(u32, &'a [u8], &'a [u8]) -> io::Result<()>,
+    ) -> io::Result<()> {
+        if depth > 64 {
+            return Err(invalid_data("the cliphist database is too deep"));
+        }
+        let flags = u16_at(page, 8)?;
+        let count = usize::from(u16_at(page, 10)?);
+        for index in 0..count {
+            let element = PAGE_HEADER_SIZE + index * ELEMENT_SIZE;
+            match flags {
+                BRANCH_PAGE => {
+                    let child = u64_at(page, element + 8)?;
+                    let child = self.page(child)?;
+                    self.walk(child, depth + 1, f)?;
+                }
+                LEAF_PAGE => {
+                    let element_flags = u32_at(page, element)?;
+                    let key_offset = element + u32_at(page, element + 4)? as usize;
+                    let key_len = u32_at(page, element + 8)? as usize;
+                    let value_len = u32_at(page, element + 12)? as usize;
+                    let key = bytes(page, key_offset, key_len)?;
+                    let value = bytes(page, key_offset + key_len, value_len)?;
+                    f(element_flags, key, value)?;
+                }
+                _ => return Err(invalid_data(format!("unknown page type {flags:#x}"))),
+            }
+        }
+        Ok(())
+    }
+
+    /// the root page of the bucket in value, it is after the bucket header if it is inlined
+    fn bucket_page(&mut self, value: &'a [u8]) -> io::Result<&'a [u8]> {
+        match u64_at(value, 0)? {
+            0 => value
+                .get(16..)
+                .ok_or_else(|| invalid_data("the cliphist database is cut")),
+            root => self.page(root),
+        }
+    }
+}
+
+/// the data of the entries in the cliphist database at path, the oldest first
+pub(crate) fn read_cliphist(path: &Path) -> io::Result<Vec<Vec<u8>>> {
+    let data = fs::read(path)?;
+    let page_size = meta(&data, 0)
+        .map(|(page_size, _, _)| page_size)
+        .unwrap_or(4096);
+    // the two meta pages are written in turns, the newer valid one is used
+    let (page_size, root, _) = [meta(&data, 0), meta(&data, page_size)]
+        .into_iter()
+        .flatten()
+        .max_by_key(|(_, _, txid)| *txid)
+        .ok_or_else(|| invalid_data("not a cliphist database"))?;
+    let mut db = Db {
+        data: &data,
+        page_size,
+        visited: HashSet::new(),
+    };
+
+    let mut bucket = None;
+    let root = db.page(root)?;
+    db.walk(root, 0, &mut |flags, key, value| {
+        if flags & BUCKET_LEAF != 0 && key == BUCKET {
+            bucket = Some(value);
+        }
+        Ok(())
+    })?;
+    let Some(bucket) = bucket else {
+        return Ok(Vec::new());
+    };
+    let mut entries = Vec::new();
+    let bucket = db.bucket_page(bucket)?;
+    db.walk(bucket, 0, &mut |flags, _, value| {
+        if flags & BUCKET_LEAF == 0 {
+            entries.push(value.to_vec());
+        }
+        Ok(())
+    })?;
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    /// the databases are written by tests/data/mkcliphist.py
+    fn fixture(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/data")
+            .join(name)
+    }
+
+    #[test]
+    fn read_an_inline_bucket() {
+        let entries = read_cliphist(&fixture("cliphist-inline.db")).unwrap();
+        assert_eq!(entries, [b"one".to_vec(), Vec::new(), b"one".to_vec()]);
+    }
+
+    #[test]
+    fn read_a_bucket_with_branch_pages() {
+        let entries = read_cliphist(&fixture("cliphist-branch.db")).unwrap();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0], b"first");
+        assert!(entries[1].starts_with(b"\x89PNG"));
+        assert_eq!(entries[2], b"third");
+        // it is longer than a page
+        assert_eq!(entries[3], vec![b'B'; 1500]);
+    }
+
+    #[test]
+    fn a_page_is_read_once() {
+        let e = read_cliphist(&fixture("cliphist-loop.db")).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn refuse_what_is_not_a_cliphist_database() {
+        let tmp = TempDir::new();
+        let path = tmp.path().join("db");
+        fs::write(&path, vec![0; 8192]).unwrap();
+        let e = read_cliphist(&path).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+        // the meta pages are there, but the rest is cut
+        let data = fs::read(fixture("cliphist-branch.db")).unwrap();
+        fs::write(&path, &data[..4 * 1024]).unwrap();
+        let e = read_cliphist(&path).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+}
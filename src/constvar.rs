@@ -3,3 +3,14 @@ pub(crate) const TEXT: &str = "text/plain;charset=utf-8";
 
 // not let image be text
 pub(crate) const IMAGE: &str = "image/png";
+
+// offered by password managers with secrets, like keepassxc
+pub(crate) const PASSWORD_HINT: &str = "x-kde-passwordManagerHint";
+
+// what is sent for PASSWORD_HINT, like klipper and keepassxc do
+pub(crate) const PASSWORD_HINT_DATA: &[u8] = b"secret";
+
+// a selection kept or watched is skipped if it is not received in time, or is larger, the owner
+// may never close the pipe, or send endless data
+pub(crate) const RECEIVE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+pub(crate) const RECEIVE_LIMIT: usize = 64 << 20;
@@ -0,0 +1,861 @@
+//! data held by the copy stream
+//! every offered mimetype points to a shared payload, so several mimetypes and concurrent pastes
+//! share the same bytes without cloning them
+//! or the data can be produced on demand by a [ClipboardDataProvider], in a thread
+//! a copy can also be served in the background thread, controlled by a [CopyHandle]
+//! the pastes are written without blocking, so a slow or broken paste side will not stop others,
+//! and a paste side that takes nothing for [WRITE_TIMEOUT] is dropped
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::fd::{AsFd, BorrowedFd};
+#[cfg(feature = "image-convert")]
+use std::panic;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+#[cfg(feature = "image-convert")]
+use std::sync::OnceLock;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use nix::{
+    errno::Errno,
+    fcntl::{fcntl, FcntlArg, OFlag},
+    libc::off_t,
+    poll::{poll, PollFd, PollFlags, PollTimeout},
+    sys::sendfile::sendfile,
+};
+use os_pipe::{PipeReader, PipeWriter};
+
+use crate::constvar::{PASSWORD_HINT, PASSWORD_HINT_DATA};
+#[cfg(feature = "image-convert")]
+use crate::image_convert::{self, IMAGE_MIME_TYPES};
+use crate::text::{TextEncoding, TEXT_SOURCE_MIME_TYPES};
+use crate::WlClipboardListenerError;
+
+/// Provide the data of a copy lazily
+/// it is useful when the data is large or expensive to make, like rendered images, only the
+/// mimetype requested by the paste side will be made
+/// ```rust, no_run
+/// use std::io::{self, Write};
+/// use wayland_clipboard_listener::{ClipboardDataProvider, WlClipboardCopyStream};
+///
+/// struct Now;
+///
+/// impl ClipboardDataProvider for Now {
+///     fn mime_types(&self) -> Vec<String> {
+///         vec!["text/plain;charset=utf-8".to_string()]
+///     }
+///     fn send(&mut self, _mime_type: &str, writer: &mut dyn Write) -> io::Result<()> {
+///         write!(writer, "{:?}", std::time::SystemTime::now())
+///     }
+/// }
+///
+/// let mut stream = WlClipboardCopyStream::init().unwrap();
+/// stream.copy_provider_to_clipboard(Now, false).unwrap();
+/// ```
+pub trait ClipboardDataProvider {
+    /// the mimetypes offered to the paste side
+    fn mime_types(&self) -> Vec<String>;
+    /// called on every paste, write the data of mime_type to writer
+    /// it is called in a thread, so a slow provider does not block the other pastes, but the
+    /// pastes of the same provider wait for each other
+    fn send(&mut self, mime_type: &str, writer: &mut dyn Write) -> io::Result<()>;
+}
+
+/// what the copy stream serves
+/// it is made from the same data for all mimetypes, data for each mimetype, a
+/// [ClipboardDataProvider], a file on disk, or text converted to the encoding of each paste
+pub struct CopySource {
+    kind: SourceKind,
+    /// offered with PASSWORD_HINT, so the clipboard managers do not keep it
+    secret: bool,
+}
+
+enum SourceKind {
+    Data(CopyData),
+    Provider {
+        provider: Arc<Mutex<Box<dyn ClipboardDataProvider + Send>>>,
+        mime_types: Vec<String>,
+    },
+    File {
+        file: Arc<File>,
+        mime_types: Vec<String>,
+    },
+    Text {
+        text: Arc<str>,
+        crlf: bool,
+    },
+    #[cfg(feature = "image-convert")]
+    Image {
+        data: Arc<[u8]>,
+        mime_type: String,
+        /// the conversions started by earlier pastes, by format, the finished ones are kept for the
+        /// later pastes
+        conversions: HashMap<String, Arc<Conversion>>,
+    },
+}
+
+impl CopySource {
+    /// all the mimetypes are served with the same data
+    /// data can be a [`Vec<u8>`], or an [`Arc<[u8]>`] to share the buffer without copying it
+    pub fn new(data: impl Into<Arc<[u8]>>, mimetypes: Vec<&str>) -> Self {
+        Self {
+            secret: false,
+            kind: SourceKind::Data(CopyData::new(data.into(), mimetypes)),
+        }
+    }
+
+    /// every mimetype is served with its own data, the mimetypes are offered in order
+    pub fn from_payloads<P: Into<Arc<[u8]>>>(
+        payloads: impl IntoIterator<Item = (String, P)>,
+    ) -> Self {
+        Self {
+            secret: false,
+            kind: SourceKind::Data(CopyData::from_payloads(
+                payloads
+                    .into_iter()
+                    .map(|(mimetype, payload)| (mimetype, payload.into())),
+            )),
+        }
+    }
+
+    /// the data is made by the provider when someone pastes
+    pub fn from_provider(provider: impl ClipboardDataProvider + Send + 'static) -> Self {
+        Self {
+            secret: false,
+            kind: SourceKind::Provider {
+                mime_types: provider.mime_types(),
+                provider: Arc::new(Mutex::new(Box::new(provider))),
+            },
+        }
+    }
+
+    /// all the mimetypes are served with the content of file, it is not read into memory, every
+    /// paste is sent from the disk by sendfile
+    /// the file should be a regular file, it is read from the beginning on every paste, so
+    /// changes to the file after the copy will be seen by later pastes
+    pub fn from_file(file: File, mimetypes: Vec<&str>) -> Self {
+        Self {
+            secret: false,
+            kind: SourceKind::File {
+                file: Arc::new(file),
+                mime_types: mimetypes.into_iter().map(str::to_string).collect(),
+            },
+        }
+    }
+
+    /// text offered as all the common text mimetypes, including the X11 ones like `STRING`
+    /// it is converted when pasted, to UTF-8, or Latin-1 and ASCII with `?` for the characters
+    /// that can not be encoded
+    /// if crlf is true, the line endings are converted to CRLF, for apps that expect them
+    /// ```rust, no_run
+    /// use wayland_clipboard_listener::{CopySource, WlClipboardCopyStream};
+    /// let mut stream = WlClipboardCopyStream::init().unwrap();
+    /// stream
+    ///     .copy_source_to_clipboard(CopySource::text("café", false), false)
+    ///     .unwrap();
+    /// ```
+    pub fn text(text: impl Into<String>, crlf: bool) -> Self {
+        Self {
+            secret: false,
+            kind: SourceKind::Text {
+                text: text.into().into(),
+                crlf,
+            },
+        }
+    }
+
+    /// an image offered as its own format, and the other formats it can be converted to, like
+    /// `image/bmp` and `image/jpeg` for a `image/png` screenshot
+    /// the image is converted in a thread when another format is pasted, so other pastes are not
+    /// blocked meanwhile, the pastes of the format wait for the same conversion, and the
+    /// converted one is kept for the later pastes
+    #[cfg(feature = "image-convert")]
+    pub fn image(data: impl Into<Arc<[u8]>>, mimetype: &str) -> Self {
+        Self {
+            secret: false,
+            kind: SourceKind::Image {
+                data: data.into(),
+                mime_type: mimetype.to_string(),
+                conversions: HashMap::new(),
+            },
+        }
+    }
+
+    /// open the file of path, see [CopySource::from_file]
+    pub fn open(path: impl AsRef<Path>, mimetypes: Vec<&str>) -> io::Result<Self> {
+        Ok(Self::from_file(File::open(path)?, mimetypes))
+    }
+
+    /// mark it as a secret, see [PASSWORD_HINT]
+    pub(crate) fn set_secret(&mut self) {
+        self.secret = true;
+    }
+
+    pub(crate) fn mime_types(&self) -> Vec<String> {
+        let mut mime_types = self.data_mime_types();
+        if self.secret && !mime_types.iter().any(|mimetype| mimetype == PASSWORD_HINT) {
+            mime_types.push(PASSWORD_HINT.to_string());
+        }
+        mime_types
+    }
+
+    fn data_mime_types(&self) -> Vec<String> {
+        match &self.kind {
+            SourceKind::Data(data) => data.mime_types().map(str::to_string).collect(),
+            SourceKind::Provider { mime_types, .. } => mime_types.clone(),
+            SourceKind::File { mime_types, .. } => mime_types.clone(),
+            SourceKind::Text { .. } => TEXT_SOURCE_MIME_TYPES.map(str::to_string).to_vec(),
+            #[cfg(feature = "image-convert")]
+            SourceKind::Image { mime_type, .. } => {
+                let mut mime_types = vec![mime_type.clone()];
+                if image_convert::can_convert(mime_type) {
+                    mime_types.extend(
+                        IMAGE_MIME_TYPES
+                            .iter()
+                            .filter(|mimetype| *mimetype != mime_type)
+                            .map(|mimetype| mimetype.to_string()),
+                    );
+                }
+                mime_types
+            }
+        }
+    }
+
+    /// prepare the data of mime_type to be written to file, None if the mimetype is not offered
+    /// the file is set to non-blocking, the data will be written by [flush_writes] when the
+    /// reader is ready
+    pub(crate) fn prepare_send(
+        &mut self,
+        mime_type: &str,
+        file: File,
+    ) -> io::Result<Option<PendingWrite>> {
+        fcntl(&file, FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+        let data = match &mut self.kind {
+            _ if self.secret && mime_type == PASSWORD_HINT => {
+                WriteData::Shared(PASSWORD_HINT_DATA.into())
+            }
+            SourceKind::Data(data) => match data.get(mime_type) {
+                Some(payload) => WriteData::Shared(payload.clone()),
+                None => return Ok(None),
+            },
+            SourceKind::Provider {
+                provider,
+                mime_types,
+            } => {
+                if !mime_types.iter().any(|mimetype| mimetype == mime_type) {
+                    return Ok(None);
+                }
+                let provider = provider.clone();
+                let mime_type = mime_type.to_string();
+                let mut writer = TimedWriter(file.try_clone()?);
+                WriteData::Providing(Worker::spawn(move || {
+                    // a panic in an earlier paste leaves the provider as it was
+                    let mut provider = provider.lock().unwrap_or_else(PoisonError::into_inner);
+                    provider.send(&mime_type, &mut writer)
+                })?)
+            }
+            SourceKind::File { file, mime_types } => {
+                if !mime_types.iter().any(|mimetype| mimetype == mime_type) {
+                    return Ok(None);
+                }
+                WriteData::File {
+                    source: file.clone(),
+                    offset: 0,
+                }
+            }
+            SourceKind::Text { text, crlf } => {
+                if !TEXT_SOURCE_MIME_TYPES.contains(&mime_type) {
+                    return Ok(None);
+                }
+                let encoding =
+                    TextEncoding::from_mime_type(mime_type).unwrap_or(TextEncoding::Utf8);
+                if encoding == TextEncoding::Utf8 && !*crlf {
+                    // the text is already UTF-8, share it
+                    WriteData::Shared(text.clone().into())
+                } else {
+                    WriteData::Owned(encoding.encode(text, *crlf))
+                }
+            }
+            #[cfg(feature = "image-convert")]
+            SourceKind::Image {
+                data,
+                mime_type: original,
+                conversions,
+            } => {
+                if mime_type == original {
+                    WriteData::Shared(data.clone())
+                } else if image_convert::can_convert(original)
+                    && image_convert::can_convert(mime_type)
+                {
+                    // a failed conversion is tried again
+                    let conversion = match conversions.get(mime_type) {
+                        Some(conversion) if !matches!(conversion.image.get(), Some(Err(_))) => {
+                            conversion.clone()
+                        }
+                        _ => {
+                            let conversion = Conversion::start(
+                                data.clone(),
+                                original.clone(),
+                                mime_type.to_string(),
+                            )?;
+                            conversions.insert(mime_type.to_string(), conversion.clone());
+                            conversion
+                        }
+                    };
+                    match conversion.image.get() {
+                        Some(Ok(image)) => WriteData::Shared(image.clone()),
+                        _ => WriteData::Converting(conversion),
+                    }
+                } else {
+                    return Ok(None);
+                }
+            }
+        };
+        Ok(Some(PendingWrite {
+            file,
+            data,
+            written: 0,
+            deadline: Instant::now() + WRITE_TIMEOUT,
+        }))
+    }
+}
+
+enum WriteData {
+    /// the payload shared with [CopyData], and other pastes of it
+    Shared(Arc<[u8]>),
+    /// made for this paste, like text in another encoding
+    Owned(Vec<u8>),
+    /// sent from the file, offset is where the next sendfile starts
+    File { source: Arc<File>, offset: off_t },
+    /// written to the paste fd by the provider in a worker thread
+    Providing(Worker),
+    /// an image being converted in a thread, shared by the pastes of the format
+    #[cfg(feature = "image-convert")]
+    Converting(Arc<Conversion>),
+}
+
+/// a provider writing a paste, it may be slow, so it runs in a thread, and the copy loop polls
+/// done instead of the paste fd until it is finished
+struct Worker {
+    result: Receiver<io::Result<()>>,
+    /// closed by the thread when the result is sent
+    done: PipeReader,
+}
+
+impl Worker {
+    fn spawn(job: impl FnOnce() -> io::Result<()> + Send + 'static) -> io::Result<Self> {
+        let (done, notify) = os_pipe::pipe()?;
+        let (sender, result) = mpsc::channel();
+        std::thread::spawn(move || {
+            let _ = sender.send(job());
+            drop(notify);
+        });
+        Ok(Self { result, done })
+    }
+
+    /// the result if the thread is finished
+    fn try_result(&self) -> Option<io::Result<()>> {
+        match self.result.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                Some(Err(io::Error::other("the worker thread panicked")))
+            }
+        }
+    }
+}
+
+/// an image conversion, it is slow for large images, so it runs in a thread, and the pastes
+/// waiting for it poll done instead of their paste fd until it is finished
+#[cfg(feature = "image-convert")]
+struct Conversion {
+    /// the error is kept as a string, as every paste waiting for it gets it
+    image: OnceLock<Result<Arc<[u8]>, String>>,
+    /// closed by the thread when the image is set
+    done: PipeReader,
+}
+
+#[cfg(feature = "image-convert")]
+impl Conversion {
+    /// convert data from one format to another
+    fn start(data: Arc<[u8]>, from: String, to: String) -> io::Result<Arc<Self>> {
+        let (done, notify) = os_pipe::pipe()?;
+        let conversion = Arc::new(Self {
+            image: OnceLock::new(),
+            done,
+        });
+        let shared = conversion.clone();
+        std::thread::spawn(move || {
+            let image = panic::catch_unwind(|| image_convert::convert_image(&data, &from, &to))
+                .unwrap_or_else(|_| Err(io::Error::other("the image conversion panicked")));
+            let _ = shared
+                .image
+                .set(image.map(Arc::from).map_err(|e| e.to_string()));
+            drop(notify);
+        });
+        Ok(conversion)
+    }
+
+    /// the image if the conversion is finished
+    fn image(&self) -> Option<io::Result<Arc<[u8]>>> {
+        let image = self.image.get()?;
+        Some(image.clone().map_err(io::Error::other))
+    }
+}
+
+/// the paste fd written by a worker thread, it is non-blocking, so it waits for the reader here,
+/// and gives up if the reader takes nothing for [WRITE_TIMEOUT]
+struct TimedWriter(File);
+
+impl Write for TimedWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        loop {
+            match (&self.0).write(data) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    let timeout = PollTimeout::try_from(WRITE_TIMEOUT).unwrap_or(PollTimeout::MAX);
+                    match poll(
+                        &mut [PollFd::new(self.0.as_fd(), PollFlags::POLLOUT)],
+                        timeout,
+                    ) {
+                        Ok(0) => return Err(io::ErrorKind::TimedOut.into()),
+                        Ok(_) | Err(Errno::EINTR) => {}
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+                written => return written,
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// how long a paste side can take nothing before it is dropped, so a reader that never reads nor
+/// closes the pipe will not keep the copy alive forever
+pub(crate) const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// a paste not finished yet, the reader may be slow, so it is written when the fd is writable
+pub(crate) struct PendingWrite {
+    file: File,
+    data: WriteData,
+    written: usize,
+    /// moved on every progress, see [WRITE_TIMEOUT]
+    deadline: Instant,
+}
+
+impl PendingWrite {
+    /// write as much as the reader can take, return true when all is written
+    fn write(&mut self) -> io::Result<bool> {
+        loop {
+            let sent = match &mut self.data {
+                // the worker times out the paste side by itself
+                WriteData::Providing(worker) => match worker.try_result() {
+                    Some(sent) => return sent.map(|()| true),
+                    None => {
+                        self.deadline = Instant::now() + WRITE_TIMEOUT;
+                        return Ok(false);
+                    }
+                },
+                #[cfg(feature = "image-convert")]
+                WriteData::Converting(conversion) => match conversion.image() {
+                    Some(image) => {
+                        self.data = WriteData::Shared(image?);
+                        continue;
+                    }
+                    // the paste side is not the one to wait for, do not time it out
+                    None => {
+                        self.deadline = Instant::now() + WRITE_TIMEOUT;
+                        return Ok(false);
+                    }
+                },
+                WriteData::Shared(data) => write_slice(&self.file, &data[self.written..]),
+                WriteData::Owned(data) => write_slice(&self.file, &data[self.written..]),
+                // the offset is moved by the kernel, the file position is not touched, so
+                // pastes of the same file do not disturb each other
+                WriteData::File { source, offset } => {
+                    sendfile(&self.file, source.as_fd(), Some(offset), SENDFILE_CHUNK)
+                        .map_err(io::Error::from)
+                }
+            };
+            match sent {
+                // all the data is written, or the end of the file is reached
+                Ok(0) => return Ok(true),
+                Ok(len) => {
+                    self.written += len;
+                    self.deadline = Instant::now() + WRITE_TIMEOUT;
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// how much sendfile sends in one call
+const SENDFILE_CHUNK: usize = 1 << 20;
+
+/// write data to file, Ok(0) means there is nothing left to write
+fn write_slice(mut file: &File, data: &[u8]) -> io::Result<usize> {
+    if data.is_empty() {
+        return Ok(0);
+    }
+    match file.write(data)? {
+        0 => Err(io::ErrorKind::WriteZero.into()),
+        len => Ok(len),
+    }
+}
+
+impl PendingWrite {
+    /// the fd to poll and its flags, the paste fd to write, or the end of a conversion
+    pub(crate) fn poll_fd(&self) -> (BorrowedFd<'_>, PollFlags) {
+        match &self.data {
+            WriteData::Providing(worker) => (worker.done.as_fd(), PollFlags::POLLIN),
+            #[cfg(feature = "image-convert")]
+            WriteData::Converting(conversion) => (conversion.done.as_fd(), PollFlags::POLLIN),
+            _ => (self.file.as_fd(), PollFlags::POLLOUT),
+        }
+    }
+}
+
+/// write the pending pastes, the finished, failed and timed out ones are removed
+/// a failed one is just dropped, like when the paste side closes the pipe early, other pastes
+/// are still served
+pub(crate) fn flush_writes(writes: &mut Vec<PendingWrite>) {
+    writes.retain_mut(|pending| match pending.write() {
+        Ok(true) => false,
+        Ok(false) if pending.deadline <= Instant::now() => {
+            log::debug!("paste side takes nothing for {WRITE_TIMEOUT:?}, drop it");
+            false
+        }
+        Ok(false) => true,
+        // the pipes are not sockets, so MSG_NOSIGNAL can not be used, a closed reader comes here
+        // as EPIPE only if SIGPIPE is ignored, Rust programs ignore it by default, see the crate
+        // docs for the other hosts
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+            log::debug!("paste side closed the pipe early");
+            false
+        }
+        Err(e) => {
+            log::warn!("failed to send the data: {e}");
+            false
+        }
+    });
+}
+
+/// the earliest deadline of the pending pastes, the copy loop must wake up by then to drop them
+pub(crate) fn writes_deadline(writes: &[PendingWrite]) -> Option<Instant> {
+    writes.iter().map(|pending| pending.deadline).min()
+}
+
+/// why the copy is finished
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyEnd {
+    /// another client takes the selection
+    Replaced,
+    /// the timeout set by set_timeout is reached, and the selection is cleared
+    Timeout,
+    /// the limit set by set_paste_limit is reached, and the selection is cleared
+    Pasted,
+    /// the copy is revoked by [CopyHandle::revoke], and the selection is cleared
+    Revoked,
+}
+
+/// handle of a copy served in the background, returned by copy_in_background
+/// dropping it will not stop the copy, it keeps serving until another client takes the selection
+pub struct CopyHandle {
+    pub(crate) thread: JoinHandle<Result<CopyEnd, WlClipboardListenerError>>,
+    pub(crate) wakeup: PipeWriter,
+}
+
+impl CopyHandle {
+    /// if the selection is still served by us
+    pub fn is_active(&self) -> bool {
+        !self.thread.is_finished()
+    }
+
+    /// block until the copy is finished, return why it is finished
+    pub fn wait(self) -> Result<CopyEnd, WlClipboardListenerError> {
+        self.thread
+            .join()
+            .map_err(|_| WlClipboardListenerError::QueueError("copy thread panicked".to_string()))?
+    }
+
+    /// clear the selection if it is still ours, and wait for the copy to finish
+    pub fn revoke(mut self) -> Result<CopyEnd, WlClipboardListenerError> {
+        // if the thread is already finished, the pipe may be closed, nothing to do then
+        let _ = self.wakeup.write_all(&[1]);
+        self.wait()
+    }
+}
+
+/// the data of every mimetype, in the order they are offered
+pub(crate) type Payloads = Vec<(String, Arc<[u8]>)>;
+
+pub(crate) struct CopyData {
+    mime_types: Vec<(String, Arc<[u8]>)>,
+}
+
+impl CopyData {
+    /// all the mimetypes are served with the same data
+    pub(crate) fn new(data: Arc<[u8]>, mimetypes: Vec<&str>) -> Self {
+        Self {
+            mime_types: mimetypes
+                .into_iter()
+                .map(|mimetype| (mimetype.to_string(), data.clone()))
+                .collect(),
+        }
+    }
+
+    /// every mimetype is served with its own data
+    pub(crate) fn from_payloads(payloads: impl IntoIterator<Item = (String, Arc<[u8]>)>) -> Self {
+        let mut data = Self {
+            mime_types: Vec::new(),
+        };
+        let mut indexes: HashMap<String, usize> = HashMap::new();
+        for (mimetype, payload) in payloads {
+            // the later one wins if the mimetype is passed twice
+            if let Some(&index) = indexes.get(&mimetype) {
+                data.mime_types[index].1 = payload;
+                continue;
+            }
+            indexes.insert(mimetype.clone(), data.mime_types.len());
+            data.mime_types.push((mimetype, payload));
+        }
+        data
+    }
+
+    /// the mimetypes to offer, in the order they are passed
+    pub(crate) fn mime_types(&self) -> impl Iterator<Item = &str> {
+        self.mime_types
+            .iter()
+            .map(|(mimetype, _)| mimetype.as_str())
+    }
+
+    /// the data to send for the mimetype requested by the paste side
+    fn get(&self, mime_type: &str) -> Option<&Arc<[u8]>> {
+        self.mime_types
+            .iter()
+            .find(|(mimetype, _)| mimetype == mime_type)
+            .map(|(_, payload)| payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::os::fd::OwnedFd;
+
+    use super::*;
+
+    fn pipe() -> (PipeReader, File) {
+        let (reader, writer) = os_pipe::pipe().unwrap();
+        (reader, File::from(OwnedFd::from(writer)))
+    }
+
+    /// write the pending pastes until they are all finished, like the copy loop
+    fn drive(writes: &mut Vec<PendingWrite>) {
+        while !writes.is_empty() {
+            {
+                let mut fds: Vec<_> = writes
+                    .iter()
+                    .map(|pending| {
+                        let (fd, flags) = pending.poll_fd();
+                        PollFd::new(fd, flags)
+                    })
+                    .collect();
+                poll(&mut fds, PollTimeout::from(5000u16)).unwrap();
+            }
+            flush_writes(writes);
+        }
+    }
+
+    /// paste mime_type of source, the reader takes it all in a thread
+    fn paste(source: &mut CopySource, mime_type: &str) -> Vec<u8> {
+        let (mut reader, file) = pipe();
+        let pending = source.prepare_send(mime_type, file).unwrap().unwrap();
+        let reading = std::thread::spawn(move || {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data).unwrap();
+            data
+        });
+        drive(&mut vec![pending]);
+        reading.join().unwrap()
+    }
+
+    /// a provider waiting for the test to let it send
+    struct Gated(Receiver<()>);
+
+    impl ClipboardDataProvider for Gated {
+        fn mime_types(&self) -> Vec<String> {
+            vec!["text/plain".to_string()]
+        }
+
+        fn send(&mut self, _mime_type: &str, writer: &mut dyn Write) -> io::Result<()> {
+            self.0.recv().map_err(io::Error::other)?;
+            writer.write_all(b"late")
+        }
+    }
+
+    #[test]
+    fn a_slow_provider_does_not_block_the_pastes() {
+        let (open, gate) = mpsc::channel();
+        let mut slow = CopySource::from_provider(Gated(gate));
+        let (mut reader, file) = pipe();
+        let mut writes = vec![slow.prepare_send("text/plain", file).unwrap().unwrap()];
+        assert!(slow.prepare_send("image/png", pipe().1).unwrap().is_none());
+
+        // the provider is still waiting
+        let mut fast = CopySource::new(b"fast".to_vec(), vec!["text/plain"]);
+        assert_eq!(paste(&mut fast, "text/plain"), b"fast");
+        flush_writes(&mut writes);
+        assert_eq!(writes.len(), 1);
+
+        open.send(()).unwrap();
+        drive(&mut writes);
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"late");
+    }
+
+    #[test]
+    fn every_mime_type_has_its_payload() {
+        let data = CopyData::from_payloads(vec![
+            ("text/plain".to_string(), Arc::<[u8]>::from(&b"plain"[..])),
+            ("text/html".to_string(), Arc::from(&b"<b>html</b>"[..])),
+            // passed twice, the later one wins, and it keeps its place
+            ("text/plain".to_string(), Arc::from(&b"later"[..])),
+        ]);
+        assert_eq!(
+            data.mime_types().collect::<Vec<_>>(),
+            ["text/plain", "text/html"]
+        );
+        assert_eq!(&**data.get("text/plain").unwrap(), b"later");
+        assert!(data.get("image/png").is_none());
+
+        let mut source = CopySource::from_payloads(vec![
+            ("text/plain".to_string(), b"plain".to_vec()),
+            ("text/html".to_string(), b"<b>html</b>".to_vec()),
+        ]);
+        assert_eq!(paste(&mut source, "text/html"), b"<b>html</b>");
+        assert_eq!(paste(&mut source, "text/plain"), b"plain");
+        assert!(source
+            .prepare_send("image/png", pipe().1)
+            .unwrap()
+            .is_none());
+    }
+
+    /// larger than the buffer of a pipe
+    fn large() -> Vec<u8> {
+        (0..1 << 20).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn a_slow_reader_gets_everything() {
+        let data = large();
+        let mut source = CopySource::new(data.clone(), vec!["application/octet-stream"]);
+        let (mut reader, file) = pipe();
+        let mut writes = vec![source
+            .prepare_send("application/octet-stream", file)
+            .unwrap()
+            .unwrap()];
+        // the pipe is full, the rest waits for the reader
+        flush_writes(&mut writes);
+        assert_eq!(writes.len(), 1);
+        assert!(writes[0].written > 0 && writes[0].written < data.len());
+
+        let mut received = Vec::new();
+        let mut buf = vec![0; 16 << 10];
+        while !writes.is_empty() {
+            let len = reader.read(&mut buf).unwrap();
+            received.extend_from_slice(&buf[..len]);
+            flush_writes(&mut writes);
+        }
+        reader.read_to_end(&mut received).unwrap();
+        assert!(received == data);
+    }
+
+    #[test]
+    fn a_closed_reader_is_dropped() {
+        let mut source = CopySource::new(large(), vec!["application/octet-stream"]);
+        let (reader, file) = pipe();
+        drop(reader);
+        let mut writes = vec![source
+            .prepare_send("application/octet-stream", file)
+            .unwrap()
+            .unwrap()];
+        flush_writes(&mut writes);
+        assert!(writes.is_empty());
+    }
+
+    #[test]
+    fn a_reader_taking_nothing_is_timed_out() {
+        let mut source = CopySource::new(large(), vec!["application/octet-stream"]);
+        let (_reader, file) = pipe();
+        let before = Instant::now();
+        let mut writes = vec![source
+            .prepare_send("application/octet-stream", file)
+            .unwrap()
+            .unwrap()];
+        flush_writes(&mut writes);
+        assert_eq!(writes.len(), 1);
+        let deadline = writes_deadline(&writes).unwrap();
+        assert!(deadline >= before + WRITE_TIMEOUT);
+
+        // nothing is read until the deadline
+        writes[0].deadline = Instant::now();
+        flush_writes(&mut writes);
+        assert!(writes.is_empty());
+    }
+
+    #[test]
+    fn the_pastes_share_the_payload() {
+        let payload = Arc::<[u8]>::from(&b"shared"[..]);
+        let data = CopyData::new(payload.clone(), vec!["text/plain", "UTF8_STRING"]);
+        assert!(Arc::ptr_eq(data.get("text/plain").unwrap(), &payload));
+        assert!(Arc::ptr_eq(data.get("UTF8_STRING").unwrap(), &payload));
+
+        let mut source = CopySource::new(payload.clone(), vec!["text/plain"]);
+        for _ in 0..2 {
+            let pending = source
+                .prepare_send("text/plain", pipe().1)
+                .unwrap()
+                .unwrap();
+            assert!(
+                matches!(&pending.data, WriteData::Shared(data) if Arc::ptr_eq(data, &payload))
+            );
+        }
+    }
+
+    #[cfg(feature = "image-convert")]
+    #[test]
+    fn the_pastes_of_a_format_share_one_conversion() {
+        let mut source = CopySource::image(image_convert::tests::png(), "image/png");
+        let (mut first_reader, first) = pipe();
+        let (mut second_reader, second) = pipe();
+        let mut writes = vec![
+            source.prepare_send("image/bmp", first).unwrap().unwrap(),
+            source.prepare_send("image/bmp", second).unwrap().unwrap(),
+        ];
+        let SourceKind::Image { conversions, .. } = &source.kind else {
+            unreachable!();
+        };
+        assert_eq!(conversions.len(), 1);
+
+        drive(&mut writes);
+        let (mut first, mut second) = (Vec::new(), Vec::new());
+        first_reader.read_to_end(&mut first).unwrap();
+        second_reader.read_to_end(&mut second).unwrap();
+        assert_eq!(crate::detect_mime_types(&first), ["image/bmp"]);
+        assert_eq!(first, second);
+        // the later pastes take the converted one
+        let pending = source.prepare_send("image/bmp", pipe().1).unwrap().unwrap();
+        assert!(matches!(pending.data, WriteData::Shared(_)));
+    }
+}
@@ -0,0 +1,230 @@
+//! encryption of the persistent history, with the `encryption` feature
+//! every record of the log and every blob is encrypted with XChaCha20-Poly1305 with a random
+//! nonce, so they can not be read or changed without the key. A record is bound to its position
+//! in the log, so the records can not be reordered either. The blobs are named by a keyed hash of
+//! their data, so the names do not tell what was copied
+//!
+//! The key is made from a passphrase with argon2, or is the sha256 of a key file or of a key in
+//! the kernel keyring. `crypt.json` in the history directory keeps the salt of the passphrase, and
+//! a known text encrypted with the key, to tell a wrong key when unlocking
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use hmac::{Hmac, Mac};
+use linux_keyutils::{KeyRing, KeyRingIdentifier};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::history::{from_hex, to_hex};
+use crate::store::{write_atomic, CRYPT_FILE};
+
+const VERSION: u32 = 1;
+const NONCE_LEN: usize = 24;
+const CHECK_TEXT: &[u8] = b"wayland-clipboard-listener history";
+const CHECK_AAD: &[u8] = b"check";
+/// a shorter key file is too easy to guess, an empty one hashes to a known key
+const MIN_KEY_FILE_LEN: usize = 32;
+
+/// where the key of an encrypted history comes from
+pub enum HistoryKey {
+    /// a passphrase, the key is made from it with argon2, it is wiped from the memory when it is
+    /// dropped, like `HistoryKey::Passphrase(passphrase.into())`
+    Passphrase(Zeroizing<String>),
+    /// a file with at least 32 bytes of random data, like `head -c 32 /dev/urandom > key`
+    KeyFile(PathBuf),
+    /// the description of a user key in the kernel keyring, like the one added by
+    /// `keyctl add user clipboard-history "$(head -c 32 /dev/urandom | base64)" @u`
+    Keyring(String),
+}
+
+#[derive(Serialize, Deserialize)]
+struct CryptParams {
+    version: u32,
+    salt: String,
+    check: String,
+}
+
+fn invalid_data(e: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+fn wrong_key() -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, "wrong key for the history")
+}
+
+/// the key material of key, hashed or stretched to 32 bytes
+fn master_key(key: &HistoryKey, salt: &[u8]) -> io::Result<Zeroizing<[u8; 32]>> {
+    let mut master = Zeroizing::new([0; 32]);
+    match key {
+        HistoryKey::Passphrase(passphrase) => Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, master.as_mut())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?,
+        HistoryKey::KeyFile(path) => {
+            let content = Zeroizing::new(fs::read(path)?);
+            if content.len() < MIN_KEY_FILE_LEN {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("the key file must have at least {MIN_KEY_FILE_LEN} bytes"),
+                ));
+            }
+            master.copy_from_slice(&Sha256::digest(content.as_slice()));
+        }
+        HistoryKey::Keyring(description) => {
+            // the session keyring is searched first, it is usually linked to the user one
+            let key = [KeyRingIdentifier::Session, KeyRingIdentifier::User]
+                .into_iter()
+                .filter_map(|id| KeyRing::from_special_id(id, false).ok())
+                .find_map(|ring| ring.search(description).ok())
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("no key {description} in the keyring"),
+                    )
+                })?;
+            let content = Zeroizing::new(
+                key.read_to_vec()
+                    .map_err(|e| io::Error::other(format!("{e:?}")))?,
+            );
+            master.copy_from_slice(&Sha256::digest(content.as_slice()));
+        }
+    }
+    Ok(master)
+}
+
+/// encrypts and decrypts the records and blobs of a history
+pub(crate) struct Cipher {
+    aead: XChaCha20Poly1305,
+    name_key: Zeroizing<[u8; 32]>,
+}
+
+impl Cipher {
+    fn new(master: &[u8; 32]) -> Self {
+        // different keys for encrypting and naming, both made from the master key
+        let derive = |purpose: &[u8]| {
+            let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(master).unwrap();
+            mac.update(purpose);
+            Zeroizing::new(<[u8; 32]>::from(mac.finalize().into_bytes()))
+        };
+        let encrypt_key = derive(b"encrypt");
+        Self {
+            aead: XChaCha20Poly1305::new(encrypt_key.as_ref().into()),
+            name_key: derive(b"name"),
+        }
+    }
+
+    /// set up the encryption of the history in dir with key
+    pub(crate) fn create(dir: &Path, key: &HistoryKey) -> io::Result<Self> {
+        let mut salt = [0; 16];
+        OsRng.fill_bytes(&mut salt);
+        let cipher = Self::new(&*master_key(key, &salt)?);
+        let params = CryptParams {
+            version: VERSION,
+            salt: to_hex(&salt),
+            check: to_hex(&cipher.encrypt(CHECK_TEXT, CHECK_AAD)),
+        };
+        let content = serde_json::to_vec_pretty(&params).map_err(io::Error::other)?;
+        write_atomic(&dir.join(CRYPT_FILE), &content)?;
+        Ok(cipher)
+    }
+
+    /// the cipher of the history in dir, if key is the right one
+    pub(crate) fn unlock(dir: &Path, key: &HistoryKey) -> io::Result<Self> {
+        let params: CryptParams =
+            serde_json::from_slice(&fs::read(dir.join(CRYPT_FILE))?).map_err(invalid_data)?;
+        if params.version != VERSION {
+            return Err(invalid_data(format!(
+                "unsupported encryption version {}",
+                params.version
+            )));
+        }
+        let salt = from_hex(&params.salt).ok_or_else(|| invalid_data("bad salt"))?;
+        let check = from_hex(&params.check).ok_or_else(|| invalid_data("bad check"))?;
+        let cipher = Self::new(&*master_key(key, &salt)?);
+        match cipher.decrypt(&check, CHECK_AAD) {
+            Ok(text) if text == CHECK_TEXT => Ok(cipher),
+            _ => Err(wrong_key()),
+        }
+    }
+
+    /// encrypt data, the nonce is put in front of it
+    /// aad is authenticated with it, so it can not be moved to where another aad is used
+    pub(crate) fn encrypt(&self, data: &[u8], aad: &[u8]) -> Vec<u8> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut sealed = nonce.to_vec();
+        sealed.extend(
+            self.aead
+                .encrypt(&nonce, Payload { msg: data, aad })
+                .expect("encrypting in memory does not fail"),
+        );
+        sealed
+    }
+
+    pub(crate) fn decrypt(&self, sealed: &[u8], aad: &[u8]) -> io::Result<Vec<u8>> {
+        if sealed.len() < NONCE_LEN {
+            return Err(invalid_data("encrypted data is too short"));
+        }
+        let (nonce, data) = sealed.split_at(NONCE_LEN);
+        self.aead
+            .decrypt(XNonce::from_slice(nonce), Payload { msg: data, aad })
+            .map_err(|_| invalid_data("encrypted data is changed, or the key is wrong"))
+    }
+
+    /// the name of the blob of data, a keyed hash
+    pub(crate) fn blob_name(&self, data: &[u8]) -> String {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(self.name_key.as_ref()).unwrap();
+        mac.update(data);
+        to_hex(&mac.finalize().into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    fn passphrase(passphrase: &str) -> HistoryKey {
+        HistoryKey::Passphrase(passphrase.to_string().into())
+    }
+
+    #[test]
+    fn unlock_needs_the_same_key() {
+        let tmp = TempDir::new();
+        let cipher = Cipher::create(tmp.path(), &passphrase("correct horse")).unwrap();
+        let sealed = cipher.encrypt(b"hello", b"aad");
+
+        let unlocked = Cipher::unlock(tmp.path(), &passphrase("correct horse")).unwrap();
+        assert_eq!(unlocked.decrypt(&sealed, b"aad").unwrap(), b"hello");
+        assert_eq!(unlocked.blob_name(b"hello"), cipher.blob_name(b"hello"));
+        // the aad is authenticated
+        assert!(unlocked.decrypt(&sealed, b"other").is_err());
+
+        let wrong = Cipher::unlock(tmp.path(), &passphrase("battery staple"));
+        assert_eq!(wrong.err().unwrap().kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn short_key_files_are_refused() {
+        let tmp = TempDir::new();
+        for len in [0, MIN_KEY_FILE_LEN - 1] {
+            let path = tmp.path().join(format!("key{len}"));
+            fs::write(&path, vec![7; len]).unwrap();
+            let key = HistoryKey::KeyFile(path);
+            assert_eq!(
+                Cipher::create(tmp.path(), &key).err().unwrap().kind(),
+                io::ErrorKind::InvalidInput
+            );
+        }
+        let path = tmp.path().join("key");
+        fs::write(&path, [7; MIN_KEY_FILE_LEN]).unwrap();
+        let key = HistoryKey::KeyFile(path);
+        Cipher::create(tmp.path(), &key).unwrap();
+        Cipher::unlock(tmp.path(), &key).unwrap();
+    }
+}
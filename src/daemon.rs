@@ -0,0 +1,1344 @@
+//! the daemon serving the clipboard to the clients of a unix socket
+//! one daemon owns the wayland connection, so the clients neither connect to wayland, nor fork to
+//! keep their copies alive, see [crate::WlClipboardDaemonStream] and [crate::ClipboardClient]
+//!
+//! the protocol is JSON Lines, every request is a line, answered by a line in order, the data is
+//! in base64 in a map of the mimetypes, like in the export, see [crate::ClipboardHistory::export]
+//! ```json
+//! > {"command":"get","primary":false,"mime_type":"text/plain"}
+//! < {"payloads":{"text/plain":"aGVsbG8="},"marks":[]}
+//! > {"command":"set","target":"clipboard","payloads":{"text/plain":"aGVsbG8="}}
+//! < {}
+//! > {"command":"clear","target":"both"}
+//! < {}
+//! > {"command":"history","query":"hel","limit":10}
+//! < {"entries":[{"id":3,"time":1700000000000,"mime_types":["text/plain"],"text":"hello"}]}
+//! > {"command":"select","id":3,"target":"clipboard"}
+//! < {}
+//! > {"command":"pin","id":3,"pinned":true}
+//! < {}
+//! > {"command":"set_register","name":"a","payloads":{"text/plain":"aGVsbG8="}}
+//! < {}
+//! > {"command":"registers"}
+//! < {"registers":{"a":{"id":0,"time":1700000000000,"pinned":false,"marks":[],"mime_types":["text/plain"],"text":"hello"}}}
+//! > {"command":"register","name":"a"}
+//! < {"payloads":{"text/plain":"aGVsbG8="},"marks":[]}
+//! > {"command":"remove_register","name":"a"}
+//! < {}
+//! > {"command":"export"}
+//! < {"lines":"{\"time\":1700000000000,\"payloads\":{\"text/plain\":\"aGVsbG8=\"}}\n","count":1}
+//! > {"command":"import","lines":"{\"time\":1700000000000,\"payloads\":{\"text/plain\":\"aGVsbG8=\"}}\n"}
+//! < {"count":1}
+//! > {"command":"lock"}
+//! < {}
+//! > {"command":"unlock","key":{"key_file":"/home/me/.history-key"}}
+//! < {}
+//! > {"command":"subscribe","target":"clipboard","mime_types":["text/*"],"data":true}
+//! < {}
+//! < {"event":"changed","primary":false,"mime_types":["text/plain"],"marks":[],"payloads":{"text/plain":"aGVsbG8="}}
+//! < {"event":"cleared","primary":false}
+//! < {"event":"lagged","missed":12}
+//! ```
+//! * get: the selection last seen by the daemon, the clipboard, or the primary selection if
+//!   primary is true, only the data of mime_type if it is passed, or all of it, the reply is `{}`
+//!   if the selection is empty, or it is not kept, like a secret or one dropped by the rules
+//! * set: copy the payloads to target, `clipboard`, `primary` or `both`, clipboard by default,
+//!   the daemon serves the pastes until another client takes the selection
+//! * clear: clear the selections of target
+//! * history: search the history like [crate::HistoryQuery], the newest first if query is empty,
+//!   the data is not sent, get it by select, then get
+//! * select: copy the history entry id to target again
+//! * pin: pin the history entry id, or unpin it if pinned is false, see
+//!   [crate::ClipboardHistory::pin]
+//! * set_register, registers, register, remove_register: put the payloads in the register name,
+//!   `a` to `z`, list the registers, get the data of one, and empty one, see
+//!   [crate::ClipboardHistory::set_register]
+//! * export, import: the history as JSON Lines in lines, see [crate::ClipboardHistory::export],
+//!   count is the count of lines exported, or of entries imported
+//! * lock, unlock: lock an encrypted history, or unlock it with key, `{"passphrase":"..."}`,
+//!   `{"key_file":"..."}` or `{"keyring":"..."}`, see [crate::HistoryKey], the history is not
+//!   read nor written while it is locked, the requests about it fail, and the selections are not
+//!   recorded
+//! * subscribe: the changes of the selections of target are sent as events since then, both
+//!   selections by default, only the selections offering one of mime_types if they are passed,
+//!   and the data of these mimetypes is sent too if data is true, see [crate::SubscriptionFilter]
+//!   the replies to the requests sent later are mixed with the events, an event always has the
+//!   event field, a reply never has it
+//!
+//! a subscriber never blocks the daemon or the other clients, the events wait until it can take
+//! them, and when too many of them are waiting, the oldest ones are dropped, then it is told how
+//! many are missed by a lagged event, see [crate::WlClipboardDaemonStream::set_backlog]
+//! the replies are never dropped, so a client sending requests without reading the replies is
+//! dropped once 256 MiB of them are waiting
+//!
+//! a failed request is answered by `{"error":"..."}`
+//!
+//! the daemon holds the lock of its history, see [HistoryStore::open], so the other programs
+//! reach the history through these requests while it runs, an encrypted history may be given
+//! locked, and unlocked by a client later
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, DirBuilder, Permissions};
+use std::io::{self, Read, Write};
+use std::os::fd::{AsFd, BorrowedFd};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use nix::poll::PollFlags;
+use nix::sys::socket::{getsockopt, sockopt};
+use nix::unistd::getuid;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::copy::Payloads;
+use crate::export::MimeMap;
+use crate::history::HistoryEntry;
+use crate::mime::mime_matches;
+use crate::search::HistoryQuery;
+use crate::store::{from_millis, to_millis, HistoryStore};
+use crate::{ClipBoardSelection, WlCopyTarget};
+
+/// a request line longer than it is refused, and the client is dropped
+const MAX_REQUEST: usize = 256 << 20;
+
+/// the bytes of the replies waiting for a client, beyond it the client is dropped, as it sends
+/// requests without reading the replies, one reply alone is always sent, like a large export
+const MAX_REPLIES: usize = 256 << 20;
+
+/// the bytes of the events waiting for a subscriber, beyond it the oldest ones are dropped
+pub(crate) const DEFAULT_BACKLOG: usize = 4 << 20;
+
+/// the socket of the daemon, `$XDG_RUNTIME_DIR/marine-clipboard.sock`
+/// it fails with NotFound if XDG_RUNTIME_DIR is not set, a path under /tmp could be taken by
+/// someone else first, pass the path of the socket then
+pub fn daemon_socket_path() -> io::Result<PathBuf> {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => Ok(PathBuf::from(dir).join("marine-clipboard.sock")),
+        _ => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "XDG_RUNTIME_DIR is not set, the path of the socket is needed",
+        )),
+    }
+}
+
+/// create the dir of the socket at path if it does not exist, the others could replace the
+/// socket if they can write the dir, so it must be the user's, and only writable by the user
+fn check_socket_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    // a link could lead to the dir of someone else
+    let metadata = fs::symlink_metadata(dir)?;
+    if !metadata.is_dir() || metadata.uid() != getuid().as_raw() || metadata.mode() & 0o022 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{dir:?} must be a dir of the user, and only writable by the user"),
+        ));
+    }
+    Ok(())
+}
+
+/// check that the other end of stream is run by the user, the daemon serves no other user, and
+/// the clients do not talk to a daemon of someone else
+pub(crate) fn check_peer(stream: &UnixStream) -> io::Result<()> {
+    let credentials = getsockopt(stream, sockopt::PeerCredentials)?;
+    if credentials.uid() != getuid().as_raw() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("the other end is run by the user {}", credentials.uid()),
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub(crate) enum Request {
+    Get {
+        #[serde(default)]
+        primary: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mime_type: Option<String>,
+    },
+    Set {
+        #[serde(default = "clipboard", with = "target")]
+        target: WlCopyTarget,
+        payloads: MimeMap,
+    },
+    Clear {
+        #[serde(default = "clipboard", with = "target")]
+        target: WlCopyTarget,
+    },
+    History {
+        #[serde(default)]
+        query: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        limit: Option<usize>,
+    },
+    Select {
+        id: u64,
+        #[serde(default = "clipboard", with = "target")]
+        target: WlCopyTarget,
+    },
+    Subscribe(SubscriptionFilter),
+    Pin {
+        id: u64,
+        #[serde(default = "yes")]
+        pinned: bool,
+    },
+    SetRegister {
+        name: char,
+        payloads: MimeMap,
+    },
+    Registers,
+    Register {
+        name: char,
+    },
+    RemoveRegister {
+        name: char,
+    },
+    Export,
+    Import {
+        lines: String,
+    },
+    Lock,
+    Unlock {
+        key: UnlockKey,
+    },
+}
+
+/// the key of an unlock request, like [crate::HistoryKey]
+/// a key file is read by the daemon, the socket is only open to the user, see [check_peer]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum UnlockKey {
+    Passphrase(String),
+    KeyFile(PathBuf),
+    Keyring(String),
+}
+
+#[cfg(feature = "encryption")]
+impl From<&crate::HistoryKey> for UnlockKey {
+    fn from(key: &crate::HistoryKey) -> Self {
+        match key {
+            crate::HistoryKey::Passphrase(passphrase) => Self::Passphrase(passphrase.to_string()),
+            crate::HistoryKey::KeyFile(path) => Self::KeyFile(path.clone()),
+            crate::HistoryKey::Keyring(description) => Self::Keyring(description.clone()),
+        }
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl From<UnlockKey> for crate::HistoryKey {
+    fn from(key: UnlockKey) -> Self {
+        match key {
+            UnlockKey::Passphrase(passphrase) => Self::Passphrase(passphrase.into()),
+            UnlockKey::KeyFile(path) => Self::KeyFile(path),
+            UnlockKey::Keyring(description) => Self::Keyring(description),
+        }
+    }
+}
+
+fn yes() -> bool {
+    true
+}
+
+fn clipboard() -> WlCopyTarget {
+    WlCopyTarget::Clipboard
+}
+
+fn both() -> WlCopyTarget {
+    WlCopyTarget::Both
+}
+
+/// the target named `clipboard`, `primary` or `both`
+pub(crate) fn target_from_name(name: &str) -> Option<WlCopyTarget> {
+    match name {
+        "clipboard" => Some(WlCopyTarget::Clipboard),
+        "primary" => Some(WlCopyTarget::Primary),
+        "both" => Some(WlCopyTarget::Both),
+        _ => None,
+    }
+}
+
+/// the reply to a request, only the fields of the request are set
+#[derive(Default, Serialize, Deserialize)]
+pub(crate) struct Reply {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) payloads: Option<MimeMap>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) marks: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) entries: Option<Vec<HistoryItem>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) registers: Option<BTreeMap<char, HistoryItem>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) lines: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) count: Option<usize>,
+}
+
+impl Reply {
+    fn error(error: impl ToString) -> Self {
+        Self {
+            error: Some(error.to_string()),
+            ..Self::default()
+        }
+    }
+}
+
+/// a change of the selections, sent to the subscribed clients, see [crate::ClipboardClient]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ClipboardEvent {
+    /// something new is copied, by another client of wayland or of the daemon
+    /// mime_types are the ones matching the filter, and payloads has their data if it is asked
+    /// by [SubscriptionFilter::set_data]
+    Changed {
+        primary: bool,
+        mime_types: Vec<String>,
+        #[serde(default)]
+        marks: Vec<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty", with = "payloads")]
+        payloads: Vec<(String, Arc<[u8]>)>,
+    },
+    /// the selection is cleared, or replaced by one the daemon does not keep, like a password
+    /// marked by its password manager
+    Cleared { primary: bool },
+    /// the subscriber is too slow, missed events are dropped, the following ones are the newest
+    Lagged { missed: usize },
+}
+
+/// what a subscriber is told, see [crate::ClipboardClient::subscribe_filtered]
+/// by default, every change of both selections, without the data
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubscriptionFilter {
+    #[serde(default = "both", with = "target")]
+    target: WlCopyTarget,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    mime_types: Vec<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    data: bool,
+}
+
+impl Default for SubscriptionFilter {
+    fn default() -> Self {
+        Self {
+            target: WlCopyTarget::Both,
+            mime_types: Vec::new(),
+            data: false,
+        }
+    }
+}
+
+impl SubscriptionFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// only the changes of the selections of target
+    pub fn set_target(&mut self, target: impl Into<WlCopyTarget>) {
+        self.target = target.into();
+    }
+
+    /// only the selections offering one of mime_types, they can end with "*", like "image/*"
+    /// the events only list the matching mimetypes, an empty list matches all of them
+    pub fn set_mime_types(&mut self, mime_types: Vec<String>) {
+        self.mime_types = mime_types;
+    }
+
+    /// send the data of the matching mimetypes with the events, so the subscriber needs not get
+    /// it, which may be too late if the selection changes again
+    pub fn set_data(&mut self, data: bool) {
+        self.data = data;
+    }
+
+    fn matches(&self, mimetype: &str) -> bool {
+        self.mime_types.is_empty()
+            || self
+                .mime_types
+                .iter()
+                .any(|pattern| mime_matches(pattern, mimetype))
+    }
+
+    /// the event of the new selection, None if it is filtered out
+    fn changed(&self, selection: &ClipBoardSelection) -> Option<ClipboardEvent> {
+        if !self.target.contains(selection.primary) {
+            return None;
+        }
+        let payloads: Payloads = selection
+            .payloads
+            .iter()
+            .filter(|(mimetype, _)| self.matches(mimetype))
+            .cloned()
+            .collect();
+        if payloads.is_empty() {
+            return None;
+        }
+        Some(ClipboardEvent::Changed {
+            primary: selection.primary,
+            mime_types: payloads
+                .iter()
+                .map(|(mimetype, _)| mimetype.clone())
+                .collect(),
+            marks: selection.marks.clone(),
+            payloads: if self.data { payloads } else { Vec::new() },
+        })
+    }
+}
+
+/// an entry of the history of the daemon, without its data, see
+/// [crate::ClipboardClient::history]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryItem {
+    pub id: u64,
+    /// the last time it was copied
+    #[serde(with = "millis")]
+    pub time: SystemTime,
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default)]
+    pub marks: Vec<String>,
+    pub mime_types: Vec<String>,
+    /// the text of the entry, see [HistoryEntry::text]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
+
+impl HistoryItem {
+    fn new(entry: &HistoryEntry) -> Self {
+        Self {
+            id: entry.id(),
+            time: entry.timestamp(),
+            pinned: entry.pinned(),
+            marks: entry.marks().to_vec(),
+            mime_types: entry.mime_types().into_iter().map(str::to_string).collect(),
+            text: entry.text().map(str::to_string),
+        }
+    }
+}
+
+/// [WlCopyTarget] as `clipboard`, `primary` or `both`
+mod target {
+    use super::*;
+
+    const NAMES: &[&str] = &["clipboard", "primary", "both"];
+
+    pub(super) fn serialize<S: Serializer>(
+        target: &WlCopyTarget,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let name = match target {
+            WlCopyTarget::Clipboard => NAMES[0],
+            WlCopyTarget::Primary => NAMES[1],
+            WlCopyTarget::Both => NAMES[2],
+        };
+        serializer.serialize_str(name)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<WlCopyTarget, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        target_from_name(&name).ok_or_else(|| serde::de::Error::unknown_variant(&name, NAMES))
+    }
+}
+
+/// the payloads as a map of the mimetypes to the data in base64, like [MimeMap]
+mod payloads {
+    use super::*;
+
+    pub(super) fn serialize<S: Serializer>(
+        payloads: &Payloads,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        MimeMap(payloads.clone()).serialize(serializer)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Payloads, D::Error> {
+        MimeMap::deserialize(deserializer).map(|payloads| payloads.0)
+    }
+}
+
+/// [SystemTime] as milliseconds since the epoch
+mod millis {
+    use super::*;
+
+    pub(super) fn serialize<S: Serializer>(
+        time: &SystemTime,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(to_millis(*time))
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<SystemTime, D::Error> {
+        u64::deserialize(deserializer).map(from_millis)
+    }
+}
+
+/// what the daemon asks the wayland side to do
+pub(crate) enum DaemonAction {
+    Set(Payloads, WlCopyTarget),
+    Clear(WlCopyTarget),
+}
+
+/// a line to write to a client
+struct Line {
+    data: Vec<u8>,
+    /// the events can be dropped if the client is too slow, the replies can not
+    event: bool,
+}
+
+struct Client {
+    stream: UnixStream,
+    input: Vec<u8>,
+    /// the lines to write, the first one may be partly written
+    output: VecDeque<Line>,
+    written: usize,
+    /// the bytes of the replies in output
+    replies: usize,
+    /// the bytes of the events in output
+    backlog: usize,
+    /// the events dropped since the last lagged event
+    missed: usize,
+    subscription: Option<SubscriptionFilter>,
+    /// the client will send nothing more, it is dropped once the replies are written
+    eof: bool,
+    closed: bool,
+}
+
+impl Client {
+    fn new(stream: UnixStream) -> Self {
+        Self {
+            stream,
+            input: Vec::new(),
+            output: VecDeque::new(),
+            written: 0,
+            replies: 0,
+            backlog: 0,
+            missed: 0,
+            subscription: None,
+            eof: false,
+            closed: false,
+        }
+    }
+
+    /// the poll flags to wait for, empty if there is nothing to wait for
+    fn poll_flags(&self) -> PollFlags {
+        let mut flags = PollFlags::empty();
+        if !self.eof {
+            flags |= PollFlags::POLLIN;
+        }
+        if !self.output.is_empty() {
+            flags |= PollFlags::POLLOUT;
+        }
+        flags
+    }
+
+    /// read what the client can send without blocking, return the complete lines
+    fn read_lines(&mut self) -> Vec<Vec<u8>> {
+        let mut buf = [0; 64 * 1024];
+        while !self.eof {
+            match self.stream.read(&mut buf) {
+                Ok(0) => self.eof = true,
+                Ok(len) => self.input.extend_from_slice(&buf[..len]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    log::debug!("failed to read from a client: {e}");
+                    self.closed = true;
+                    return Vec::new();
+                }
+            }
+        }
+        let Some(end) = self.input.iter().rposition(|byte| *byte == b'\n') else {
+            if self.input.len() > MAX_REQUEST {
+                log::warn!("a client sent a too long request, it is dropped");
+                self.closed = true;
+            }
+            return Vec::new();
+        };
+        let rest = self.input.split_off(end + 1);
+        let input = std::mem::replace(&mut self.input, rest);
+        input
+            .split(|byte| *byte == b'\n')
+            .filter(|line| !line.trim_ascii().is_empty())
+            .map(<[u8]>::to_vec)
+            .collect()
+    }
+
+    fn line(line: &impl Serialize) -> Vec<u8> {
+        // the lines are made of the types here, they can always be serialized
+        let mut data = serde_json::to_vec(line).unwrap();
+        data.push(b'\n');
+        data
+    }
+
+    /// queue the reply, the client is dropped if the replies waiting are more than max_replies
+    /// bytes, but one reply alone is always kept
+    fn send(&mut self, reply: &Reply, max_replies: usize) {
+        let data = Self::line(reply);
+        self.replies += data.len();
+        if self.replies > max_replies && self.replies > data.len() {
+            log::warn!("a client does not read its replies, it is dropped");
+            self.closed = true;
+            self.output.clear();
+            return;
+        }
+        self.output.push_back(Line { data, event: false });
+    }
+
+    /// queue the event, and drop the oldest events waiting if they are more than backlog bytes
+    /// the newest event is always kept, and so is the one partly written
+    fn send_event(&mut self, event: &ClipboardEvent, backlog: usize) {
+        let data = Self::line(event);
+        self.backlog += data.len();
+        self.output.push_back(Line { data, event: true });
+        let first = usize::from(self.written > 0);
+        while self.backlog > backlog {
+            let Some(index) =
+                (first..self.output.len() - 1).find(|index| self.output[*index].event)
+            else {
+                break;
+            };
+            let dropped = self.output.remove(index).unwrap();
+            self.backlog -= dropped.data.len();
+            self.missed += 1;
+        }
+    }
+
+    /// write what the client can take without blocking
+    fn flush(&mut self) {
+        if self.closed {
+            return;
+        }
+        loop {
+            // the client is told about the dropped events before the newer ones
+            if self.missed > 0
+                && self.written == 0
+                && self.output.front().is_some_and(|line| line.event)
+            {
+                let lagged = ClipboardEvent::Lagged {
+                    missed: std::mem::take(&mut self.missed),
+                };
+                let data = Self::line(&lagged);
+                self.replies += data.len();
+                self.output.push_front(Line { data, event: false });
+            }
+            let Some(line) = self.output.front() else {
+                break;
+            };
+            match self.stream.write(&line.data[self.written..]) {
+                Ok(len) => {
+                    self.written += len;
+                    if self.written == line.data.len() {
+                        if line.event {
+                            self.backlog -= line.data.len();
+                        } else {
+                            self.replies -= line.data.len();
+                        }
+                        self.output.pop_front();
+                        self.written = 0;
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    log::debug!("failed to write to a client: {e}");
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+        if self.eof && self.output.is_empty() && self.subscription.is_none() {
+            self.closed = true;
+        }
+    }
+}
+
+/// the socket side of the daemon, it knows the selections, and keeps the history
+pub(crate) struct Server {
+    listener: UnixListener,
+    path: PathBuf,
+    clients: Vec<Client>,
+    current: [Option<ClipBoardSelection>; 2],
+    pub(crate) history: Option<HistoryStore>,
+    pub(crate) backlog: usize,
+    max_replies: usize,
+}
+
+impl Server {
+    /// listen on path, the socket left by a dead daemon is replaced
+    /// the dir of path is created if it does not exist, and it must be only writable by the user
+    pub(crate) fn bind(path: &Path) -> io::Result<Self> {
+        check_socket_dir(path)?;
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "another daemon is listening on it",
+            ));
+        }
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        fs::set_permissions(path, Permissions::from_mode(0o600))?;
+        Ok(Self {
+            listener,
+            path: path.to_path_buf(),
+            clients: Vec::new(),
+            current: [None, None],
+            history: None,
+            backlog: DEFAULT_BACKLOG,
+            max_replies: MAX_REPLIES,
+        })
+    }
+
+    /// the fds to poll with the wayland connection, see [Server::serve]
+    pub(crate) fn poll_fds(&self) -> Vec<(BorrowedFd<'_>, PollFlags)> {
+        std::iter::once((self.listener.as_fd(), PollFlags::POLLIN))
+            .chain(
+                self.clients
+                    .iter()
+                    .map(|client| (client.stream.as_fd(), client.poll_flags()))
+                    .filter(|(_, flags)| !flags.is_empty()),
+            )
+            .collect()
+    }
+
+    /// a new selection is received, or set by a client
+    /// only the clipboard is kept in the history, not every selected text
+    pub(crate) fn selection_changed(&mut self, selection: ClipBoardSelection) {
+        let index = usize::from(selection.primary);
+        if !selection.primary {
+            if let Some(history) = self.history.as_mut().filter(|history| !history.is_locked()) {
+                if let Err(e) = history.record(selection.clone()) {
+                    log::warn!("failed to save the selection in the history: {e}");
+                }
+            }
+        }
+        for client in &mut self.clients {
+            if let Some(event) = client
+                .subscription
+                .as_ref()
+                .and_then(|filter| filter.changed(&selection))
+            {
+                client.send_event(&event, self.backlog);
+            }
+        }
+        self.current[index] = Some(selection);
+    }
+
+    /// the selection is cleared by another client of wayland, or it is one the daemon does not
+    /// keep, like a secret, so it is not answered to get anymore
+    pub(crate) fn selection_gone(&mut self, primary: bool) {
+        if self.current[usize::from(primary)].is_some() {
+            self.cleared(primary);
+        }
+    }
+
+    fn cleared(&mut self, primary: bool) {
+        self.current[usize::from(primary)] = None;
+        for client in &mut self.clients {
+            if client
+                .subscription
+                .as_ref()
+                .is_some_and(|filter| filter.target.contains(primary))
+            {
+                client.send_event(&ClipboardEvent::Cleared { primary }, self.backlog);
+            }
+        }
+    }
+
+    /// accept the new clients, answer their requests, and write what they can take
+    /// return what is to be done on the wayland side
+    pub(crate) fn serve(&mut self) -> Vec<DaemonAction> {
+        self.accept();
+        let mut actions = Vec::new();
+        for index in 0..self.clients.len() {
+            for line in self.clients[index].read_lines() {
+                let reply = self.handle(index, &line, &mut actions);
+                self.clients[index].send(&reply, self.max_replies);
+                if self.clients[index].closed {
+                    break;
+                }
+            }
+        }
+        for client in &mut self.clients {
+            client.flush();
+        }
+        self.clients.retain(|client| !client.closed);
+        actions
+    }
+
+    fn accept(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    match check_peer(&stream).and_then(|()| stream.set_nonblocking(true)) {
+                        Ok(()) => self.clients.push(Client::new(stream)),
+                        Err(e) => log::warn!("failed to set up a client: {e}"),
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    log::warn!("failed to accept a client: {e}");
+                    return;
+                }
+            }
+        }
+    }
+
+    fn handle(&mut self, index: usize, line: &[u8], actions: &mut Vec<DaemonAction>) -> Reply {
+        let request = match serde_json::from_slice(line) {
+            Ok(request) => request,
+            Err(e) => return Reply::error(format!("bad request: {e}")),
+        };
+        match request {
+            Request::Get { primary, mime_type } => {
+                let Some(selection) = &self.current[usize::from(primary)] else {
+                    return Reply::default();
+                };
+                let payloads = match mime_type {
+                    Some(mime_type) => {
+                        let Some(payload) = selection
+                            .payloads
+                            .iter()
+                            .find(|(mimetype, _)| *mimetype == mime_type)
+                        else {
+                            return Reply::error(format!("{mime_type} is not offered"));
+                        };
+                        vec![payload.clone()]
+                    }
+                    None => selection.payloads.clone(),
+                };
+                Reply {
+                    payloads: Some(MimeMap(payloads)),
+                    marks: Some(selection.marks.clone()),
+                    ..Reply::default()
+                }
+            }
+            Request::Set { target, payloads } => {
+                if payloads.0.is_empty() {
+                    return Reply::error("nothing to set");
+                }
+                self.set(payloads.0, Vec::new(), target, actions);
+                Reply::default()
+            }
+            Request::Clear { target } => {
+                for &useprimary in target.primaries() {
+                    self.cleared(useprimary);
+                }
+                actions.push(DaemonAction::Clear(target));
+                Reply::default()
+            }
+            Request::History { query, limit } => {
+                let history = match self.unlocked_history() {
+                    Ok(history) => history,
+                    Err(e) => return Reply::error(e),
+                };
+                let mut query = HistoryQuery::new(query);
+                query.set_limit(limit);
+                Reply {
+                    entries: Some(
+                        history
+                            .search(&query)
+                            .into_iter()
+                            .map(|found| HistoryItem::new(found.entry))
+                            .collect(),
+                    ),
+                    ..Reply::default()
+                }
+            }
+            Request::Select { id, target } => {
+                let history = match self.unlocked_history() {
+                    Ok(history) => history,
+                    Err(e) => return Reply::error(e),
+                };
+                let Some(entry) = history.history().get(id) else {
+                    return Reply::error(format!("no entry {id} in the history"));
+                };
+                let (payloads, marks) = (entry.payloads().to_vec(), entry.marks().to_vec());
+                // it is recorded again, which moves it to the top
+                self.set(payloads, marks, target, actions);
+                Reply::default()
+            }
+            Request::Subscribe(filter) => {
+                self.clients[index].subscription = Some(filter);
+                Reply::default()
+            }
+            request => match self.handle_history(request) {
+                Ok(reply) => reply,
+                Err(e) => Reply::error(e),
+            },
+        }
+    }
+
+    fn unlocked_history(&self) -> io::Result<&HistoryStore> {
+        let history = self
+            .history
+            .as_ref()
+            .ok_or_else(|| io::Error::other("the daemon keeps no history"))?;
+        if history.is_locked() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "the history is locked",
+            ));
+        }
+        Ok(history)
+    }
+
+    /// the requests only about the history
+    fn handle_history(&mut self, request: Request) -> io::Result<Reply> {
+        if let Request::Lock | Request::Unlock { .. } = request {
+            return self.handle_lock(request);
+        }
+        self.unlocked_history()?;
+        let history = self.history.as_mut().unwrap();
+        let reply = match request {
+            Request::Pin { id, pinned } => {
+                if !history.pin(id, pinned)? {
+                    return Err(io::Error::other(format!("no entry {id} in the history")));
+                }
+                Reply::default()
+            }
+            Request::SetRegister { name, payloads } => {
+                history.set_register(name, payloads.0)?;
+                Reply::default()
+            }
+            Request::Registers => Reply {
+                registers: Some(
+                    history
+                        .history()
+                        .registers()
+                        .map(|(name, register)| (name, HistoryItem::new(register)))
+                        .collect(),
+                ),
+                ..Reply::default()
+            },
+            Request::Register { name } => match history.history().register(name) {
+                Some(register) => Reply {
+                    payloads: Some(MimeMap(register.payloads().to_vec())),
+                    marks: Some(register.marks().to_vec()),
+                    ..Reply::default()
+                },
+                None => Reply::default(),
+            },
+            Request::RemoveRegister { name } => {
+                history.remove_register(name)?;
+                Reply::default()
+            }
+            Request::Export => {
+                let mut lines = Vec::new();
+                let count = history.export(&mut lines)?;
+                Reply {
+                    // the export is JSON, so it is UTF-8
+                    lines: Some(String::from_utf8(lines).map_err(io::Error::other)?),
+                    count: Some(count),
+                    ..Reply::default()
+                }
+            }
+            Request::Import { lines } => Reply {
+                count: Some(history.import(lines.as_bytes())?),
+                ..Reply::default()
+            },
+            _ => unreachable!("not a request about the history"),
+        };
+        Ok(reply)
+    }
+
+    #[cfg(feature = "encryption")]
+    fn handle_lock(&mut self, request: Request) -> io::Result<Reply> {
+        let history = self
+            .history
+            .as_mut()
+            .ok_or_else(|| io::Error::other("the daemon keeps no history"))?;
+        if !history.is_encrypted() {
+            return Err(io::Error::other("the history is not encrypted"));
+        }
+        match request {
+            Request::Lock => history.lock(),
+            Request::Unlock { key } => history.unlock(&key.into())?,
+            _ => unreachable!("not a request to lock the history"),
+        }
+        Ok(Reply::default())
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    fn handle_lock(&mut self, _: Request) -> io::Result<Reply> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the daemon is built without the encryption feature",
+        ))
+    }
+
+    fn set(
+        &mut self,
+        payloads: Payloads,
+        marks: Vec<String>,
+        target: WlCopyTarget,
+        actions: &mut Vec<DaemonAction>,
+    ) {
+        for &useprimary in target.primaries() {
+            self.selection_changed(ClipBoardSelection {
+                primary: useprimary,
+                payloads: payloads.clone(),
+                marks: marks.clone(),
+            });
+        }
+        actions.push(DaemonAction::Set(payloads, target));
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+    use std::time::Duration;
+
+    use super::*;
+    use crate::testutil::TempDir;
+
+    struct TestClient {
+        reader: BufReader<UnixStream>,
+        writer: UnixStream,
+    }
+
+    impl TestClient {
+        fn connect(path: &Path) -> Self {
+            let writer = UnixStream::connect(path).unwrap();
+            writer
+                .set_read_timeout(Some(Duration::from_millis(200)))
+                .unwrap();
+            Self {
+                reader: BufReader::new(writer.try_clone().unwrap()),
+                writer,
+            }
+        }
+
+        fn send(&mut self, server: &mut Server, line: &str) -> Vec<DaemonAction> {
+            self.writer.write_all(line.as_bytes()).unwrap();
+            self.writer.write_all(b"\n").unwrap();
+            server.serve()
+        }
+
+        /// the next line written by the server, None if there is none
+        fn line(&mut self, server: &mut Server) -> Option<serde_json::Value> {
+            server.serve();
+            let mut line = String::new();
+            match self.reader.read_line(&mut line) {
+                Ok(len) if len > 0 => Some(serde_json::from_str(&line).unwrap()),
+                _ => None,
+            }
+        }
+    }
+
+    fn text(primary: bool, text: &str) -> ClipBoardSelection {
+        ClipBoardSelection {
+            primary,
+            payloads: vec![("text/plain".to_string(), text.as_bytes().into())],
+            marks: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn the_socket_needs_the_runtime_dir() {
+        let old = std::env::var_os("XDG_RUNTIME_DIR");
+        std::env::remove_var("XDG_RUNTIME_DIR");
+        let unset = daemon_socket_path().map_err(|e| e.kind());
+        std::env::set_var("XDG_RUNTIME_DIR", "/run/user/1000");
+        let set = daemon_socket_path().unwrap();
+        match old {
+            Some(old) => std::env::set_var("XDG_RUNTIME_DIR", old),
+            None => std::env::remove_var("XDG_RUNTIME_DIR"),
+        }
+        assert_eq!(unset, Err(io::ErrorKind::NotFound));
+        assert_eq!(set, Path::new("/run/user/1000/marine-clipboard.sock"));
+    }
+
+    #[test]
+    fn the_socket_dir_is_private() {
+        let tmp = TempDir::new();
+        let path = tmp.path().join("run/socket");
+        let _server = Server::bind(&path).unwrap();
+        let mode = fs::metadata(tmp.path().join("run")).unwrap().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        check_peer(&UnixStream::connect(&path).unwrap()).unwrap();
+
+        // the others could replace the socket
+        let shared = tmp.path().join("shared");
+        fs::create_dir(&shared).unwrap();
+        fs::set_permissions(&shared, Permissions::from_mode(0o777)).unwrap();
+        let e = Server::bind(&shared.join("socket")).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        // and a link can lead anywhere
+        std::os::unix::fs::symlink(tmp.path().join("run"), tmp.path().join("link")).unwrap();
+        let e = Server::bind(&tmp.path().join("link/other")).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn a_gone_selection_is_not_answered() {
+        let tmp = TempDir::new();
+        let mut server = Server::bind(&tmp.path().join("socket")).unwrap();
+        let mut client = TestClient::connect(&tmp.path().join("socket"));
+        client.send(&mut server, r#"{"command":"subscribe"}"#);
+        assert_eq!(client.line(&mut server).unwrap(), serde_json::json!({}));
+
+        server.selection_changed(text(false, "hello"));
+        let event = client.line(&mut server).unwrap();
+        assert_eq!(event["event"], "changed");
+        client.send(&mut server, r#"{"command":"get"}"#);
+        assert_eq!(
+            client.line(&mut server).unwrap()["payloads"]["text/plain"],
+            "aGVsbG8="
+        );
+
+        // cleared by another client of wayland
+        server.selection_gone(false);
+        assert_eq!(
+            client.line(&mut server).unwrap(),
+            serde_json::json!({"event": "cleared", "primary": false})
+        );
+        client.send(&mut server, r#"{"command":"get"}"#);
+        assert_eq!(client.line(&mut server).unwrap(), serde_json::json!({}));
+        // it is told once
+        server.selection_gone(false);
+        server.selection_gone(true);
+        assert!(client.line(&mut server).is_none());
+    }
+
+    #[test]
+    fn a_slow_subscriber_is_told_what_it_missed() {
+        let tmp = TempDir::new();
+        let mut server = Server::bind(&tmp.path().join("socket")).unwrap();
+        server.backlog = 300;
+        let mut client = TestClient::connect(&tmp.path().join("socket"));
+        client.send(&mut server, r#"{"command":"subscribe","data":true}"#);
+        assert_eq!(client.line(&mut server).unwrap(), serde_json::json!({}));
+
+        // the events are queued until the next serve, more than the backlog holds
+        for number in ["one", "two", "three", "four", "five"] {
+            server.selection_changed(text(false, number));
+        }
+        let mut lines = Vec::new();
+        while let Some(line) = client.line(&mut server) {
+            lines.push(line);
+        }
+        assert_eq!(lines[0]["event"], "lagged");
+        let kept = &lines[1..];
+        assert!(!kept.is_empty() && kept.len() < 5);
+        assert_eq!(lines[0]["missed"], 5 - kept.len());
+        // the newest ones are kept
+        assert_eq!(kept.last().unwrap()["payloads"]["text/plain"], "Zml2ZQ==");
+        assert!(kept.iter().all(|line| line["event"] == "changed"));
+    }
+
+    #[test]
+    fn the_filters_suppress_the_other_events() {
+        let tmp = TempDir::new();
+        let mut server = Server::bind(&tmp.path().join("socket")).unwrap();
+        let mut primary = TestClient::connect(&tmp.path().join("socket"));
+        primary.send(&mut server, r#"{"command":"subscribe","target":"primary"}"#);
+        assert_eq!(primary.line(&mut server).unwrap(), serde_json::json!({}));
+        let mut texts = TestClient::connect(&tmp.path().join("socket"));
+        texts.send(
+            &mut server,
+            r#"{"command":"subscribe","mime_types":["text/*"]}"#,
+        );
+        assert_eq!(texts.line(&mut server).unwrap(), serde_json::json!({}));
+
+        server.selection_changed(text(false, "hello"));
+        assert!(primary.line(&mut server).is_none());
+        assert_eq!(texts.line(&mut server).unwrap()["primary"], false);
+
+        server.selection_changed(ClipBoardSelection {
+            primary: true,
+            payloads: vec![("image/png".to_string(), b"\x89PNG".as_slice().into())],
+            marks: Vec::new(),
+        });
+        let changed = primary.line(&mut server).unwrap();
+        assert_eq!(changed["primary"], true);
+        assert_eq!(changed["mime_types"], serde_json::json!(["image/png"]));
+        assert!(texts.line(&mut server).is_none());
+
+        // only the matching mimetypes are listed
+        server.selection_changed(ClipBoardSelection {
+            primary: false,
+            payloads: vec![
+                ("image/png".to_string(), b"\x89PNG".as_slice().into()),
+                (
+                    "text/uri-list".to_string(),
+                    b"file:///a.png".as_slice().into(),
+                ),
+            ],
+            marks: Vec::new(),
+        });
+        assert!(primary.line(&mut server).is_none());
+        let changed = texts.line(&mut server).unwrap();
+        assert_eq!(changed["mime_types"], serde_json::json!(["text/uri-list"]));
+    }
+
+    #[test]
+    fn the_history_is_served() {
+        let tmp = TempDir::new();
+        let mut server = Server::bind(&tmp.path().join("socket")).unwrap();
+        let mut client = TestClient::connect(&tmp.path().join("socket"));
+        client.send(&mut server, r#"{"command":"registers"}"#);
+        assert_eq!(
+            client.line(&mut server).unwrap()["error"],
+            "the daemon keeps no history"
+        );
+
+        let mut store = HistoryStore::open(tmp.path().join("history")).unwrap();
+        let id = store
+            .push(vec![("text/plain".to_string(), b"hello".to_vec())])
+            .unwrap()
+            .unwrap();
+        server.history = Some(store);
+        client.send(&mut server, &format!(r#"{{"command":"pin","id":{id}}}"#));
+        assert_eq!(client.line(&mut server).unwrap(), serde_json::json!({}));
+        client.send(&mut server, r#"{"command":"pin","id":100}"#);
+        assert!(client.line(&mut server).unwrap()["error"].is_string());
+
+        client.send(
+            &mut server,
+            r#"{"command":"set_register","name":"a","payloads":{"text/plain":"d29ybGQ="}}"#,
+        );
+        assert_eq!(client.line(&mut server).unwrap(), serde_json::json!({}));
+        client.send(&mut server, r#"{"command":"registers"}"#);
+        assert_eq!(
+            client.line(&mut server).unwrap()["registers"]["a"]["text"],
+            "world"
+        );
+        client.send(&mut server, r#"{"command":"register","name":"a"}"#);
+        assert_eq!(
+            client.line(&mut server).unwrap()["payloads"]["text/plain"],
+            "d29ybGQ="
+        );
+
+        // the export is imported in another daemon
+        client.send(&mut server, r#"{"command":"export"}"#);
+        let export = client.line(&mut server).unwrap();
+        assert_eq!(export["count"], 2);
+        let import = serde_json::json!({"command": "import", "lines": export["lines"]});
+        let mut other = Server::bind(&tmp.path().join("other")).unwrap();
+        other.history = Some(HistoryStore::open(tmp.path().join("other-history")).unwrap());
+        let mut client = TestClient::connect(&tmp.path().join("other"));
+        client.send(&mut other, &import.to_string());
+        assert_eq!(client.line(&mut other).unwrap()["count"], 2);
+        client.send(&mut other, r#"{"command":"history","query":""}"#);
+        let entries = client.line(&mut other).unwrap();
+        assert_eq!(entries["entries"][0]["text"], "hello");
+        assert_eq!(entries["entries"][0]["pinned"], true);
+
+        client.send(&mut other, r#"{"command":"remove_register","name":"a"}"#);
+        assert_eq!(client.line(&mut other).unwrap(), serde_json::json!({}));
+        client.send(&mut other, r#"{"command":"register","name":"a"}"#);
+        assert_eq!(client.line(&mut other).unwrap(), serde_json::json!({}));
+    }
+
+    #[test]
+    fn a_client_not_reading_is_dropped() {
+        let tmp = TempDir::new();
+        let mut server = Server::bind(&tmp.path().join("socket")).unwrap();
+        server.max_replies = 1 << 10;
+        server.selection_changed(text(false, &"a".repeat(600)));
+        let mut client = TestClient::connect(&tmp.path().join("socket"));
+
+        // one reply alone is larger than the cap, but it is sent
+        client.send(&mut server, r#"{"command":"get"}"#);
+        assert!(client.line(&mut server).unwrap()["payloads"].is_object());
+        assert_eq!(server.clients.len(), 1);
+
+        // the replies are queued, as the client only writes
+        client
+            .writer
+            .set_write_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let mut sent = 0;
+        while server.clients.len() == 1 {
+            let requests = r#"{"command":"get"}"#.to_string() + "\n";
+            if client
+                .writer
+                .write_all(requests.repeat(100).as_bytes())
+                .is_err()
+            {
+                break;
+            }
+            server.serve();
+            sent += 1;
+            assert!(sent < 10_000, "the client is never dropped");
+        }
+        assert!(server.clients.is_empty());
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn a_locked_history_is_not_served() {
+        let tmp = TempDir::new();
+        let mut store = HistoryStore::open(tmp.path().join("history")).unwrap();
+        store
+            .push(vec![("text/plain".to_string(), b"hello".to_vec())])
+            .unwrap();
+        store
+            .encrypt(&crate::HistoryKey::Passphrase(
+                "correct horse".to_string().into(),
+            ))
+            .unwrap();
+        drop(store);
+        let mut server = Server::bind(&tmp.path().join("socket")).unwrap();
+        server.history = Some(HistoryStore::open(tmp.path().join("history")).unwrap());
+        let mut client = TestClient::connect(&tmp.path().join("socket"));
+
+        client.send(&mut server, r#"{"command":"history"}"#);
+        assert_eq!(
+            client.line(&mut server).unwrap()["error"],
+            "the history is locked"
+        );
+        client.send(&mut server, r#"{"command":"registers"}"#);
+        assert_eq!(
+            client.line(&mut server).unwrap()["error"],
+            "the history is locked"
+        );
+        // the selections are not recorded meanwhile
+        server.selection_changed(text(false, "while locked"));
+        let wrong = r#"{"command":"unlock","key":{"passphrase":"battery staple"}}"#;
+        client.send(&mut server, wrong);
+        assert!(client.line(&mut server).unwrap()["error"].is_string());
+
+        let right = r#"{"command":"unlock","key":{"passphrase":"correct horse"}}"#;
+        client.send(&mut server, right);
+        assert_eq!(client.line(&mut server).unwrap(), serde_json::json!({}));
+        client.send(&mut server, r#"{"command":"history"}"#);
+        let entries = client.line(&mut server).unwrap()["entries"].clone();
+        assert_eq!(entries.as_array().unwrap().len(), 1);
+        assert_eq!(entries[0]["text"], "hello");
+
+        client.send(&mut server, r#"{"command":"lock"}"#);
+        assert_eq!(client.line(&mut server).unwrap(), serde_json::json!({}));
+        client.send(&mut server, r#"{"command":"select","id":1}"#);
+        assert_eq!(
+            client.line(&mut server).unwrap()["error"],
+            "the history is locked"
+        );
+    }
+}
@@ -0,0 +1,388 @@
+//! the daemon on the session bus, see [crate::ClipboardDBusService]
+//! it is a bridge, the method calls are sent to the daemon by a [ClipboardClient], and the events
+//! of the daemon are emitted as signals
+//! every call is sent from a thread, with a client of its own, so a slow one does not hold the
+//! others
+//!
+//! the interface `io.github.Decodetalkers.MarineClipboard` at
+//! `/io/github/Decodetalkers/MarineClipboard`, like the socket protocol, see
+//! [crate::WlClipboardDaemonStream]
+//! ```text
+//! Get(b primary, s mime_type) -> a(say) payloads
+//! Set(a(say) payloads, s target)
+//! Clear(s target)
+//! History(s query, u limit) -> a(ttbasass) entries
+//! Select(t id, s target)
+//! signal Changed(b primary, as mime_types, as marks)
+//! signal Cleared(b primary)
+//! ```
+//! * the payloads are the mimetypes with their data, in the order they are offered
+//! * Get returns all the payloads if mime_type is empty, and nothing if the selection is empty
+//! * target is `clipboard`, `primary` or `both`
+//! * History returns the id, the time in milliseconds since the epoch, if it is pinned, the
+//!   marks, the mimetypes and the text of the entries, the text is empty if it is not text, a
+//!   limit of 0 returns all the matches
+//!
+//! like
+//! ```sh
+//! busctl --user call io.github.Decodetalkers.MarineClipboard \
+//!     /io/github/Decodetalkers/MarineClipboard io.github.Decodetalkers.MarineClipboard \
+//!     Set 'a(say)s' 1 text/plain 5 104 101 108 108 111 clipboard
+//! ```
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use zbus::blocking::connection::Builder;
+use zbus::blocking::Connection;
+use zbus::fdo;
+use zbus::object_server::SignalEmitter;
+
+use crate::client::{ClipboardClient, ClipboardSubscription};
+use crate::daemon::{target_from_name, ClipboardEvent};
+use crate::store::to_millis;
+use crate::WlCopyTarget;
+
+const BUS_NAME: &str = "io.github.Decodetalkers.MarineClipboard";
+const OBJECT_PATH: &str = "/io/github/Decodetalkers/MarineClipboard";
+
+/// id, time, pinned, marks, mime_types and text
+type DBusHistoryItem = (u64, u64, bool, Vec<String>, Vec<String>, String);
+
+fn dbus_error(e: zbus::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+fn failed(e: io::Error) -> fdo::Error {
+    fdo::Error::Failed(e.to_string())
+}
+
+fn target(name: &str) -> fdo::Result<WlCopyTarget> {
+    target_from_name(name).ok_or_else(|| {
+        fdo::Error::InvalidArgs(format!(
+            "{name} is not a target, they are clipboard, primary and both"
+        ))
+    })
+}
+
+struct ClipboardInterface {
+    socket: PathBuf,
+    /// the clients not used by a call
+    idle: Arc<Mutex<Vec<ClipboardClient>>>,
+}
+
+impl ClipboardInterface {
+    /// send request from a thread with an idle client, or a new one if they are all used
+    async fn call<R, F>(&self, request: F) -> fdo::Result<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut ClipboardClient) -> io::Result<R> + Send + 'static,
+    {
+        let socket = self.socket.clone();
+        let idle = self.idle.clone();
+        blocking::unblock(move || {
+            // a panic in a call leaves the idle clients as they were
+            let client = idle.lock().unwrap_or_else(|e| e.into_inner()).pop();
+            let mut client = match client {
+                Some(client) => client,
+                None => ClipboardClient::connect(&socket)?,
+            };
+            let reply = request(&mut client)?;
+            // a client that failed may be out of step with the daemon, it is dropped
+            idle.lock().unwrap_or_else(|e| e.into_inner()).push(client);
+            Ok(reply)
+        })
+        .await
+        .map_err(failed)
+    }
+}
+
+#[zbus::interface(name = "io.github.Decodetalkers.MarineClipboard")]
+impl ClipboardInterface {
+    #[zbus(out_args("payloads"))]
+    async fn get(&self, primary: bool, mime_type: String) -> fdo::Result<Vec<(String, Vec<u8>)>> {
+        let mime_type = (!mime_type.is_empty()).then_some(mime_type);
+        let payloads = self
+            .call(move |client| client.get(primary, mime_type.as_deref()))
+            .await?
+            .unwrap_or_default();
+        Ok(payloads
+            .into_iter()
+            .map(|(mimetype, data)| (mimetype, data.to_vec()))
+            .collect())
+    }
+
+    async fn set(&self, payloads: Vec<(String, Vec<u8>)>, target: &str) -> fdo::Result<()> {
+        let target = self::target(target)?;
+        self.call(move |client| client.set(payloads, target)).await
+    }
+
+    async fn clear(&self, target: &str) -> fdo::Result<()> {
+        let target = self::target(target)?;
+        self.call(move |client| client.clear(target)).await
+    }
+
+    #[zbus(out_args("entries"))]
+    async fn history(&self, query: String, limit: u32) -> fdo::Result<Vec<DBusHistoryItem>> {
+        let limit = (limit > 0).then_some(limit as usize);
+        let items = self
+            .call(move |client| client.history(&query, limit))
+            .await?;
+        Ok(items
+            .into_iter()
+            .map(|item| {
+                (
+                    item.id,
+                    to_millis(item.time),
+                    item.pinned,
+                    item.marks,
+                    item.mime_types,
+                    item.text.unwrap_or_default(),
+                )
+            })
+            .collect())
+    }
+
+    async fn select(&self, id: u64, target: &str) -> fdo::Result<()> {
+        let target = self::target(target)?;
+        self.call(move |client| client.select(id, target)).await
+    }
+
+    #[zbus(signal)]
+    async fn changed(
+        emitter: &SignalEmitter<'_>,
+        primary: bool,
+        mime_types: Vec<String>,
+        marks: Vec<String>,
+    ) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn cleared(emitter: &SignalEmitter<'_>, primary: bool) -> zbus::Result<()>;
+}
+
+/// the daemon on the session bus, for the desktop tools and the scripts talking D-Bus
+/// the calls are sent to the daemon, so it must be running, see [crate::WlClipboardDaemonStream]
+/// ``` rust, no_run
+/// use wayland_clipboard_listener::{daemon_socket_path, ClipboardDBusService};
+/// let service = ClipboardDBusService::init(daemon_socket_path().unwrap()).unwrap();
+/// service.run().unwrap();
+///```
+pub struct ClipboardDBusService {
+    connection: Connection,
+    events: ClipboardSubscription,
+}
+
+impl ClipboardDBusService {
+    /// connect to the daemon listening on socket, and serve it on the session bus
+    pub fn init(socket: impl AsRef<Path>) -> io::Result<Self> {
+        Self::init_with(socket.as_ref(), Builder::session())
+    }
+
+    /// like init, but serve it on the bus at address, like the one printed by
+    /// `dbus-daemon --print-address`
+    pub fn init_on_bus(socket: impl AsRef<Path>, address: &str) -> io::Result<Self> {
+        Self::init_with(socket.as_ref(), Builder::address(address))
+    }
+
+    fn init_with(socket: &Path, builder: zbus::Result<Builder>) -> io::Result<Self> {
+        let client = ClipboardClient::connect(socket)?;
+        let events = ClipboardClient::connect(socket)?.subscribe()?;
+        let interface = ClipboardInterface {
+            socket: socket.to_path_buf(),
+            idle: Arc::new(Mutex::new(vec![client])),
+        };
+        let connection = builder
+            .and_then(|builder| builder.name(BUS_NAME)?.serve_at(OBJECT_PATH, interface))
+            .and_then(Builder::build)
+            .map_err(dbus_error)?;
+        Ok(Self { connection, events })
+    }
+
+    /// emit the changes of the selections as signals, the calls are served meanwhile
+    /// it returns when the daemon exits, or when there is an error
+    pub fn run(self) -> io::Result<()> {
+        let emitter =
+            SignalEmitter::new(self.connection.inner(), OBJECT_PATH).map_err(dbus_error)?;
+        for event in self.events {
+            let emitted = match event? {
+                ClipboardEvent::Changed {
+                    primary,
+                    mime_types,
+                    marks,
+                    ..
+                } => zbus::block_on(ClipboardInterface::changed(
+                    &emitter, primary, mime_types, marks,
+                )),
+                ClipboardEvent::Cleared { primary } => {
+                    zbus::block_on(ClipboardInterface::cleared(&emitter, primary))
+                }
+                // the events after it are the latest changes, signaling them is enough
+                ClipboardEvent::Lagged { .. } => Ok(()),
+            };
+            emitted.map_err(dbus_error)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::{self, Receiver};
+    use std::thread;
+    use std::time::Duration;
+
+    use zbus::blocking::{MessageIterator, Proxy};
+
+    use super::*;
+    use crate::daemon::Server;
+    use crate::store::HistoryStore;
+    use crate::testutil::TempDir;
+
+    /// a private session bus, killed when dropped
+    struct Bus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl Bus {
+        /// None if the tests needing dbus-daemon are skipped by MARINE_SKIP_DBUS_TESTS, it panics
+        /// if dbus-daemon can not be run otherwise
+        fn start() -> Option<Self> {
+            if std::env::var_os("MARINE_SKIP_DBUS_TESTS").is_some() {
+                eprintln!("MARINE_SKIP_DBUS_TESTS is set, skipped");
+                return None;
+            }
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--print-address", "--nofork"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .unwrap_or_else(|e| {
+                    panic!(
+                        "cannot run dbus-daemon: {e}, install it, or set MARINE_SKIP_DBUS_TESTS=1 \
+                         to skip the tests needing it"
+                    )
+                });
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take().unwrap())
+                .read_line(&mut address)
+                .unwrap();
+            Some(Self {
+                daemon,
+                address: address.trim().to_string(),
+            })
+        }
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    /// the signals of the interface, received in a thread, so they can be waited with a timeout
+    fn signals(connection: &Connection) -> Receiver<zbus::Message> {
+        let rule = zbus::MatchRule::builder()
+            .msg_type(zbus::message::Type::Signal)
+            .interface(BUS_NAME)
+            .unwrap()
+            .build();
+        let signals = MessageIterator::for_match_rule(rule, connection, None).unwrap();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for message in signals {
+                if sender.send(message.unwrap()).is_err() {
+                    break;
+                }
+            }
+        });
+        receiver
+    }
+
+    /// the next signal of the interface, its name and body, it panics if there is none in 5
+    /// seconds
+    fn next_signal<B>(signals: &Receiver<zbus::Message>) -> (String, B)
+    where
+        B: serde::de::DeserializeOwned + zbus::zvariant::Type,
+    {
+        let message = signals
+            .recv_timeout(Duration::from_secs(5))
+            .expect("no signal in 5 seconds");
+        let header = message.header();
+        let member = header.member().unwrap().to_string();
+        (member, message.body().deserialize().unwrap())
+    }
+
+    #[test]
+    fn the_daemon_is_served_on_the_bus() {
+        let Some(bus) = Bus::start() else {
+            return;
+        };
+        let tmp = TempDir::new();
+        let socket = tmp.path().join("socket");
+        let mut server = Server::bind(&socket).unwrap();
+        server.history = Some(HistoryStore::open(tmp.path().join("history")).unwrap());
+        let stop = Arc::new(AtomicBool::new(false));
+        let serving = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                // there is no wayland side, the daemon sees its own copies only
+                while !stop.load(Ordering::Relaxed) {
+                    server.serve();
+                    thread::sleep(Duration::from_millis(5));
+                }
+            })
+        };
+
+        let service = ClipboardDBusService::init_on_bus(&socket, &bus.address).unwrap();
+        thread::spawn(move || service.run());
+
+        let connection = Builder::address(bus.address.as_str())
+            .unwrap()
+            .build()
+            .unwrap();
+        let proxy = Proxy::new(&connection, BUS_NAME, OBJECT_PATH, BUS_NAME).unwrap();
+        let signals = signals(&connection);
+
+        let payloads = vec![("text/plain".to_string(), b"hello".to_vec())];
+        proxy
+            .call::<_, _, ()>("Set", &(payloads.clone(), "clipboard"))
+            .unwrap();
+        let (member, changed) = next_signal::<(bool, Vec<String>, Vec<String>)>(&signals);
+        assert_eq!(member, "Changed");
+        assert_eq!(changed, (false, vec!["text/plain".to_string()], Vec::new()));
+
+        let got: Vec<(String, Vec<u8>)> = proxy.call("Get", &(false, "")).unwrap();
+        assert_eq!(got, payloads);
+        let e = proxy
+            .call::<_, _, Vec<(String, Vec<u8>)>>("Get", &(false, "image/png"))
+            .err()
+            .unwrap();
+        assert!(matches!(e, zbus::Error::MethodError(..)));
+
+        let entries: Vec<DBusHistoryItem> = proxy.call("History", &("hel", 0u32)).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].4, vec!["text/plain".to_string()]);
+        assert_eq!(entries[0].5, "hello");
+
+        proxy.call::<_, _, ()>("Clear", &("clipboard",)).unwrap();
+        let (member, cleared) = next_signal::<(bool,)>(&signals);
+        assert_eq!((member.as_str(), cleared), ("Cleared", (false,)));
+        let got: Vec<(String, Vec<u8>)> = proxy.call("Get", &(false, "")).unwrap();
+        assert!(got.is_empty());
+
+        let e = proxy
+            .call::<_, _, ()>("Clear", &("nowhere",))
+            .err()
+            .unwrap();
+        assert!(matches!(e, zbus::Error::MethodError(..)));
+
+        stop.store(true, Ordering::Relaxed);
+        serving.join().unwrap();
+    }
+}
@@ -1,7 +1,6 @@
 use super::WlClipboardListenerStreamWlr;
 
 use std::fs::File;
-use std::io::Write;
 use std::os::fd::AsFd;
 
 use wayland_client::{
@@ -17,7 +16,7 @@ use wayland_protocols_wlr::data_control::v1::client::{
 };
 
 use crate::{
-    constvar::{IMAGE, TEXT},
+    constvar::{PASSWORD_HINT, TEXT},
     WlListenType,
 };
 
@@ -101,7 +100,7 @@ impl Dispatch<zwlr_data_control_device_v1::ZwlrDataControlDeviceV1, ()>
                     .entry(id.id().protocol_id())
                     .or_default();
                 if let WlListenType::ListenOnSelect = state.listentype {
-                    if state.copy_data.is_some() {
+                    if state.copy_source.is_some() {
                         return;
                     }
                     let (read, write) = pipe().unwrap();
@@ -120,11 +119,17 @@ impl Dispatch<zwlr_data_control_device_v1::ZwlrDataControlDeviceV1, ()>
             }
             zwlr_data_control_device_v1::Event::PrimarySelection { id } => {
                 state.replace_primary_selection_offer(id);
+                state.selection_replaced(true);
             }
             zwlr_data_control_device_v1::Event::Selection { id } => {
                 state.replace_selection_offer(id.clone());
+                state.selection_replaced(false);
+                // the selections are received by the keep loop
+                if state.keeping() {
+                    return;
+                }
                 // if is copying, not run this
-                if state.copy_data.is_some() {
+                if state.copy_source.is_some() {
                     return;
                 }
                 let Some(offer) = id else {
@@ -148,6 +153,8 @@ impl Dispatch<zwlr_data_control_device_v1::ZwlrDataControlDeviceV1, ()>
                     } else {
                         select_mimetype(state)
                     };
+                    #[cfg(feature = "image-convert")]
+                    let mimetype = state.prefer_image_format(mimetype);
                     state.current_type = Some(mimetype.clone());
                     let (read, write) = pipe().unwrap();
                     offer.receive(mimetype, write.as_fd());
@@ -170,7 +177,7 @@ impl Dispatch<zwlr_data_control_source_v1::ZwlrDataControlSourceV1, ()>
 {
     fn event(
         state: &mut Self,
-        _proxy: &zwlr_data_control_source_v1::ZwlrDataControlSourceV1,
+        proxy: &zwlr_data_control_source_v1::ZwlrDataControlSourceV1,
         event: <zwlr_data_control_source_v1::ZwlrDataControlSourceV1 as Proxy>::Event,
         _data: &(),
         _conn: &Connection,
@@ -178,16 +185,31 @@ impl Dispatch<zwlr_data_control_source_v1::ZwlrDataControlSourceV1, ()>
     ) {
         match event {
             zwlr_data_control_source_v1::Event::Send { fd, mime_type } => {
-                let Some(data) = state.copy_data.as_ref() else {
+                // the limit is reached, the selection is going to be cleared
+                if state.paste_limit_reached() {
+                    return;
+                }
+                let Some(source) = state.source_data(proxy) else {
                     return;
                 };
-                // FIXME: how to handle the mime_type?
-                if mime_type == TEXT || mime_type == IMAGE {
-                    let mut f = File::from(fd);
-                    f.write_all(&data.to_vec()).unwrap();
+                // it is written in the copy loop when the paste side is ready
+                // only the pastes really served count toward the limit
+                match source.prepare_send(&mime_type, File::from(fd)) {
+                    Ok(Some(write)) => {
+                        state.copy_writes.push(write);
+                        // a clipboard manager checking the hint is not a paste
+                        if mime_type != PASSWORD_HINT {
+                            state.copy_pasted += 1;
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => log::warn!("failed to send {mime_type}: {e}"),
                 }
             }
-            zwlr_data_control_source_v1::Event::Cancelled => state.copy_cancelled = true,
+            zwlr_data_control_source_v1::Event::Cancelled => {
+                state.copy_sources.retain(|(source, _)| source != proxy);
+                proxy.destroy();
+            }
             _ => {
                 eprintln!("unhandled event: {event:?}");
             }
@@ -0,0 +1,242 @@
+//! dispatch with a timeout
+//! `blocking_dispatch` will only wake up when the compositor sends something, but when copying we
+//! sometimes need to wake up by ourselves, for example when the clear timeout is reached, or the
+//! copy is revoked from another thread, or a slow paste side is ready to take more data. So here
+//! we poll the wayland connection fd with a timeout together with other fds, and then dispatch
+//! what we read
+//!
+//! the selections of others are received in a thread for the same reason, see [Receiving]
+
+use std::io::{self, Read};
+use std::os::fd::{AsFd, BorrowedFd};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::time::{Duration, Instant};
+
+use nix::{
+    errno::Errno,
+    fcntl::{fcntl, FcntlArg, OFlag},
+    poll::{poll, PollFd, PollFlags, PollTimeout},
+};
+use os_pipe::PipeReader;
+use wayland_client::{backend::WaylandError, EventQueue};
+
+use crate::constvar::{RECEIVE_LIMIT, RECEIVE_TIMEOUT};
+use crate::copy::Payloads;
+use crate::mime::TEXT_MIME_TYPES;
+use crate::WlClipboardListenerError;
+
+fn queue_error(e: impl ToString) -> WlClipboardListenerError {
+    WlClipboardListenerError::QueueError(e.to_string())
+}
+
+/// what happened to a wakeup pipe
+pub(crate) enum Wakeup {
+    Nothing,
+    Woken,
+    Closed,
+}
+
+/// check the wakeup pipe without blocking
+pub(crate) fn check_wakeup(reader: &mut PipeReader) -> Wakeup {
+    let mut fds = [PollFd::new(reader.as_fd(), PollFlags::POLLIN)];
+    if !matches!(poll(&mut fds, PollTimeout::ZERO), Ok(ready) if ready > 0) {
+        return Wakeup::Nothing;
+    }
+    let mut buf = [0; 1];
+    match reader.read(&mut buf) {
+        Ok(0) | Err(_) => Wakeup::Closed,
+        Ok(_) => Wakeup::Woken,
+    }
+}
+
+/// read reader to its end like `read_to_end`, but give up if it is not finished by deadline, or
+/// it is longer than limit, None is returned then
+pub(crate) fn read_to_end_until(
+    reader: &mut PipeReader,
+    deadline: Instant,
+    limit: usize,
+) -> io::Result<Option<Vec<u8>>> {
+    fcntl(&*reader, FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+    let mut data = Vec::new();
+    let mut buf = vec![0; 64 << 10];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => return Ok(Some(data)),
+            Ok(len) if data.len() + len > limit => return Ok(None),
+            Ok(len) => data.extend_from_slice(&buf[..len]),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return Ok(None);
+                }
+                let timeout = PollTimeout::try_from(left).unwrap_or(PollTimeout::MAX);
+                match poll(
+                    &mut [PollFd::new(reader.as_fd(), PollFlags::POLLIN)],
+                    timeout,
+                ) {
+                    Ok(_) | Err(Errno::EINTR) => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// a selection received in a thread, so the event loop keeps serving the pastes and the clients
+/// while the owner writes it, the owner may even be waiting for one of them
+pub(crate) struct Receiving {
+    result: Receiver<Option<Payloads>>,
+    /// closed by the thread when the result is sent
+    done: PipeReader,
+}
+
+impl Receiving {
+    /// read the pipes one by one, the data of the first text is also put under aliases
+    /// the result is None if it is not received in RECEIVE_TIMEOUT, or it is larger than
+    /// RECEIVE_LIMIT
+    pub(crate) fn start(
+        pipes: Vec<(String, PipeReader)>,
+        aliases: Vec<String>,
+    ) -> io::Result<Self> {
+        let (done, notify) = os_pipe::pipe()?;
+        let (sender, result) = mpsc::channel();
+        std::thread::spawn(move || {
+            let _ = sender.send(receive(pipes, aliases));
+            drop(notify);
+        });
+        Ok(Self { result, done })
+    }
+
+    pub(crate) fn poll_fd(&self) -> (BorrowedFd<'_>, PollFlags) {
+        (self.done.as_fd(), PollFlags::POLLIN)
+    }
+
+    /// the result if the thread is finished
+    pub(crate) fn try_result(&self) -> Option<Option<Payloads>> {
+        match self.result.try_recv() {
+            Ok(payloads) => Some(payloads),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(None),
+        }
+    }
+}
+
+fn receive(pipes: Vec<(String, PipeReader)>, aliases: Vec<String>) -> Option<Payloads> {
+    let deadline = Instant::now() + RECEIVE_TIMEOUT;
+    let mut left = RECEIVE_LIMIT;
+    let mut payloads: Payloads = Vec::new();
+    for (mime_type, mut read) in pipes {
+        match read_to_end_until(&mut read, deadline, left) {
+            Ok(Some(data)) => {
+                left -= data.len();
+                payloads.push((mime_type, data.into()));
+            }
+            Ok(None) => {
+                log::warn!(
+                    "the selection is not received in {RECEIVE_TIMEOUT:?}, or larger than \
+                     {RECEIVE_LIMIT} bytes, skip it"
+                );
+                return None;
+            }
+            Err(e) => log::warn!("failed to receive {mime_type}: {e}"),
+        }
+    }
+    let text = payloads
+        .iter()
+        .find(|(mimetype, _)| TEXT_MIME_TYPES.contains(&mimetype.as_str()))
+        .map(|(_, data)| data.clone());
+    if let Some(text) = text {
+        payloads.extend(aliases.into_iter().map(|alias| (alias, text.clone())));
+    }
+    Some(payloads)
+}
+
+/// dispatch the queue, wait for at most `timeout` for new events
+/// if timeout is None, it acts like `blocking_dispatch`
+/// it also returns when one of the extra fds is ready for its flags, like a wakeup pipe to read or
+/// a paste pipe to write, the caller should check them
+/// return the number of dispatched events, 0 means timeout or woken up
+pub(crate) fn dispatch_timeout<State>(
+    queue: &mut EventQueue<State>,
+    state: &mut State,
+    timeout: Option<Duration>,
+    extra: &[(BorrowedFd, PollFlags)],
+) -> Result<usize, WlClipboardListenerError> {
+    let dispatched = queue.dispatch_pending(state).map_err(queue_error)?;
+    if dispatched > 0 {
+        return Ok(dispatched);
+    }
+
+    queue.flush().map_err(queue_error)?;
+
+    // prepare_read returns None if there are events need to be dispatched
+    let Some(guard) = queue.prepare_read() else {
+        return queue.dispatch_pending(state).map_err(queue_error);
+    };
+
+    let timeout = match timeout {
+        Some(timeout) => PollTimeout::try_from(timeout).unwrap_or(PollTimeout::MAX),
+        None => PollTimeout::NONE,
+    };
+    let ready = {
+        let mut fds = vec![PollFd::new(guard.connection_fd(), PollFlags::POLLIN)];
+        fds.extend(extra.iter().map(|(fd, flags)| PollFd::new(*fd, *flags)));
+        match poll(&mut fds, timeout) {
+            Ok(_) => fds[0].any().unwrap_or_default(),
+            Err(Errno::EINTR) => false,
+            Err(e) => return Err(queue_error(e)),
+        }
+    };
+    if !ready {
+        return Ok(0);
+    }
+
+    match guard.read() {
+        Ok(_) => {}
+        Err(WaylandError::Io(e)) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+        Err(e) => return Err(queue_error(e)),
+    }
+    queue.dispatch_pending(state).map_err(queue_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn receive_in_a_thread() {
+        let (html, mut html_writer) = os_pipe::pipe().unwrap();
+        let (text, mut text_writer) = os_pipe::pipe().unwrap();
+        let receiving = Receiving::start(
+            vec![("text/html".to_string(), html), ("TEXT".to_string(), text)],
+            vec!["text/plain".to_string()],
+        )
+        .unwrap();
+        assert!(receiving.try_result().is_none());
+        html_writer.write_all(b"<b>hi</b>").unwrap();
+        drop(html_writer);
+        text_writer.write_all(b"hi").unwrap();
+        drop(text_writer);
+
+        // the done pipe wakes the event loop up
+        let mut fds = [PollFd::new(receiving.done.as_fd(), PollFlags::POLLIN)];
+        poll(&mut fds, PollTimeout::from(5000u16)).unwrap();
+        let payloads = receiving.try_result().unwrap().unwrap();
+        let payloads: Vec<(&str, &[u8])> = payloads
+            .iter()
+            .map(|(mimetype, data)| (mimetype.as_str(), &data[..]))
+            .collect();
+        assert_eq!(
+            payloads,
+            [
+                ("text/html", b"<b>hi</b>".as_slice()),
+                ("TEXT", b"hi"),
+                ("text/plain", b"hi")
+            ]
+        );
+    }
+}
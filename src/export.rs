@@ -0,0 +1,274 @@
+//! export and import the history
+//! the history is written as JSON Lines, an entry per line from the oldest to the newest, and
+//! then the registers, like
+//! ```json
+//! {"time":1700000000000,"payloads":{"text/plain;charset=utf-8":"aGVsbG8=","text/plain":"aGVsbG8="}}
+//! {"time":1700000001000,"pinned":true,"marks":["tickets"],"payloads":{"image/png":"iVBORw0KGgo..."}}
+//! {"register":"a","time":1700000002000,"payloads":{"text/plain":"d29ybGQ="}}
+//! ```
+//! the data of every mimetype is in base64, in the order the mimetypes are offered, and time is
+//! the last time it was copied, in milliseconds since the epoch
+
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::de::{MapAccess, Visitor};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::cliphist::read_cliphist;
+use crate::copy::Payloads;
+use crate::history::{ClipboardHistory, HistoryEntry};
+use crate::mime::detect_mime_types;
+use crate::store::{from_millis, to_millis};
+
+#[derive(Serialize, Deserialize)]
+struct ExportedEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    register: Option<char>,
+    time: u64,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pinned: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    marks: Vec<String>,
+    payloads: MimeMap,
+}
+
+impl ExportedEntry {
+    fn new(register: Option<char>, entry: &HistoryEntry) -> Self {
+        Self {
+            register,
+            time: to_millis(entry.timestamp()),
+            pinned: entry.pinned(),
+            marks: entry.marks().to_vec(),
+            payloads: MimeMap(entry.payloads().to_vec()),
+        }
+    }
+}
+
+/// the payloads, as a map of the mimetypes to the data in base64, in order
+pub(crate) struct MimeMap(pub(crate) Payloads);
+
+impl Serialize for MimeMap {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (mimetype, data) in &self.0 {
+            map.serialize_entry(mimetype, &STANDARD.encode(data))?;
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for MimeMap {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct MimeMapVisitor;
+
+        impl<'de> Visitor<'de> for MimeMapVisitor {
+            type Value = MimeMap;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a map of mimetypes to data in base64")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<MimeMap, A::Error> {
+                let mut payloads = Vec::new();
+                while let Some((mimetype, data)) = map.next_entry::<String, String>()? {
+                    let data = STANDARD.decode(data).map_err(|e| {
+                        serde::de::Error::custom(format!("bad base64 of {mimetype}: {e}"))
+                    })?;
+                    payloads.push((mimetype, data.into()));
+                }
+                Ok(MimeMap(payloads))
+            }
+        }
+
+        deserializer.deserialize_map(MimeMapVisitor)
+    }
+}
+
+/// write the entries and the registers of history, return the count of lines
+pub(crate) fn export(history: &ClipboardHistory, mut writer: impl Write) -> io::Result<usize> {
+    let entries: Vec<&HistoryEntry> = history.entries().collect();
+    let lines = entries
+        .into_iter()
+        .rev()
+        .map(|entry| ExportedEntry::new(None, entry))
+        .chain(
+            history
+                .registers()
+                .map(|(name, register)| ExportedEntry::new(Some(name), register)),
+        );
+    let mut count = 0;
+    for line in lines {
+        serde_json::to_writer(&mut writer, &line).map_err(io::Error::other)?;
+        writer.write_all(b"\n")?;
+        count += 1;
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+/// read an export into history, nothing is imported if a line is bad
+/// return the count of entries and registers imported
+pub(crate) fn import(history: &mut ClipboardHistory, reader: impl BufRead) -> io::Result<usize> {
+    let mut lines = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line: ExportedEntry = serde_json::from_str(&line).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: {e}", number + 1),
+            )
+        })?;
+        lines.push(line);
+    }
+    let mut count = 0;
+    for line in lines {
+        let time = from_millis(line.time);
+        let imported = match line.register {
+            Some(name) => history.insert_register(name, line.payloads.0, time),
+            None => history.insert_entry(line.payloads.0, time, line.marks, line.pinned),
+        };
+        count += usize::from(imported);
+    }
+    Ok(count)
+}
+
+/// read the cliphist database at path into history, the mimetypes are guessed from the data, as
+/// cliphist does not keep them
+/// return the count of entries imported
+pub(crate) fn import_cliphist(history: &mut ClipboardHistory, path: &Path) -> io::Result<usize> {
+    let now = SystemTime::now();
+    let mut count = 0;
+    for data in read_cliphist(path)? {
+        if data.is_empty() {
+            continue;
+        }
+        let data: Arc<[u8]> = data.into();
+        let payloads = detect_mime_types(&data)
+            .into_iter()
+            .map(|mimetype| (mimetype.to_string(), data.clone()))
+            .collect();
+        count += usize::from(history.insert_entry(payloads, now, Vec::new(), false));
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn text(text: &str) -> Payloads {
+        vec![
+            (
+                "text/plain;charset=utf-8".to_string(),
+                text.as_bytes().into(),
+            ),
+            ("text/plain".to_string(), text.as_bytes().into()),
+        ]
+    }
+
+    /// what is kept of an entry, its id is not
+    fn summary(entry: &HistoryEntry) -> (Payloads, SystemTime, Vec<String>, bool) {
+        (
+            entry.payloads().to_vec(),
+            entry.timestamp(),
+            entry.marks().to_vec(),
+            entry.pinned(),
+        )
+    }
+
+    #[test]
+    fn export_and_import_again() {
+        let mut history = ClipboardHistory::new(10);
+        history.insert_entry(text("hello"), from_millis(1_000), Vec::new(), false);
+        history.insert_entry(
+            vec![(
+                "image/png".to_string(),
+                b"\x89PNG\r\n\x1a\n".as_slice().into(),
+            )],
+            from_millis(2_000),
+            Vec::new(),
+            true,
+        );
+        history.insert_entry(
+            text("see TICKET-42"),
+            from_millis(3_000),
+            vec!["tickets".to_string()],
+            true,
+        );
+        history.insert_register('a', text("world"), from_millis(4_000));
+        history.insert_register('z', text("zzz"), from_millis(5_000));
+
+        let mut exported = Vec::new();
+        assert_eq!(history.export(&mut exported).unwrap(), 5);
+        let mut imported = ClipboardHistory::new(10);
+        assert_eq!(imported.import(exported.as_slice()).unwrap(), 5);
+
+        let entries: Vec<_> = history.entries().map(summary).collect();
+        let imported_entries: Vec<_> = imported.entries().map(summary).collect();
+        assert_eq!(imported_entries, entries);
+        let registers: Vec<_> = history
+            .registers()
+            .map(|(name, register)| (name, summary(register)))
+            .collect();
+        let imported_registers: Vec<_> = imported
+            .registers()
+            .map(|(name, register)| (name, summary(register)))
+            .collect();
+        assert_eq!(imported_registers, registers);
+
+        // the entries already there are skipped, the registers are replaced
+        assert_eq!(imported.import(exported.as_slice()).unwrap(), 2);
+        assert_eq!(imported.len(), 3);
+    }
+
+    #[test]
+    fn nothing_is_imported_from_a_bad_export() {
+        let mut history = ClipboardHistory::new(10);
+        let export = concat!(
+            r#"{"time":1000,"payloads":{"text/plain":"aGVsbG8="}}"#,
+            "\n",
+            r#"{"time":2000,"payloads":{"text/plain":"not base64!"}}"#,
+            "\n",
+        );
+        let e = history.import(export.as_bytes()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().starts_with("line 2:"));
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn import_cliphist_guesses_the_mimetypes() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data/cliphist-branch.db");
+        let mut history = ClipboardHistory::new(10);
+        assert_eq!(history.import_cliphist(&path).unwrap(), 4);
+        let texts: Vec<_> = history.entries().map(HistoryEntry::text).collect();
+        assert_eq!(
+            texts[..],
+            [
+                Some("B".repeat(1500).as_str()),
+                Some("third"),
+                None,
+                Some("first")
+            ]
+        );
+        let png = history.entries().nth(2).unwrap();
+        assert_eq!(png.mime_types(), ["image/png"]);
+
+        // the empty entry and the same one twice are skipped
+        let path = path.with_file_name("cliphist-inline.db");
+        let mut history = ClipboardHistory::new(10);
+        assert_eq!(history.import_cliphist(&path).unwrap(), 1);
+    }
+}
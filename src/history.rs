@@ -0,0 +1,619 @@
+//! clipboard history
+//! keep the recent selections in memory, with the data of all their mimetypes, so any of them can
+//! be copied again as it was. A selection copied again is moved to the top instead of being added
+//! twice, the entries are told apart by the hash of their content
+//!
+//! The pinned entries never expire, and the named registers "a" to "z" keep a selection aside
+//! from the history, until it is replaced
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use sha2::{Digest, Sha256};
+
+use crate::copy::Payloads;
+use crate::export::{export, import, import_cliphist};
+use crate::search::{search, HistoryQuery, SearchMatch};
+use crate::text::TextEncoding;
+use crate::{ClipBoardSelection, CopySource};
+
+/// a selection in the history
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    id: u64,
+    payloads: Vec<(String, Arc<[u8]>)>,
+    timestamp: SystemTime,
+    size: usize,
+    hash: String,
+    marks: Vec<String>,
+    pinned: bool,
+}
+
+impl HistoryEntry {
+    fn new(id: u64, payloads: Vec<(String, Arc<[u8]>)>, marks: Vec<String>) -> Self {
+        let size = payloads.iter().map(|(_, data)| data.len()).sum();
+        let hash = content_hash(&payloads);
+        Self {
+            id,
+            payloads,
+            timestamp: SystemTime::now(),
+            size,
+            hash,
+            marks,
+            pinned: false,
+        }
+    }
+
+    /// an entry read back from the disk, with the time it was copied
+    pub(crate) fn restore(
+        id: u64,
+        payloads: Vec<(String, Arc<[u8]>)>,
+        timestamp: SystemTime,
+        marks: Vec<String>,
+        pinned: bool,
+    ) -> Self {
+        let mut entry = Self::new(id, payloads, marks);
+        entry.timestamp = timestamp;
+        entry.pinned = pinned;
+        entry
+    }
+
+    /// the id, it is unique in the history, and is kept when the entry is moved to the top
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// the mimetypes, in the order they were offered
+    pub fn mime_types(&self) -> Vec<&str> {
+        self.payloads
+            .iter()
+            .map(|(mimetype, _)| mimetype.as_str())
+            .collect()
+    }
+
+    /// the data of every mimetype
+    pub fn payloads(&self) -> &[(String, Arc<[u8]>)] {
+        &self.payloads
+    }
+
+    /// the data of mime_type
+    pub fn get(&self, mime_type: &str) -> Option<&Arc<[u8]>> {
+        self.payloads
+            .iter()
+            .find(|(mimetype, _)| mimetype == mime_type)
+            .map(|(_, data)| data)
+    }
+
+    /// the UTF-8 text, if there is one
+    pub fn text(&self) -> Option<&str> {
+        self.payloads
+            .iter()
+            .filter(|(mimetype, _)| {
+                TextEncoding::from_mime_type(mimetype) == Some(TextEncoding::Utf8)
+            })
+            .find_map(|(_, data)| std::str::from_utf8(data).ok())
+    }
+
+    /// the last time it was copied
+    pub fn timestamp(&self) -> SystemTime {
+        self.timestamp
+    }
+
+    /// the size of the data of all the mimetypes
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// the sha256 of the content in hex, the same content has the same hash
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    /// the names of the rules marking it, see [crate::ContentRules]
+    pub fn marks(&self) -> &[String] {
+        &self.marks
+    }
+
+    /// a pinned entry never expires, and is kept when the history is cleared
+    pub fn pinned(&self) -> bool {
+        self.pinned
+    }
+
+    /// a source to copy it again, with all the mimetypes, see
+    /// [crate::WlClipboardCopyStream::copy_source_to_clipboard]
+    pub fn source(&self) -> CopySource {
+        CopySource::from_payloads(self.payloads.clone())
+    }
+}
+
+/// the hash of every mimetype and its data, with their lengths, so the boundaries are part of it
+/// the mimetypes are sorted, so the same content offered in another order has the same hash
+fn content_hash(payloads: &[(String, Arc<[u8]>)]) -> String {
+    let mut sorted: Vec<_> = payloads.iter().collect();
+    sorted.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let mut hasher = Sha256::new();
+    for (mimetype, data) in sorted {
+        hasher.update((mimetype.len() as u64).to_le_bytes());
+        hasher.update(mimetype.as_bytes());
+        hasher.update((data.len() as u64).to_le_bytes());
+        hasher.update(data);
+    }
+    to_hex(&hasher.finalize())
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// if name is a register name, "a" to "z"
+pub fn is_register_name(name: char) -> bool {
+    name.is_ascii_lowercase()
+}
+
+pub(crate) fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+/// a bounded list of selections, the newest first
+/// when there are more than max_entries entries, or the size of them is more than max_bytes, the
+/// oldest ones are dropped, the pinned entries are not counted and never dropped
+/// ``` rust
+/// use wayland_clipboard_listener::ClipboardHistory;
+///
+/// let mut history = ClipboardHistory::new(10);
+/// let first = history.push(vec![("text/plain".to_string(), b"hello".to_vec())]).unwrap();
+/// history.push(vec![("text/plain".to_string(), b"world".to_vec())]);
+/// // copied again, it is moved to the top
+/// assert_eq!(history.push(vec![("text/plain".to_string(), b"hello".to_vec())]), Some(first));
+/// assert_eq!(history.len(), 2);
+/// assert_eq!(history.latest().unwrap().text(), Some("hello"));
+/// ```
+#[derive(Debug)]
+pub struct ClipboardHistory {
+    entries: VecDeque<HistoryEntry>,
+    max_entries: usize,
+    max_bytes: Option<usize>,
+    next_id: u64,
+    registers: BTreeMap<char, HistoryEntry>,
+}
+
+impl ClipboardHistory {
+    /// an empty history with at most max_entries entries
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            max_entries,
+            max_bytes: None,
+            next_id: 1,
+            registers: BTreeMap::new(),
+        }
+    }
+
+    /// set the max count of entries, the oldest ones are dropped if there are more
+    pub fn set_max_entries(&mut self, max_entries: usize) {
+        self.max_entries = max_entries;
+        self.trim();
+    }
+
+    /// set the max size of all the entries, the oldest ones are dropped if they are larger, but
+    /// the newest one is always kept
+    pub fn set_max_bytes(&mut self, max_bytes: Option<usize>) {
+        self.max_bytes = max_bytes;
+        self.trim();
+    }
+
+    /// add a selection with the data of its mimetypes to the top, and return its id
+    /// if the same content is already in the history, it is moved to the top instead
+    /// return None if there is no data, or it is not kept as max_entries is 0
+    pub fn push<P: Into<Arc<[u8]>>>(
+        &mut self,
+        payloads: impl IntoIterator<Item = (String, P)>,
+    ) -> Option<u64> {
+        self.push_marked(payloads, Vec::new())
+    }
+
+    /// push with the marks of the rules, marks are added to the ones of an entry already in the
+    /// history
+    pub(crate) fn push_marked<P: Into<Arc<[u8]>>>(
+        &mut self,
+        payloads: impl IntoIterator<Item = (String, P)>,
+        marks: Vec<String>,
+    ) -> Option<u64> {
+        let payloads: Vec<(String, Arc<[u8]>)> = payloads
+            .into_iter()
+            .map(|(mimetype, data)| (mimetype, data.into()))
+            .collect();
+        if payloads.is_empty() {
+            return None;
+        }
+        let entry = HistoryEntry::new(self.next_id, payloads, marks);
+        if let Some(index) = self.position_of_hash(&entry.hash) {
+            let mut existing = self.entries.remove(index).unwrap();
+            existing.timestamp = entry.timestamp;
+            for mark in entry.marks {
+                if !existing.marks.contains(&mark) {
+                    existing.marks.push(mark);
+                }
+            }
+            let id = existing.id;
+            self.entries.push_front(existing);
+            return Some(id);
+        }
+        self.next_id += 1;
+        let id = entry.id;
+        self.entries.push_front(entry);
+        self.trim();
+        self.get(id).map(|_| id)
+    }
+
+    /// add a selection from [crate::WlClipboardSelectionStream], see [ClipboardHistory::push]
+    /// the marks of the selection are kept
+    pub fn record(&mut self, selection: ClipBoardSelection) -> Option<u64> {
+        self.push_marked(selection.payloads, selection.marks)
+    }
+
+    /// the entries, the newest first
+    pub fn entries(&self) -> impl Iterator<Item = &HistoryEntry> {
+        self.entries.iter()
+    }
+
+    /// the entry of id
+    pub fn get(&self, id: u64) -> Option<&HistoryEntry> {
+        self.entries.iter().find(|entry| entry.id == id)
+    }
+
+    /// the newest entry, the one copied last
+    pub fn latest(&self) -> Option<&HistoryEntry> {
+        self.entries.front()
+    }
+
+    /// remove the entry of id, even if it is pinned, and return it
+    pub fn remove(&mut self, id: u64) -> Option<HistoryEntry> {
+        let index = self.entries.iter().position(|entry| entry.id == id)?;
+        self.entries.remove(index)
+    }
+
+    /// remove the entries, but the pinned ones, the registers are kept too
+    pub fn clear(&mut self) {
+        self.entries.retain(|entry| entry.pinned);
+    }
+
+    /// pin or unpin the entry, return false if it is not in the history
+    pub fn pin(&mut self, id: u64, pinned: bool) -> bool {
+        let Some(entry) = self.entries.iter_mut().find(|entry| entry.id == id) else {
+            return false;
+        };
+        entry.pinned = pinned;
+        self.trim();
+        true
+    }
+
+    /// the pinned entries, the newest first
+    pub fn pinned(&self) -> impl Iterator<Item = &HistoryEntry> {
+        self.entries.iter().filter(|entry| entry.pinned)
+    }
+
+    /// put a selection with the data of its mimetypes in the register name, "a" to "z"
+    /// the selection in it before is replaced
+    /// return false if name is not a register name, or there is no data
+    /// ``` rust
+    /// use wayland_clipboard_listener::ClipboardHistory;
+    ///
+    /// let mut history = ClipboardHistory::new(10);
+    /// assert!(history.set_register('a', vec![("text/plain".to_string(), b"hello".to_vec())]));
+    /// assert_eq!(history.register('a').unwrap().text(), Some("hello"));
+    /// // it is not in the history
+    /// assert!(history.is_empty());
+    /// ```
+    pub fn set_register<P: Into<Arc<[u8]>>>(
+        &mut self,
+        name: char,
+        payloads: impl IntoIterator<Item = (String, P)>,
+    ) -> bool {
+        let payloads: Vec<(String, Arc<[u8]>)> = payloads
+            .into_iter()
+            .map(|(mimetype, data)| (mimetype, data.into()))
+            .collect();
+        if !is_register_name(name) || payloads.is_empty() {
+            return false;
+        }
+        // the registers are not in the history, so they do not need an id
+        self.registers
+            .insert(name, HistoryEntry::new(0, payloads, Vec::new()));
+        true
+    }
+
+    /// the selection in the register name, its id is 0
+    pub fn register(&self, name: char) -> Option<&HistoryEntry> {
+        self.registers.get(&name)
+    }
+
+    /// the registers with a selection, by name
+    pub fn registers(&self) -> impl Iterator<Item = (char, &HistoryEntry)> {
+        self.registers.iter().map(|(name, entry)| (*name, entry))
+    }
+
+    /// empty the register name, and return the selection in it
+    pub fn remove_register(&mut self, name: char) -> Option<HistoryEntry> {
+        self.registers.remove(&name)
+    }
+
+    /// the count of entries, the pinned ones included, the registers not
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// if there is no entry
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// fuzzy search the text of the entries, the best matches first
+    /// ``` rust
+    /// use wayland_clipboard_listener::{ClipboardHistory, HistoryQuery};
+    ///
+    /// let mut history = ClipboardHistory::new(10);
+    /// history.push(vec![("text/plain".to_string(), b"cargo build --release".to_vec())]);
+    /// history.push(vec![("text/plain".to_string(), b"git commit".to_vec())]);
+    /// let matches = history.search(&HistoryQuery::new("cbr"));
+    /// assert_eq!(matches.len(), 1);
+    /// assert_eq!(matches[0].entry.text(), Some("cargo build --release"));
+    /// assert_eq!(matches[0].highlights, [0..1, 6..7, 14..15]);
+    /// ```
+    pub fn search(&self, query: &HistoryQuery) -> Vec<SearchMatch<'_>> {
+        search(self, query)
+    }
+
+    /// move the entry to the top, and return a source to copy it again, like
+    /// ``` rust, no_run
+    /// use wayland_clipboard_listener::{ClipboardHistory, WlClipboardCopyStream};
+    /// # let mut history = ClipboardHistory::new(10);
+    /// # let id = 1;
+    /// if let Some(source) = history.select(id) {
+    ///     let stream = WlClipboardCopyStream::init().unwrap();
+    ///     let handle = stream.copy_in_background(source, false).unwrap();
+    /// }
+    ///```
+    pub fn select(&mut self, id: u64) -> Option<CopySource> {
+        let index = self.entries.iter().position(|entry| entry.id == id)?;
+        let mut entry = self.entries.remove(index).unwrap();
+        entry.timestamp = SystemTime::now();
+        let source = entry.source();
+        self.entries.push_front(entry);
+        Some(source)
+    }
+
+    /// put an entry read back from the disk on the top, with its id
+    pub(crate) fn restore(&mut self, entry: HistoryEntry) {
+        self.next_id = self.next_id.max(entry.id + 1);
+        self.entries.push_front(entry);
+    }
+
+    /// write the entries and the registers as JSON Lines, with the data in base64, the oldest
+    /// entry first, return the count of lines
+    /// ```json
+    /// {"time":1700000000000,"payloads":{"text/plain;charset=utf-8":"aGVsbG8=","text/plain":"aGVsbG8="}}
+    /// {"time":1700000001000,"pinned":true,"marks":["tickets"],"payloads":{"image/png":"iVBORw0KGgo..."}}
+    /// {"register":"a","time":1700000002000,"payloads":{"text/plain":"d29ybGQ="}}
+    /// ```
+    /// time is the last time it was copied, in milliseconds since the epoch
+    /// ``` rust
+    /// use wayland_clipboard_listener::ClipboardHistory;
+    ///
+    /// let mut history = ClipboardHistory::new(10);
+    /// history.push(vec![("text/plain".to_string(), b"hello".to_vec())]);
+    /// let mut exported = Vec::new();
+    /// history.export(&mut exported).unwrap();
+    ///
+    /// let mut imported = ClipboardHistory::new(10);
+    /// assert_eq!(imported.import(exported.as_slice()).unwrap(), 1);
+    /// assert_eq!(imported.latest().unwrap().text(), Some("hello"));
+    /// ```
+    pub fn export(&self, writer: impl Write) -> io::Result<usize> {
+        export(self, writer)
+    }
+
+    /// read an export of [ClipboardHistory::export], the entries are put in their place by time
+    /// the entries already in the history are skipped, and the registers are replaced
+    /// nothing is imported if a line is bad, return the count of entries and registers imported
+    pub fn import(&mut self, reader: impl BufRead) -> io::Result<usize> {
+        import(self, reader)
+    }
+
+    /// read the database of cliphist, like `~/.cache/cliphist/db`
+    /// cliphist does not keep the mimetypes and the time, so the mimetypes are guessed from the
+    /// data, and the entries are put on the top, the newest first
+    pub fn import_cliphist(&mut self, path: impl AsRef<Path>) -> io::Result<usize> {
+        import_cliphist(self, path.as_ref())
+    }
+
+    /// put an imported entry in its place by time, return false if it is already in the history,
+    /// or it has no data
+    pub(crate) fn insert_entry(
+        &mut self,
+        payloads: Payloads,
+        timestamp: SystemTime,
+        marks: Vec<String>,
+        pinned: bool,
+    ) -> bool {
+        if payloads.is_empty() {
+            return false;
+        }
+        let entry = HistoryEntry::restore(self.next_id, payloads, timestamp, marks, pinned);
+        if self.position_of_hash(&entry.hash).is_some() {
+            return false;
+        }
+        self.next_id += 1;
+        let index = self
+            .entries
+            .iter()
+            .position(|newer| newer.timestamp <= timestamp)
+            .unwrap_or(self.entries.len());
+        self.entries.insert(index, entry);
+        self.trim();
+        true
+    }
+
+    /// put an imported register, return false if name is not a register name, or it has no data
+    pub(crate) fn insert_register(
+        &mut self,
+        name: char,
+        payloads: Payloads,
+        timestamp: SystemTime,
+    ) -> bool {
+        if !is_register_name(name) || payloads.is_empty() {
+            return false;
+        }
+        self.registers.insert(
+            name,
+            HistoryEntry::restore(0, payloads, timestamp, Vec::new(), false),
+        );
+        true
+    }
+
+    /// put a register read back from the disk
+    pub(crate) fn restore_register(&mut self, name: char, entry: HistoryEntry) {
+        self.registers.insert(name, entry);
+    }
+
+    /// the oldest entry which can expire
+    pub(crate) fn oldest(&self) -> Option<&HistoryEntry> {
+        self.entries.iter().rev().find(|entry| !entry.pinned)
+    }
+
+    fn position_of_hash(&self, hash: &str) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.hash == hash)
+    }
+
+    fn trim(&mut self) {
+        let (max_entries, max_bytes) = (self.max_entries, self.max_bytes);
+        let mut count = 0;
+        let mut size = 0;
+        self.entries.retain(|entry| {
+            if entry.pinned {
+                return true;
+            }
+            count += 1;
+            size += entry.size;
+            // the newest one is kept, even if it is larger than max_bytes
+            count <= max_entries && (count == 1 || max_bytes.is_none_or(|max| size <= max))
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Vec<(String, Vec<u8>)> {
+        vec![("text/plain".to_string(), text.as_bytes().to_vec())]
+    }
+
+    fn texts(history: &ClipboardHistory) -> Vec<&str> {
+        history.entries().filter_map(HistoryEntry::text).collect()
+    }
+
+    #[test]
+    fn push_moves_the_same_content_to_the_top() {
+        let mut history = ClipboardHistory::new(10);
+        let hello = history.push(text("hello")).unwrap();
+        let world = history.push(text("world")).unwrap();
+        assert_ne!(hello, world);
+        assert_eq!(history.push(text("hello")), Some(hello));
+        assert_eq!(texts(&history), ["hello", "world"]);
+        assert_eq!(history.push(Vec::<(String, Vec<u8>)>::new()), None);
+    }
+
+    #[test]
+    fn push_merges_the_marks_of_the_same_content() {
+        let mut history = ClipboardHistory::new(10);
+        let marks = |marks: &[&str]| marks.iter().map(|mark| mark.to_string()).collect();
+        let id = history.push_marked(text("hello"), marks(&["a"])).unwrap();
+        assert_eq!(
+            history.push_marked(text("hello"), marks(&["b", "a"])),
+            Some(id)
+        );
+        assert_eq!(history.get(id).unwrap().marks(), ["a", "b"]);
+        // a push without marks keeps them
+        history.push(text("hello"));
+        assert_eq!(history.get(id).unwrap().marks(), ["a", "b"]);
+    }
+
+    #[test]
+    fn push_finds_the_same_content_in_another_order() {
+        let mut history = ClipboardHistory::new(10);
+        let html = ("text/html".to_string(), b"<b>hi</b>".to_vec());
+        let plain = ("text/plain".to_string(), b"hi".to_vec());
+        let id = history.push(vec![html.clone(), plain.clone()]).unwrap();
+        assert_eq!(history.push(vec![plain, html.clone()]), Some(id));
+        assert_eq!(history.len(), 1);
+        // the mimetypes are part of the content
+        let other = history
+            .push(vec![("text/xml".to_string(), html.1)])
+            .unwrap();
+        assert_ne!(other, id);
+    }
+
+    #[test]
+    fn push_returns_none_if_nothing_is_kept() {
+        let mut history = ClipboardHistory::new(0);
+        assert_eq!(history.push(text("hello")), None);
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn trim_drops_the_oldest_but_the_pinned() {
+        let mut history = ClipboardHistory::new(2);
+        let first = history.push(text("first")).unwrap();
+        assert!(history.pin(first, true));
+        history.push(text("second"));
+        history.push(text("third"));
+        history.push(text("fourth"));
+        assert_eq!(texts(&history), ["fourth", "third", "first"]);
+
+        history.set_max_bytes(Some(5));
+        // the newest one is kept even if it is larger
+        assert_eq!(texts(&history), ["fourth", "first"]);
+
+        history.clear();
+        assert_eq!(texts(&history), ["first"]);
+        assert!(history.pin(first, false));
+        assert!(!history.pin(first + 100, true));
+    }
+
+    #[test]
+    fn select_moves_the_entry_to_the_top() {
+        let mut history = ClipboardHistory::new(10);
+        let hello = history.push(text("hello")).unwrap();
+        history.push(text("world"));
+        assert!(history.select(hello).is_some());
+        assert_eq!(history.latest().unwrap().id(), hello);
+        assert!(history.remove(hello).is_some());
+        assert!(history.get(hello).is_none());
+        assert!(history.select(hello).is_none());
+    }
+
+    #[test]
+    fn registers_are_kept_aside() {
+        let mut history = ClipboardHistory::new(10);
+        assert!(history.set_register('a', text("hello")));
+        assert!(history.set_register('a', text("world")));
+        assert!(!history.set_register('A', text("hello")));
+        assert!(!history.set_register('b', Vec::<(String, Vec<u8>)>::new()));
+        assert_eq!(history.register('a').unwrap().text(), Some("world"));
+        assert!(history.is_empty());
+        history.clear();
+        assert_eq!(history.registers().count(), 1);
+        assert!(history.remove_register('a').is_some());
+        assert!(history.register('a').is_none());
+    }
+}
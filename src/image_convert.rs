@@ -0,0 +1,86 @@
+//! convert images between formats, with the `image-convert` feature
+//! some apps only accept `image/bmp` or `image/jpeg`, but screenshots are usually `image/png`, so
+//! the copy side can offer more formats than it has, and the paste side can ask for the format it
+//! wants
+
+use std::io::{self, Cursor};
+
+use image::{DynamicImage, ImageFormat};
+
+/// the image formats can be converted from and to
+pub(crate) const IMAGE_MIME_TYPES: [&str; 6] = [
+    "image/png",
+    "image/jpeg",
+    "image/bmp",
+    "image/gif",
+    "image/webp",
+    "image/tiff",
+];
+
+fn format_of(mime_type: &str) -> Option<ImageFormat> {
+    if !IMAGE_MIME_TYPES.contains(&mime_type) {
+        return None;
+    }
+    ImageFormat::from_mime_type(mime_type)
+}
+
+/// if data of mime_type can be converted by [convert_image]
+pub(crate) fn can_convert(mime_type: &str) -> bool {
+    format_of(mime_type).is_some()
+}
+
+/// decode data as from, and encode it as to
+pub(crate) fn convert_image(data: &[u8], from: &str, to: &str) -> io::Result<Vec<u8>> {
+    let (Some(from_format), Some(to_format)) = (format_of(from), format_of(to)) else {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("cannot convert {from} to {to}"),
+        ));
+    };
+
+    let image = image::load_from_memory_with_format(data, from_format).map_err(io::Error::other)?;
+    // jpeg has no alpha, and the webp and gif encoders only take 8 bit colors
+    let image = match to_format {
+        ImageFormat::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8()),
+        ImageFormat::WebP | ImageFormat::Gif => DynamicImage::ImageRgba8(image.to_rgba8()),
+        _ => image,
+    };
+    let mut converted = Cursor::new(Vec::new());
+    image
+        .write_to(&mut converted, to_format)
+        .map_err(io::Error::other)?;
+    Ok(converted.into_inner())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::mime::detect_mime_types;
+
+    pub(crate) fn png() -> Vec<u8> {
+        let mut png = Cursor::new(Vec::new());
+        image::RgbaImage::from_pixel(4, 4, image::Rgba([255, 0, 0, 128]))
+            .write_to(&mut png, ImageFormat::Png)
+            .unwrap();
+        png.into_inner()
+    }
+
+    #[test]
+    fn the_images_are_converted_to_every_format() {
+        let png = png();
+        for to in IMAGE_MIME_TYPES {
+            let converted = convert_image(&png, "image/png", to).unwrap();
+            assert_eq!(detect_mime_types(&converted), [to]);
+            let back = convert_image(&converted, to, "image/png").unwrap();
+            assert_eq!(detect_mime_types(&back), ["image/png"]);
+        }
+    }
+
+    #[test]
+    fn the_other_formats_are_refused() {
+        let e = convert_image(&png(), "image/png", "image/svg+xml").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::Unsupported);
+        assert!(!can_convert("image/avif"));
+        assert!(convert_image(b"not a png", "image/png", "image/bmp").is_err());
+    }
+}
@@ -61,6 +61,11 @@
 //!     will be in clipboard
 //!   * 2.2. when received cancelled, exit the progress
 //!
+//! Writing to a pipe whose paste side is closed raises SIGPIPE. Rust programs ignore it by
+//! default, so the failed paste is just dropped, but if this library is used from a program that
+//! does not ignore it, like a C host, it must set SIGPIPE to `SIG_IGN` before copying, or it will
+//! be killed.
+//!
 //! A simple example to create a clipboard listener is following:
 //!
 //! ```rust, no_run
@@ -114,31 +119,75 @@
 
 #![allow(clippy::needless_doctest_main)]
 
+mod client;
+mod cliphist;
 mod constvar;
+mod copy;
+#[cfg(feature = "encryption")]
+mod crypt;
+mod daemon;
+#[cfg(feature = "dbus")]
+mod dbus;
 mod dispatch;
+mod eventloop;
+mod export;
+mod history;
+#[cfg(feature = "image-convert")]
+mod image_convert;
+mod mime;
+mod rules;
+mod search;
+mod store;
+#[cfg(test)]
+mod testutil;
+mod text;
 
 #[cfg(feature = "wlr-data-control")]
 mod dispatch_wlr;
 
 use std::collections::HashMap;
 use std::io::Read;
+use std::os::fd::{AsFd, BorrowedFd};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use os_pipe::{pipe, PipeReader};
 
 use wayland_client::{protocol::wl_seat, Connection, DispatchError, EventQueue, Proxy};
 
 use wayland_protocols::ext::data_control::v1::client::{
     ext_data_control_device_v1, ext_data_control_manager_v1, ext_data_control_offer_v1,
+    ext_data_control_source_v1,
 };
 
 #[cfg(feature = "wlr-data-control")]
 use wayland_protocols_wlr::data_control::v1::client::{
     zwlr_data_control_device_v1, zwlr_data_control_manager_v1, zwlr_data_control_offer_v1,
+    zwlr_data_control_source_v1,
 };
 
 use std::sync::{Arc, Mutex};
 
 use thiserror::Error;
 
-use constvar::{IMAGE, TEXT};
+use constvar::{IMAGE, PASSWORD_HINT, TEXT};
+use copy::{Payloads, PendingWrite};
+use daemon::{DaemonAction, Server};
+use eventloop::{Receiving, Wakeup};
+use nix::poll::PollFlags;
+
+pub use client::{ClipboardClient, ClipboardSubscription};
+pub use copy::{ClipboardDataProvider, CopyEnd, CopyHandle, CopySource};
+#[cfg(feature = "encryption")]
+pub use crypt::HistoryKey;
+pub use daemon::{daemon_socket_path, ClipboardEvent, HistoryItem, SubscriptionFilter};
+#[cfg(feature = "dbus")]
+pub use dbus::ClipboardDBusService;
+pub use history::{is_register_name, ClipboardHistory, HistoryEntry};
+pub use mime::{detect_mime_types, TEXT_MIME_TYPES};
+pub use rules::{ContentRule, ContentRules, EntropyRule, RuleAction};
+pub use search::{HistoryQuery, MimeClass, SearchMatch};
+pub use store::{default_history_dir, HistoryStore};
 
 /// listentype
 /// if ListenOnHover, it will be useful for translation apps, but in dispatch, we cannot know the
@@ -151,6 +200,65 @@ pub enum WlListenType {
     ListenOnCopy,
 }
 
+/// copytarget
+/// Clipboard is the one pasted by ctrl-v, and Primary is the one pasted by the middle button of
+/// mouse, Both will set the two selections at once, and the copy lives until both are taken by
+/// others
+///
+/// it can be converted from a bool, true means Primary, as the old useprimary
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WlCopyTarget {
+    Clipboard,
+    Primary,
+    Both,
+}
+
+impl From<bool> for WlCopyTarget {
+    fn from(useprimary: bool) -> Self {
+        if useprimary {
+            Self::Primary
+        } else {
+            Self::Clipboard
+        }
+    }
+}
+
+impl WlCopyTarget {
+    /// the useprimary of every selection to set
+    fn primaries(self) -> &'static [bool] {
+        match self {
+            Self::Clipboard => &[false],
+            Self::Primary => &[true],
+            Self::Both => &[false, true],
+        }
+    }
+
+    fn contains(self, useprimary: bool) -> bool {
+        self.primaries().contains(&useprimary)
+    }
+}
+
+/// syncdirection
+/// PrimaryToClipboard fills the clipboard when some text is selected, ClipboardToPrimary fills
+/// the primary selection when something is copied, and Both does the two, like X11 tools
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WlSyncDirection {
+    PrimaryToClipboard,
+    ClipboardToPrimary,
+    Both,
+}
+
+impl WlSyncDirection {
+    /// if the selection of useprimary is synced to the other one
+    fn syncs_from(self, useprimary: bool) -> bool {
+        match self {
+            Self::PrimaryToClipboard => useprimary,
+            Self::ClipboardToPrimary => !useprimary,
+            Self::Both => true,
+        }
+    }
+}
+
 /// Error
 /// it describe three kind of error
 /// 1. failed when init
@@ -178,10 +286,34 @@ pub struct ClipBoardListenContext {
     pub context: Vec<u8>,
 }
 
+/// marks are the names of the [RuleAction::Mark] rules matching it, see [ContentRules]
+/// more fields may be added, so it can only be made by this crate
 #[derive(Debug)]
+#[non_exhaustive]
 pub struct ClipBoardListenMessage {
     pub mime_types: Vec<String>,
     pub context: ClipBoardListenContext,
+    pub marks: Vec<String>,
+}
+
+/// selection
+/// a new selection with the data of all its mimetypes, in the order they are offered
+/// primary is true if it is the primary selection
+/// marks are the names of the [RuleAction::Mark] rules matching it, see [ContentRules]
+#[derive(Debug, Clone)]
+pub struct ClipBoardSelection {
+    pub primary: bool,
+    pub payloads: Vec<(String, Arc<[u8]>)>,
+    pub marks: Vec<String>,
+}
+
+/// what the keep loop sees of the selections of others
+pub(crate) enum KeptSelection {
+    /// a new selection is received
+    New(ClipBoardSelection),
+    /// the selection is cleared, or it is skipped, like a secret, a selection dropped by the
+    /// rules, or one which is not received
+    Gone { primary: bool },
 }
 
 /// Paste stream
@@ -229,6 +361,18 @@ impl WlClipboardPasteStream {
     pub fn set_priority(&mut self, val: Vec<String>) {
         self.inner.set_priority = Some(val);
     }
+
+    /// apply rules to the received content, the dropped content is skipped
+    pub fn set_rules(&mut self, rules: Option<ContentRules>) {
+        self.inner.rules = rules;
+    }
+
+    /// Set the image format wanted, like "image/jpeg" (only applies when using ListenOnCopy)
+    /// if an image is received, and the format is not offered, the image is converted to it
+    #[cfg(feature = "image-convert")]
+    pub fn set_image_format(&mut self, format: Option<String>) {
+        self.inner.image_format = format;
+    }
 }
 
 /// copy stream,
@@ -245,10 +389,32 @@ impl WlClipboardCopyStream {
         })
     }
 
+    /// Set a timeout for copy, useful for secrets like passwords
+    /// if no one else takes the selection before the timeout, the selection will be cleared, and
+    /// copy_to_clipboard will return
+    /// mark the copy as a secret too, or a persist stream will offer it again, see set_secret
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.inner.copy_timeout = timeout;
+    }
+
+    /// Serve only limit pastes, then the selection will be cleared, and copy_to_clipboard will
+    /// return, like `wl-copy --paste-once` when the limit is 1, useful for one-time tokens
+    pub fn set_paste_limit(&mut self, limit: Option<usize>) {
+        self.inner.copy_paste_limit = limit;
+    }
+
+    /// offer the selection with the `x-kde-passwordManagerHint` of password managers, so the
+    /// clipboard managers and [WlClipboardPersistStream] do not keep it
+    pub fn set_secret(&mut self, secret: bool) {
+        self.inner.copy_secret = secret;
+    }
+
     /// it will run a never end loop, to handle the paste event, like what wl-copy do
     /// it will live until next copy event happened
-    /// you need to pass data and if use useprimary to it,
-    /// if is useprimary, you can use the middle button of mouse to paste
+    /// you need to pass data and the target to it, see [WlCopyTarget]
+    /// data can be a [`Vec<u8>`], or an [`Arc<[u8]>`] if you want to share the buffer
+    /// target can also be a bool as useprimary, if is useprimary, you can use the middle button
+    /// of mouse to paste
     /// Take [primary-selection](https://patchwork.freedesktop.org/patch/257267/) as reference
     /// ``` rust, no_run
     /// use wayland_clipboard_listener::{WlClipboardCopyStream, WlClipboardListenerError};
@@ -263,13 +429,335 @@ impl WlClipboardCopyStream {
     ///```
     pub fn copy_to_clipboard(
         &mut self,
-        data: Vec<u8>,
+        data: impl Into<Arc<[u8]>>,
         mimetypes: Vec<&str>,
-        useprimary: bool,
+        target: impl Into<WlCopyTarget>,
+    ) -> Result<(), WlClipboardListenerError> {
+        self.inner
+            .copy_to_clipboard(CopySource::new(data, mimetypes), target.into())?;
+        Ok(())
+    }
+
+    /// like copy_to_clipboard, but every mimetype has its own data, you can pass a HashMap, or a
+    /// Vec of (mimetype, data) if the order of the mimetypes matters
+    /// the mimetypes are offered in order, and a paste is served with the data of the requested
+    /// mimetype
+    /// ``` rust, no_run
+    /// use wayland_clipboard_listener::WlClipboardCopyStream;
+    /// let mut stream = WlClipboardCopyStream::init().unwrap();
+    /// stream
+    ///     .copy_payloads_to_clipboard(
+    ///         vec![
+    ///             ("text/html".to_string(), b"<b>hello</b>".to_vec()),
+    ///             ("text/plain".to_string(), b"hello".to_vec()),
+    ///         ],
+    ///         false,
+    ///     )
+    ///     .unwrap();
+    ///```
+    pub fn copy_payloads_to_clipboard<P: Into<Arc<[u8]>>>(
+        &mut self,
+        payloads: impl IntoIterator<Item = (String, P)>,
+        target: impl Into<WlCopyTarget>,
+    ) -> Result<(), WlClipboardListenerError> {
+        self.inner
+            .copy_to_clipboard(CopySource::from_payloads(payloads), target.into())?;
+        Ok(())
+    }
+
+    /// like copy_to_clipboard, but the data is made by the provider when someone pastes, see
+    /// [ClipboardDataProvider]
+    pub fn copy_provider_to_clipboard(
+        &mut self,
+        provider: impl ClipboardDataProvider + Send + 'static,
+        target: impl Into<WlCopyTarget>,
+    ) -> Result<(), WlClipboardListenerError> {
+        self.inner
+            .copy_to_clipboard(CopySource::from_provider(provider), target.into())?;
+        Ok(())
+    }
+
+    /// copy a [CopySource] to clipboard, like copy_to_clipboard, it lives until the selection is
+    /// taken by others
+    /// ``` rust, no_run
+    /// use wayland_clipboard_listener::{CopySource, WlClipboardCopyStream};
+    /// let mut stream = WlClipboardCopyStream::init().unwrap();
+    /// let source = CopySource::open("/tmp/large.png", vec!["image/png"]).unwrap();
+    /// stream.copy_source_to_clipboard(source, false).unwrap();
+    ///```
+    pub fn copy_source_to_clipboard(
+        &mut self,
+        source: CopySource,
+        target: impl Into<WlCopyTarget>,
+    ) -> Result<(), WlClipboardListenerError> {
+        self.inner.copy_to_clipboard(source, target.into())?;
+        Ok(())
+    }
+
+    /// like copy_to_clipboard, but it returns once the selection is set, the pastes are served in
+    /// a background thread, use the returned [CopyHandle] to wait or revoke it
+    /// ``` rust, no_run
+    /// use wayland_clipboard_listener::{CopySource, WlClipboardCopyStream};
+    /// let stream = WlClipboardCopyStream::init().unwrap();
+    /// let handle = stream
+    ///     .copy_in_background(CopySource::new(b"hello".to_vec(), vec!["text/plain"]), false)
+    ///     .unwrap();
+    /// // do other things
+    /// if handle.is_active() {
+    ///     handle.revoke().unwrap();
+    /// }
+    ///```
+    pub fn copy_in_background(
+        mut self,
+        source: CopySource,
+        target: impl Into<WlCopyTarget>,
+    ) -> Result<CopyHandle, WlClipboardListenerError> {
+        self.inner.start_copy(source, target.into())?;
+        let (reader, writer) = pipe().map_err(|_| WlClipboardListenerError::PipeError)?;
+        let thread = std::thread::spawn(move || self.inner.serve_copy(Some(reader)));
+        Ok(CopyHandle {
+            thread,
+            wakeup: writer,
+        })
+    }
+
+    /// clear the clipboard, after that, there is nothing to paste, even if
+    /// [WlClipboardPersistStream] is running
+    pub fn clear(&mut self) -> Result<(), WlClipboardListenerError> {
+        self.inner.clear(false)
+    }
+
+    /// clear the primary selection, the one pasted by the middle button of mouse
+    pub fn clear_primary(&mut self) -> Result<(), WlClipboardListenerError> {
+        self.inner.clear(true)
+    }
+}
+
+/// persist stream
+/// it keeps the selections alive when their owners exit, like wl-clip-persist, so the text copied
+/// from firefox can still be pasted after firefox is killed
+/// every mimetype of a new selection is received and kept, when the selection becomes empty, the
+/// kept data is offered again by ourselves
+/// a selection not received in 5 seconds, or larger than 64 MiB, is not kept
+pub struct WlClipboardPersistStream {
+    inner: WlClipboardListenerStream,
+}
+
+impl WlClipboardPersistStream {
+    /// init a persist stream
+    pub fn init() -> Result<Self, WlClipboardListenerError> {
+        Ok(Self {
+            inner: WlClipboardListenerStream::init(WlListenType::ListenOnCopy)?,
+        })
+    }
+
+    /// keep the selections of target alive, it runs a never end loop, and only returns when there
+    /// is an error
+    /// Note: a cleared selection is offered again too, as it cannot be told from an exited owner,
+    /// except the secrets marked by password managers, they are never kept, so a copy marked by
+    /// [WlClipboardCopyStream::set_secret] is not offered again when its timeout or paste limit
+    /// clears it
+    /// ``` rust, no_run
+    /// use wayland_clipboard_listener::{WlClipboardPersistStream, WlCopyTarget};
+    /// let mut stream = WlClipboardPersistStream::init().unwrap();
+    /// stream.persist(WlCopyTarget::Both).unwrap();
+    ///```
+    pub fn persist(
+        &mut self,
+        target: impl Into<WlCopyTarget>,
     ) -> Result<(), WlClipboardListenerError> {
-        self.inner.copy_to_clipboard(data, mimetypes, useprimary)
+        self.inner.keep_target = Some(target.into());
+        self.inner.keep_selections()
+    }
+}
+
+/// sync stream
+/// it syncs the clipboard and the primary selection, like the X11 tools, see [WlSyncDirection]
+/// every mimetype is synced with its data, so images and rich text are synced too
+pub struct WlClipboardSyncStream {
+    inner: WlClipboardListenerStream,
+}
+
+impl WlClipboardSyncStream {
+    /// init a sync stream
+    pub fn init() -> Result<Self, WlClipboardListenerError> {
+        Ok(Self {
+            inner: WlClipboardListenerStream::init(WlListenType::ListenOnCopy)?,
+        })
+    }
+
+    /// also keep the selections of target alive when syncing, see
+    /// [WlClipboardPersistStream::persist]
+    pub fn set_persist(&mut self, target: Option<WlCopyTarget>) {
+        self.inner.keep_target = target;
+    }
+
+    /// sync the selections in direction, it runs a never end loop, and only returns when there is
+    /// an error
+    /// the synced selection is offered by ourselves, so it lives until it is taken by others
+    /// ``` rust, no_run
+    /// use wayland_clipboard_listener::{WlClipboardSyncStream, WlSyncDirection};
+    /// let mut stream = WlClipboardSyncStream::init().unwrap();
+    /// stream.sync(WlSyncDirection::PrimaryToClipboard).unwrap();
+    ///```
+    pub fn sync(&mut self, direction: WlSyncDirection) -> Result<(), WlClipboardListenerError> {
+        self.inner.sync_direction = Some(direction);
+        self.inner.keep_selections()
+    }
+}
+
+/// selection stream
+/// it provides a iter of the new selections, with the data of all their mimetypes, see
+/// [ClipBoardSelection], so a clipboard history can copy them again, see [ClipboardHistory]
+/// ``` rust, no_run
+/// use wayland_clipboard_listener::{ClipboardHistory, WlClipboardSelectionStream, WlCopyTarget};
+/// let mut history = ClipboardHistory::new(100);
+/// let mut stream = WlClipboardSelectionStream::init(WlCopyTarget::Clipboard).unwrap();
+/// for selection in stream.flatten() {
+///     history.record(selection);
+/// }
+///```
+pub struct WlClipboardSelectionStream {
+    inner: WlClipboardListenerStream,
+}
+
+impl WlClipboardSelectionStream {
+    /// init a selection stream, to watch the selections of target
+    pub fn init(target: impl Into<WlCopyTarget>) -> Result<Self, WlClipboardListenerError> {
+        let mut inner = WlClipboardListenerStream::init(WlListenType::ListenOnCopy)?;
+        inner.watch_target = Some(target.into());
+        Ok(Self { inner })
+    }
+
+    /// also keep the selections of target alive, see [WlClipboardPersistStream::persist]
+    pub fn set_persist(&mut self, target: Option<WlCopyTarget>) {
+        self.inner.keep_target = target;
+    }
+
+    /// also sync the selections in direction, see [WlClipboardSyncStream::sync]
+    /// the synced selection is offered by ourselves, so it is not yielded again
+    pub fn set_sync(&mut self, direction: Option<WlSyncDirection>) {
+        self.inner.sync_direction = direction;
+    }
+
+    /// apply rules to the selections, the dropped ones are skipped, and are not kept alive
+    pub fn set_rules(&mut self, rules: Option<ContentRules>) {
+        self.inner.rules = rules;
+    }
+
+    /// wait for the next selection
+    /// the secrets marked by password managers, and the selections dropped by the rules are
+    /// skipped
+    pub fn get_selection(&mut self) -> Result<ClipBoardSelection, WlClipboardListenerError> {
+        self.inner.next_kept_selection()
+    }
+
+    /// get the current selection without waiting, call it before [Self::get_selection]
+    /// return None if it is empty, or skipped like in [Self::get_selection]
+    pub fn get_current_selection(
+        &mut self,
+    ) -> Result<Option<ClipBoardSelection>, WlClipboardListenerError> {
+        self.inner.current_kept_selection()
+    }
+}
+
+impl Iterator for WlClipboardSelectionStream {
+    type Item = Result<ClipBoardSelection, WlClipboardListenerError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let data = self.get_selection();
+        if let Err(WlClipboardListenerError::DispatchError(err)) = data.as_ref() {
+            panic!("error with wayland side: {err}");
+        }
+        Some(data)
+    }
+}
+
+/// daemon stream
+/// it owns one connection, and serves the clipboard to the clients of a unix socket, so they need
+/// no wayland connection of their own, and do not fork to keep their copies alive, see
+/// [ClipboardClient]
+/// the clients get the selections, set and clear them, search the history, and subscribe to the
+/// changes, the protocol is JSON Lines, see the request methods of [ClipboardClient]
+/// the new selections are received in a thread, so the clients are served meanwhile, a text is
+/// received once even if it is offered as several mimetypes, and an image in one format
+/// ``` rust, no_run
+/// use wayland_clipboard_listener::{daemon_socket_path, HistoryStore, WlClipboardDaemonStream};
+/// let mut stream = WlClipboardDaemonStream::init(daemon_socket_path().unwrap()).unwrap();
+/// stream.set_history(Some(HistoryStore::open("/tmp/history").unwrap()));
+/// stream.run().unwrap();
+///```
+pub struct WlClipboardDaemonStream {
+    inner: WlClipboardListenerStream,
+    server: Server,
+}
+
+impl WlClipboardDaemonStream {
+    /// init a daemon stream listening on the socket at path, see [daemon_socket_path]
+    /// a socket left by a dead daemon is replaced, but it fails if another daemon is listening
+    /// the dir of path is created if it does not exist, it fails if the others can write it, and
+    /// the clients run by the others are refused
+    pub fn init(path: impl AsRef<Path>) -> Result<Self, WlClipboardListenerError> {
+        let path = path.as_ref();
+        let server = Server::bind(path).map_err(|e| {
+            WlClipboardListenerError::InitFailed(format!("Cannot listen on {path:?}: {e}"))
+        })?;
+        let mut inner = WlClipboardListenerStream::init(WlListenType::ListenOnCopy)?;
+        inner.watch_target = Some(WlCopyTarget::Both);
+        inner.history_mime_types = true;
+        Ok(Self { inner, server })
+    }
+
+    /// also keep the selections of target alive, see [WlClipboardPersistStream::persist]
+    pub fn set_persist(&mut self, target: Option<WlCopyTarget>) {
+        self.inner.keep_target = target;
+    }
+
+    /// apply rules to the selections, see [WlClipboardSelectionStream::set_rules]
+    pub fn set_rules(&mut self, rules: Option<ContentRules>) {
+        self.inner.rules = rules;
+    }
+
+    /// keep the clipboard in the history, the clients can search it and select the entries again
+    /// the selected texts of the primary selection are not kept
+    /// an encrypted history can be given locked, it is unlocked by `ClipboardClient::unlock`,
+    /// and nothing is kept until then
+    pub fn set_history(&mut self, history: Option<HistoryStore>) {
+        self.server.history = history;
+    }
+
+    /// the bytes of the events waiting for a slow subscriber, 4 MiB by default, beyond it the
+    /// oldest ones are dropped, and the subscriber is told by [ClipboardEvent::Lagged]
+    pub fn set_backlog(&mut self, bytes: usize) {
+        self.server.backlog = bytes;
+    }
+
+    /// serve the clients, it runs a never end loop, and only returns when there is an error
+    pub fn run(&mut self) -> Result<(), WlClipboardListenerError> {
+        if let Some(selection) = self.inner.current_kept_selection()? {
+            self.server.selection_changed(selection);
+        }
+        loop {
+            let fds = self.server.poll_fds();
+            let selection = self.inner.poll_kept_selection(&fds)?;
+            drop(fds);
+            match selection {
+                Some(KeptSelection::New(selection)) => self.server.selection_changed(selection),
+                Some(KeptSelection::Gone { primary }) => self.server.selection_gone(primary),
+                None => {}
+            }
+            for action in self.server.serve() {
+                match action {
+                    DaemonAction::Set(payloads, target) => {
+                        self.inner.set_kept_selection(payloads, target)?
+                    }
+                    DaemonAction::Clear(target) => self.inner.clear_kept_selection(target)?,
+                }
+            }
+        }
     }
 }
+
 /// Stream, provide a iter to listen to clipboard
 /// Note, the iter will loop very fast, you would better to use thread sleep
 /// or iter you self
@@ -287,8 +775,25 @@ pub struct WlClipboardListenerStream {
     pipereader: Option<os_pipe::PipeReader>,
     current_type: Option<String>,
     queue: Option<Arc<Mutex<EventQueue<Self>>>>,
-    copy_data: Option<Vec<u8>>,
-    copy_cancelled: bool,
+    copy_source: Option<CopySource>,
+    copy_sources: Vec<(ext_data_control_source_v1::ExtDataControlSourceV1, bool)>,
+    copy_timeout: Option<Duration>,
+    copy_secret: bool,
+    copy_paste_limit: Option<usize>,
+    copy_pasted: usize,
+    copy_writes: Vec<PendingWrite>,
+    #[cfg(feature = "image-convert")]
+    image_format: Option<String>,
+    keep_target: Option<WlCopyTarget>,
+    watch_target: Option<WlCopyTarget>,
+    sync_direction: Option<WlSyncDirection>,
+    selection_changed: [bool; 2],
+    kept_sources: [Option<CopySource>; 2],
+    /// the selections being received from others, see [Receiving]
+    receiving: [Option<Receiving>; 2],
+    /// only receive the mimetypes kept in a history, see [mime::history_mime_types]
+    history_mime_types: bool,
+    rules: Option<ContentRules>,
 }
 
 impl Iterator for WlClipboardListenerStream {
@@ -331,8 +836,23 @@ impl WlClipboardListenerStream {
             pipereader: None,
             current_type: None,
             queue: None,
-            copy_data: None,
-            copy_cancelled: false,
+            copy_source: None,
+            copy_sources: Vec::new(),
+            copy_timeout: None,
+            copy_secret: false,
+            copy_paste_limit: None,
+            copy_pasted: 0,
+            copy_writes: Vec::new(),
+            #[cfg(feature = "image-convert")]
+            image_format: None,
+            keep_target: None,
+            watch_target: None,
+            sync_direction: None,
+            selection_changed: [false; 2],
+            kept_sources: [None, None],
+            receiving: [None, None],
+            history_mime_types: false,
+            rules: None,
         };
 
         event_queue.blocking_dispatch(&mut state).map_err(|e| {
@@ -357,140 +877,670 @@ impl WlClipboardListenerStream {
     }
 
     /// copy data to stream
-    /// pass [Vec<u8>] as data
-    /// now it can just copy text
+    /// every mimetype of [CopySource] is offered, and served on demand
     /// It will always live in the background, so you need to handle it yourself
     fn copy_to_clipboard(
         &mut self,
-        data: Vec<u8>,
-        mimetypes: Vec<&str>,
-        useprimary: bool,
+        data: CopySource,
+        target: WlCopyTarget,
+    ) -> Result<CopyEnd, WlClipboardListenerError> {
+        self.start_copy(data, target)?;
+        self.serve_copy(None)
+    }
+
+    /// create sources with the mimetypes of data, and set them as the selections of target
+    /// every selection needs its own source
+    fn start_copy(
+        &mut self,
+        mut data: CopySource,
+        target: WlCopyTarget,
     ) -> Result<(), WlClipboardListenerError> {
         let eventqh = self.queue.clone().unwrap();
-        let mut event_queue = eventqh.lock().unwrap();
+        let event_queue = eventqh.lock().unwrap();
         let qh = event_queue.handle();
-        let manager = self.data_manager.as_ref().unwrap();
-        let source = manager.create_data_source(&qh, ());
-        let device = self.data_device.as_ref().unwrap();
 
-        for mimetype in mimetypes {
-            source.offer(mimetype.to_string());
+        if self.copy_secret {
+            data.set_secret();
         }
-
-        if useprimary {
-            device.set_primary_selection(Some(&source));
-        } else {
-            device.set_selection(Some(&source));
+        let mime_types = data.mime_types();
+        for &useprimary in target.primaries() {
+            self.offer_selection(&qh, &mime_types, useprimary);
         }
+        event_queue
+            .flush()
+            .map_err(|e| WlClipboardListenerError::QueueError(e.to_string()))?;
 
-        self.copy_data = Some(data);
-        while !self.copy_cancelled {
-            event_queue
-                .blocking_dispatch(self)
-                .map_err(|e| WlClipboardListenerError::QueueError(e.to_string()))?;
+        self.copy_source = Some(data);
+        self.copy_pasted = 0;
+        Ok(())
+    }
+
+    /// serve the pastes until all the sources are cancelled, and the pastes are all written
+    /// If copy_timeout is set, the selections will be cleared when it is reached
+    /// If copy_paste_limit is set, the selections will be cleared after that many pastes
+    /// If something is written to wakeup, the selections will be cleared too
+    fn serve_copy(
+        &mut self,
+        mut wakeup: Option<PipeReader>,
+    ) -> Result<CopyEnd, WlClipboardListenerError> {
+        let eventqh = self.queue.clone().unwrap();
+        let mut event_queue = eventqh.lock().unwrap();
+        let deadline = self.copy_timeout.map(|timeout| Instant::now() + timeout);
+        let mut end = CopyEnd::Replaced;
+        let mut cleared = false;
+        while !self.copy_sources.is_empty() || !self.copy_writes.is_empty() {
+            if !cleared && !self.copy_sources.is_empty() {
+                let mut clear = None;
+                if deadline.is_some_and(|deadline| deadline <= Instant::now()) {
+                    clear = Some(CopyEnd::Timeout);
+                }
+                if self.paste_limit_reached() {
+                    clear = Some(CopyEnd::Pasted);
+                }
+                if let Some(reader) = wakeup.as_mut() {
+                    match eventloop::check_wakeup(reader) {
+                        Wakeup::Nothing => {}
+                        Wakeup::Woken => clear = Some(CopyEnd::Revoked),
+                        // the handle is dropped, nobody can revoke it anymore
+                        Wakeup::Closed => wakeup = None,
+                    }
+                }
+                if let Some(reason) = clear {
+                    // clear the selections ourselves, then wait for the cancelled events of our
+                    // sources
+                    let primaries: Vec<bool> = self
+                        .copy_sources
+                        .iter()
+                        .map(|(_, useprimary)| *useprimary)
+                        .collect();
+                    for useprimary in primaries {
+                        self.clear_selection(useprimary);
+                    }
+                    cleared = true;
+                    wakeup = None;
+                    end = reason;
+                }
+            }
+            let timeout = deadline
+                .filter(|_| !cleared)
+                .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            let extra: Vec<(BorrowedFd, PollFlags)> = wakeup
+                .iter()
+                .map(|reader| (reader.as_fd(), PollFlags::POLLIN))
+                .collect();
+            self.dispatch_copy(&mut event_queue, timeout, &extra)?;
         }
-        self.copy_data = None;
-        self.copy_cancelled = false;
+        self.copy_source = None;
+        Ok(end)
+    }
+
+    /// dispatch the events, and write the pastes whose paste side is ready
+    /// it waits for at most timeout, or until one of extra is ready for its flags
+    fn dispatch_copy(
+        &mut self,
+        event_queue: &mut EventQueue<Self>,
+        timeout: Option<Duration>,
+        extra: &[(BorrowedFd, PollFlags)],
+    ) -> Result<(), WlClipboardListenerError> {
+        // wake up in time to drop the pastes that take nothing
+        let timeout = match copy::writes_deadline(&self.copy_writes) {
+            Some(deadline) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                Some(timeout.map_or(left, |timeout| timeout.min(left)))
+            }
+            None => timeout,
+        };
+        // take the writes and the receivings out, so their fds can be polled while dispatching
+        let mut writes = std::mem::take(&mut self.copy_writes);
+        let receiving = std::mem::take(&mut self.receiving);
+        let fds: Vec<(BorrowedFd, PollFlags)> = extra
+            .iter()
+            .copied()
+            .chain(writes.iter().map(PendingWrite::poll_fd))
+            .chain(receiving.iter().flatten().map(Receiving::poll_fd))
+            .collect();
+        let dispatched = eventloop::dispatch_timeout(event_queue, self, timeout, &fds);
+        drop(fds);
+        writes.append(&mut self.copy_writes);
+        self.copy_writes = writes;
+        self.receiving = receiving;
+        dispatched?;
+        copy::flush_writes(&mut self.copy_writes);
         Ok(())
     }
 
-    /// get data from clipboard for once
-    /// it is also used in iter
-    fn get_clipboard_sync(&mut self) -> Result<ClipBoardListenMessage, WlClipboardListenerError> {
-        // get queue, start blocking_dispatch for first loop
-        let queue = self.queue.clone().unwrap();
-        let mut queue = queue
-            .lock()
-            .map_err(|e| WlClipboardListenerError::QueueError(e.to_string()))?;
-        while self.pipereader.is_none() {
-            queue.blocking_dispatch(self)?;
+    /// keep the selections of keep_target alive, see [WlClipboardPersistStream::persist], and
+    /// sync them in sync_direction, see [WlClipboardSyncStream::sync]
+    /// it only returns when there is an error
+    fn keep_selections(&mut self) -> Result<(), WlClipboardListenerError> {
+        loop {
+            self.next_kept_selection()?;
         }
+    }
 
-        // Flush the receive request so the source can start writing, but avoid
-        // a full roundtrip which can race in a newer selection and replace the
-        // pipe reader we are about to consume.
-        queue
-            .flush()
-            .map_err(|e| WlClipboardListenerError::QueueError(e.to_string()))?;
-        let mut read = self.pipereader.as_ref().unwrap();
-        let mut context = vec![];
-        read.read_to_end(&mut context)
-            .map_err(|_| WlClipboardListenerError::PipeError)?;
-        self.pipereader = None;
-        let mime_types = self.mime_types.clone();
-        self.mime_types.clear();
-        let mime_type = self.current_type.clone().unwrap();
-        Ok(ClipBoardListenMessage {
-            mime_types,
-            context: ClipBoardListenContext { mime_type, context },
-        })
+    /// run the keep loop until a new selection offered by others is received
+    fn next_kept_selection(&mut self) -> Result<ClipBoardSelection, WlClipboardListenerError> {
+        let eventqh = self.queue.clone().unwrap();
+        let mut event_queue = eventqh.lock().unwrap();
+        loop {
+            if let Some(KeptSelection::New(selection)) = self.take_kept_selection(&event_queue)? {
+                return Ok(selection);
+            }
+            self.dispatch_copy(&mut event_queue, None, &[])?;
+        }
     }
 
-    /// get data from clipboard for once
-    /// it is also used in iter
-    fn try_get_clipboard(
+    /// the current selection, the compositor sends it when the device is created, so a roundtrip
+    /// is enough to get it, and then it is received
+    fn current_kept_selection(
         &mut self,
-    ) -> Result<Option<ClipBoardListenMessage>, WlClipboardListenerError> {
-        // get queue, start blocking_dispatch for first loop
-        let queue = self.queue.clone().unwrap();
-        let mut queue = queue
-            .lock()
-            .map_err(|e| WlClipboardListenerError::QueueError(e.to_string()))?;
-        queue.blocking_dispatch(self)?;
-        if self.pipereader.is_some() {
-            queue
-                .flush()
-                .map_err(|e| WlClipboardListenerError::QueueError(e.to_string()))?;
-            let mut read = self.pipereader.as_ref().unwrap();
-            let mut context = vec![];
-            read.read_to_end(&mut context)
-                .map_err(|_| WlClipboardListenerError::PipeError)?;
-            self.pipereader = None;
-            let mime_types = self.mime_types.clone();
-            self.mime_types.clear();
-            let mime_type = self.current_type.clone().unwrap();
-            Ok(Some(ClipBoardListenMessage {
-                mime_types,
-                context: ClipBoardListenContext { mime_type, context },
-            }))
-        } else {
-            Ok(None)
+    ) -> Result<Option<ClipBoardSelection>, WlClipboardListenerError> {
+        let eventqh = self.queue.clone().unwrap();
+        let mut event_queue = eventqh.lock().unwrap();
+        event_queue.roundtrip(self)?;
+        loop {
+            match self.take_kept_selection(&event_queue)? {
+                Some(KeptSelection::New(selection)) => return Ok(Some(selection)),
+                Some(KeptSelection::Gone { .. }) => continue,
+                None => {}
+            }
+            if self.receiving.iter().all(Option::is_none) {
+                return Ok(None);
+            }
+            self.dispatch_copy(&mut event_queue, None, &[])?;
         }
     }
 
-    fn device_ready(&self) -> bool {
-        self.seat.is_some() && self.data_manager.is_some()
+    /// like next_kept_selection, but it also tells when a selection is gone, and returns None when
+    /// one of extra is ready for its flags, so the caller can handle it between the selections
+    fn poll_kept_selection(
+        &mut self,
+        extra: &[(BorrowedFd, PollFlags)],
+    ) -> Result<Option<KeptSelection>, WlClipboardListenerError> {
+        let eventqh = self.queue.clone().unwrap();
+        let mut event_queue = eventqh.lock().unwrap();
+        if let Some(selection) = self.take_kept_selection(&event_queue)? {
+            return Ok(Some(selection));
+        }
+        self.dispatch_copy(&mut event_queue, None, extra)?;
+        self.take_kept_selection(&event_queue)
     }
 
-    fn set_data_device(&mut self, qh: &wayland_client::QueueHandle<Self>) {
-        let seat = self.seat.as_ref().unwrap();
-        let manager = self.data_manager.as_ref().unwrap();
-        let device = manager.get_data_device(seat, qh, ());
-
-        self.data_device = Some(device);
+    /// offer payloads as the selections of target by ourselves, they are served like the kept
+    /// selections, so the keep loop serves them until they are taken by others
+    fn set_kept_selection(
+        &mut self,
+        payloads: Payloads,
+        target: WlCopyTarget,
+    ) -> Result<(), WlClipboardListenerError> {
+        let eventqh = self.queue.clone().unwrap();
+        let event_queue = eventqh.lock().unwrap();
+        for &useprimary in target.primaries() {
+            let source = CopySource::from_payloads(payloads.clone());
+            let mime_types = source.mime_types();
+            self.kept_sources[usize::from(useprimary)] = Some(source);
+            self.offer_selection(&event_queue.handle(), &mime_types, useprimary);
+        }
+        event_queue
+            .flush()
+            .map_err(|e| WlClipboardListenerError::QueueError(e.to_string()))
     }
 
-    fn is_text(&self) -> bool {
-        !self.mime_types.is_empty()
-            && self.mime_types.contains(&TEXT.to_string())
-            && !self.mime_types.contains(&IMAGE.to_string())
+    /// clear the selections of target, and forget the kept ones, so they are not offered again
+    fn clear_kept_selection(
+        &mut self,
+        target: WlCopyTarget,
+    ) -> Result<(), WlClipboardListenerError> {
+        let eventqh = self.queue.clone().unwrap();
+        let event_queue = eventqh.lock().unwrap();
+        for &useprimary in target.primaries() {
+            self.kept_sources[usize::from(useprimary)] = None;
+            self.clear_selection(useprimary);
+        }
+        event_queue
+            .flush()
+            .map_err(|e| WlClipboardListenerError::QueueError(e.to_string()))
     }
 
-    fn replace_selection_offer(
+    /// update the changed selections, and return the first new one offered by others once it is
+    /// received, or the first one gone
+    fn take_kept_selection(
         &mut self,
-        offer: Option<ext_data_control_offer_v1::ExtDataControlOfferV1>,
-    ) {
-        if let Some(old_offer) = self.selection_offer.take() {
-            self.offer_mime_types.remove(&old_offer.id().protocol_id());
-            old_offer.destroy();
+        event_queue: &EventQueue<Self>,
+    ) -> Result<Option<KeptSelection>, WlClipboardListenerError> {
+        for useprimary in [false, true] {
+            let index = usize::from(useprimary);
+            if std::mem::take(&mut self.selection_changed[index])
+                && self.watches(useprimary)
+                && self.update_kept_selection(event_queue, useprimary)?
+            {
+                return Ok(Some(KeptSelection::Gone {
+                    primary: useprimary,
+                }));
+            }
+            let Some(payloads) = self.receiving[index]
+                .as_ref()
+                .and_then(Receiving::try_result)
+            else {
+                continue;
+            };
+            self.receiving[index] = None;
+            let gone = KeptSelection::Gone {
+                primary: useprimary,
+            };
+            let Some(payloads) = self.kept_selection_received(event_queue, useprimary, payloads)?
+            else {
+                return Ok(Some(gone));
+            };
+            let Some((payloads, marks)) = self.apply_rules(payloads) else {
+                // like the secrets, the dropped content should not outlive its owner
+                self.kept_sources[index] = None;
+                return Ok(Some(gone));
+            };
+            return Ok(Some(KeptSelection::New(ClipBoardSelection {
+                primary: useprimary,
+                payloads,
+                marks,
+            })));
         }
+        Ok(None)
+    }
 
-        self.mime_types = offer
-            .as_ref()
-            .and_then(|offer| self.offer_mime_types.remove(&offer.id().protocol_id()))
-            .unwrap_or_default();
+    /// if the selections are received by the keep loop
+    fn keeping(&self) -> bool {
+        self.keep_target.is_some() || self.sync_direction.is_some() || self.watch_target.is_some()
+    }
 
-        self.selection_offer = offer;
+    /// if the selection of useprimary is watched, kept or synced
+    fn watches(&self, useprimary: bool) -> bool {
+        [self.watch_target, self.keep_target]
+            .iter()
+            .flatten()
+            .any(|target| target.contains(useprimary))
+            || self.syncs_from(useprimary)
+    }
+
+    fn syncs_from(&self, useprimary: bool) -> bool {
+        self.sync_direction
+            .is_some_and(|direction| direction.syncs_from(useprimary))
+    }
+
+    /// start receiving the new selection, see [Self::kept_selection_received], or offer the kept
+    /// one again if the selection becomes empty
+    /// return true if the selection is gone, it is empty or skipped, and nothing is kept of it
+    fn update_kept_selection(
+        &mut self,
+        event_queue: &EventQueue<Self>,
+        useprimary: bool,
+    ) -> Result<bool, WlClipboardListenerError> {
+        let index = usize::from(useprimary);
+        // the selection is offered by ourselves, ignoring it also stops syncing back and forth
+        if self
+            .copy_sources
+            .iter()
+            .any(|(_, primary)| *primary == useprimary)
+        {
+            self.receiving[index] = None;
+            return Ok(false);
+        }
+        let offer = if useprimary {
+            self.primary_selection_offer.clone()
+        } else {
+            self.selection_offer.clone()
+        };
+        let Some(offer) = offer else {
+            // the owner has exited, or the selection is cleared
+            if !self.keeps(useprimary) {
+                self.receiving[index] = None;
+                return Ok(true);
+            }
+            // the selection being received is offered again once it is received
+            if self.receiving[index].is_some() {
+                return Ok(false);
+            }
+            let Some(kept) = &self.kept_sources[index] else {
+                return Ok(true);
+            };
+            let mime_types = kept.mime_types();
+            self.offer_selection(&event_queue.handle(), &mime_types, useprimary);
+            event_queue
+                .flush()
+                .map_err(|e| WlClipboardListenerError::QueueError(e.to_string()))?;
+            return Ok(false);
+        };
+        // the kept selection is replaced, even if the new one is not received
+        self.kept_sources[index] = None;
+        self.receiving[index] = None;
+        let mime_types = self.selection_mime_types(useprimary);
+        // secrets marked by password managers should not outlive their owners
+        if mime_types.iter().any(|mimetype| mimetype == PASSWORD_HINT) {
+            return Ok(true);
+        }
+        let (mime_types, aliases) = if self.history_mime_types {
+            mime::history_mime_types(&mime_types)
+        } else {
+            (mime_types, Vec::new())
+        };
+        self.receiving[index] = Some(Self::receive_offer(
+            event_queue,
+            &offer,
+            mime_types,
+            aliases,
+        )?);
+        Ok(false)
+    }
+
+    /// keep the received selection, and sync it
+    /// return its data, if it is received
+    fn kept_selection_received(
+        &mut self,
+        event_queue: &EventQueue<Self>,
+        useprimary: bool,
+        payloads: Option<Payloads>,
+    ) -> Result<Option<Payloads>, WlClipboardListenerError> {
+        let Some(payloads) = payloads.filter(|payloads| !payloads.is_empty()) else {
+            return Ok(None);
+        };
+        let source = CopySource::from_payloads(payloads.clone());
+        let mime_types = source.mime_types();
+        self.kept_sources[usize::from(useprimary)] = Some(source);
+        let owner_exited = if useprimary {
+            self.primary_selection_offer.is_none()
+        } else {
+            self.selection_offer.is_none()
+        };
+        if owner_exited && self.keeps(useprimary) {
+            self.offer_selection(&event_queue.handle(), &mime_types, useprimary);
+        }
+
+        if self.syncs_from(useprimary) {
+            // the other selection offers every mimetype of this one, with the same data
+            self.kept_sources[usize::from(!useprimary)] =
+                Some(CopySource::from_payloads(payloads.clone()));
+            self.offer_selection(&event_queue.handle(), &mime_types, !useprimary);
+        }
+        event_queue
+            .flush()
+            .map_err(|e| WlClipboardListenerError::QueueError(e.to_string()))?;
+        Ok(Some(payloads))
+    }
+
+    fn keeps(&self, useprimary: bool) -> bool {
+        self.keep_target
+            .is_some_and(|target| target.contains(useprimary))
+    }
+
+    /// the mimetypes of the current selection
+    fn selection_mime_types(&self, useprimary: bool) -> Vec<String> {
+        if !useprimary {
+            return self.mime_types.clone();
+        }
+        self.primary_selection_offer
+            .as_ref()
+            .and_then(|offer| self.offer_mime_types.get(&offer.id().protocol_id()))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// called when the compositor sends a new selection, a secret is forgotten at once, so it is
+    /// not offered again even if the selection is cleared before the keep loop sees it, like a
+    /// copy marked by [WlClipboardCopyStream::set_secret] when its timeout is reached
+    fn selection_replaced(&mut self, useprimary: bool) {
+        self.selection_changed[usize::from(useprimary)] = true;
+        if self
+            .selection_mime_types(useprimary)
+            .iter()
+            .any(|mimetype| mimetype == PASSWORD_HINT)
+        {
+            self.kept_sources[usize::from(useprimary)] = None;
+        }
+    }
+
+    /// ask the owner of the offer for the data of every mimetype, it is read in a thread, see
+    /// [Receiving]
+    fn receive_offer(
+        event_queue: &EventQueue<Self>,
+        offer: &ext_data_control_offer_v1::ExtDataControlOfferV1,
+        mime_types: Vec<String>,
+        aliases: Vec<String>,
+    ) -> Result<Receiving, WlClipboardListenerError> {
+        let mut pipes = Vec::new();
+        for mime_type in mime_types {
+            let (read, write) = pipe().map_err(|_| WlClipboardListenerError::PipeError)?;
+            offer.receive(mime_type.clone(), write.as_fd());
+            pipes.push((mime_type, read));
+        }
+        event_queue
+            .flush()
+            .map_err(|e| WlClipboardListenerError::QueueError(e.to_string()))?;
+        Receiving::start(pipes, aliases).map_err(|_| WlClipboardListenerError::PipeError)
+    }
+
+    /// the data to serve for source, the kept selection if the source is offered to keep it
+    fn source_data(
+        &mut self,
+        source: &ext_data_control_source_v1::ExtDataControlSourceV1,
+    ) -> Option<&mut CopySource> {
+        if self.copy_source.is_some() {
+            return self.copy_source.as_mut();
+        }
+        let (_, useprimary) = self
+            .copy_sources
+            .iter()
+            .find(|(copy_source, _)| copy_source == source)?;
+        self.kept_sources[usize::from(*useprimary)].as_mut()
+    }
+
+    fn paste_limit_reached(&self) -> bool {
+        self.copy_paste_limit
+            .is_some_and(|limit| self.copy_pasted >= limit)
+    }
+
+    /// get data from clipboard for once
+    /// it is also used in iter
+    fn get_clipboard_sync(&mut self) -> Result<ClipBoardListenMessage, WlClipboardListenerError> {
+        // get queue, start blocking_dispatch for first loop
+        let queue = self.queue.clone().unwrap();
+        let mut queue = queue
+            .lock()
+            .map_err(|e| WlClipboardListenerError::QueueError(e.to_string()))?;
+        loop {
+            while self.pipereader.is_none() {
+                queue.blocking_dispatch(self)?;
+            }
+
+            // Flush the receive request so the source can start writing, but avoid
+            // a full roundtrip which can race in a newer selection and replace the
+            // pipe reader we are about to consume.
+            queue
+                .flush()
+                .map_err(|e| WlClipboardListenerError::QueueError(e.to_string()))?;
+            let mut read = self.pipereader.as_ref().unwrap();
+            let mut context = vec![];
+            read.read_to_end(&mut context)
+                .map_err(|_| WlClipboardListenerError::PipeError)?;
+            self.pipereader = None;
+            let mime_types = self.mime_types.clone();
+            self.mime_types.clear();
+            let mime_type = self.current_type.clone().unwrap();
+            #[cfg(feature = "image-convert")]
+            let (mime_type, context) = self.convert_image_format(mime_type, context);
+            let message = ClipBoardListenMessage {
+                mime_types,
+                context: ClipBoardListenContext { mime_type, context },
+                marks: Vec::new(),
+            };
+            // the dropped content is skipped, wait for the next one
+            if let Some(message) = self.apply_rules_to_message(message) {
+                return Ok(message);
+            }
+        }
+    }
+
+    /// get data from clipboard for once
+    /// it is also used in iter
+    fn try_get_clipboard(
+        &mut self,
+    ) -> Result<Option<ClipBoardListenMessage>, WlClipboardListenerError> {
+        // get queue, start blocking_dispatch for first loop
+        let queue = self.queue.clone().unwrap();
+        let mut queue = queue
+            .lock()
+            .map_err(|e| WlClipboardListenerError::QueueError(e.to_string()))?;
+        queue.blocking_dispatch(self)?;
+        if self.pipereader.is_some() {
+            queue
+                .flush()
+                .map_err(|e| WlClipboardListenerError::QueueError(e.to_string()))?;
+            let mut read = self.pipereader.as_ref().unwrap();
+            let mut context = vec![];
+            read.read_to_end(&mut context)
+                .map_err(|_| WlClipboardListenerError::PipeError)?;
+            self.pipereader = None;
+            let mime_types = self.mime_types.clone();
+            self.mime_types.clear();
+            let mime_type = self.current_type.clone().unwrap();
+            #[cfg(feature = "image-convert")]
+            let (mime_type, context) = self.convert_image_format(mime_type, context);
+            Ok(self.apply_rules_to_message(ClipBoardListenMessage {
+                mime_types,
+                context: ClipBoardListenContext { mime_type, context },
+                marks: Vec::new(),
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// clear the selection, set a null source to the device
+    /// it will do a roundtrip, so when it returns, the compositor has handled it
+    fn clear(&mut self, useprimary: bool) -> Result<(), WlClipboardListenerError> {
+        let eventqh = self.queue.clone().unwrap();
+        let mut event_queue = eventqh.lock().unwrap();
+        self.clear_selection(useprimary);
+        event_queue
+            .roundtrip(self)
+            .map_err(|e| WlClipboardListenerError::QueueError(e.to_string()))?;
+        Ok(())
+    }
+
+    fn device_ready(&self) -> bool {
+        self.seat.is_some() && self.data_manager.is_some()
+    }
+
+    fn set_data_device(&mut self, qh: &wayland_client::QueueHandle<Self>) {
+        let seat = self.seat.as_ref().unwrap();
+        let manager = self.data_manager.as_ref().unwrap();
+        let device = manager.get_data_device(seat, qh, ());
+
+        self.data_device = Some(device);
+    }
+
+    fn set_device_selection(
+        device: &ext_data_control_device_v1::ExtDataControlDeviceV1,
+        source: Option<&ext_data_control_source_v1::ExtDataControlSourceV1>,
+        useprimary: bool,
+    ) {
+        if useprimary {
+            device.set_primary_selection(source);
+        } else {
+            device.set_selection(source);
+        }
+    }
+
+    /// clear the selection
+    fn clear_selection(&mut self, useprimary: bool) {
+        Self::set_device_selection(self.data_device.as_ref().unwrap(), None, useprimary);
+    }
+
+    /// create a source offering mime_types, and set it as the selection
+    fn offer_selection(
+        &mut self,
+        qh: &wayland_client::QueueHandle<Self>,
+        mime_types: &[String],
+        useprimary: bool,
+    ) {
+        let manager = self.data_manager.as_ref().unwrap();
+        let source = manager.create_data_source(qh, ());
+        for mimetype in mime_types {
+            source.offer(mimetype.clone());
+        }
+        Self::set_device_selection(
+            self.data_device.as_ref().unwrap(),
+            Some(&source),
+            useprimary,
+        );
+        self.copy_sources.push((source, useprimary));
+    }
+
+    /// if an image is going to be received, and image_format is offered too, receive it instead
+    #[cfg(feature = "image-convert")]
+    fn prefer_image_format(&self, mimetype: String) -> String {
+        match &self.image_format {
+            Some(format) if mimetype.starts_with("image/") && self.mime_types.contains(format) => {
+                format.clone()
+            }
+            _ => mimetype,
+        }
+    }
+
+    /// convert the received image to image_format, if it is set and the image is another format
+    #[cfg(feature = "image-convert")]
+    fn convert_image_format(&self, mime_type: String, context: Vec<u8>) -> (String, Vec<u8>) {
+        let Some(format) = self.image_format.as_ref() else {
+            return (mime_type, context);
+        };
+        if *format == mime_type || !image_convert::can_convert(&mime_type) {
+            return (mime_type, context);
+        }
+        match image_convert::convert_image(&context, &mime_type, format) {
+            Ok(converted) => (format.clone(), converted),
+            Err(e) => {
+                log::warn!("failed to convert {mime_type} to {format}: {e}");
+                (mime_type, context)
+            }
+        }
+    }
+
+    /// apply the rules to the received payloads, return None if they are dropped
+    fn apply_rules(&self, payloads: Payloads) -> Option<(Payloads, Vec<String>)> {
+        match &self.rules {
+            Some(rules) => rules.apply_payloads(payloads, Vec::new()),
+            None => Some((payloads, Vec::new())),
+        }
+    }
+
+    fn apply_rules_to_message(
+        &self,
+        message: ClipBoardListenMessage,
+    ) -> Option<ClipBoardListenMessage> {
+        match &self.rules {
+            Some(rules) => rules.apply_message(message),
+            None => Some(message),
+        }
+    }
+
+    fn is_text(&self) -> bool {
+        !self.mime_types.is_empty()
+            && self.mime_types.contains(&TEXT.to_string())
+            && !self.mime_types.contains(&IMAGE.to_string())
+    }
+
+    fn replace_selection_offer(
+        &mut self,
+        offer: Option<ext_data_control_offer_v1::ExtDataControlOfferV1>,
+    ) {
+        if let Some(old_offer) = self.selection_offer.take() {
+            self.offer_mime_types.remove(&old_offer.id().protocol_id());
+            old_offer.destroy();
+        }
+
+        self.mime_types = offer
+            .as_ref()
+            .and_then(|offer| self.offer_mime_types.remove(&offer.id().protocol_id()))
+            .unwrap_or_default();
+
+        self.selection_offer = offer;
     }
 
     fn replace_primary_selection_offer(
@@ -564,6 +1614,18 @@ impl WlClipboardPasteStreamWlr {
     pub fn set_priority(&mut self, val: Vec<String>) {
         self.inner.set_priority = Some(val);
     }
+
+    /// apply rules to the received content, the dropped content is skipped
+    pub fn set_rules(&mut self, rules: Option<ContentRules>) {
+        self.inner.rules = rules;
+    }
+
+    /// Set the image format wanted, like "image/jpeg" (only applies when using ListenOnCopy)
+    /// if an image is received, and the format is not offered, the image is converted to it
+    #[cfg(feature = "image-convert")]
+    pub fn set_image_format(&mut self, format: Option<String>) {
+        self.inner.image_format = format;
+    }
 }
 
 /// copy stream,
@@ -582,10 +1644,32 @@ impl WlClipboardCopyStreamWlr {
         })
     }
 
+    /// Set a timeout for copy, useful for secrets like passwords
+    /// if no one else takes the selection before the timeout, the selection will be cleared, and
+    /// copy_to_clipboard will return
+    /// mark the copy as a secret too, or a persist stream will offer it again, see set_secret
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.inner.copy_timeout = timeout;
+    }
+
+    /// Serve only limit pastes, then the selection will be cleared, and copy_to_clipboard will
+    /// return, like `wl-copy --paste-once` when the limit is 1, useful for one-time tokens
+    pub fn set_paste_limit(&mut self, limit: Option<usize>) {
+        self.inner.copy_paste_limit = limit;
+    }
+
+    /// offer the selection with the `x-kde-passwordManagerHint` of password managers, so the
+    /// clipboard managers and [WlClipboardPersistStreamWlr] do not keep it
+    pub fn set_secret(&mut self, secret: bool) {
+        self.inner.copy_secret = secret;
+    }
+
     /// it will run a never end loop, to handle the paste event, like what wl-copy do
     /// it will live until next copy event happened
-    /// you need to pass data and if use useprimary to it,
-    /// if is useprimary, you can use the middle button of mouse to paste
+    /// you need to pass data and the target to it, see [WlCopyTarget]
+    /// data can be a [`Vec<u8>`], or an [`Arc<[u8]>`] if you want to share the buffer
+    /// target can also be a bool as useprimary, if is useprimary, you can use the middle button
+    /// of mouse to paste
     /// Take [primary-selection](https://patchwork.freedesktop.org/patch/257267/) as reference
     /// ``` rust, no_run
     /// use wayland_clipboard_listener::{WlClipboardCopyStreamWlr, WlClipboardListenerError};
@@ -600,11 +1684,341 @@ impl WlClipboardCopyStreamWlr {
     ///```
     pub fn copy_to_clipboard(
         &mut self,
-        data: Vec<u8>,
+        data: impl Into<Arc<[u8]>>,
         mimetypes: Vec<&str>,
-        useprimary: bool,
+        target: impl Into<WlCopyTarget>,
+    ) -> Result<(), WlClipboardListenerError> {
+        self.inner
+            .copy_to_clipboard(CopySource::new(data, mimetypes), target.into())?;
+        Ok(())
+    }
+
+    /// like copy_to_clipboard, but every mimetype has its own data, you can pass a HashMap, or a
+    /// Vec of (mimetype, data) if the order of the mimetypes matters
+    /// the mimetypes are offered in order, and a paste is served with the data of the requested
+    /// mimetype
+    /// ``` rust, no_run
+    /// use wayland_clipboard_listener::WlClipboardCopyStreamWlr;
+    /// let mut stream = WlClipboardCopyStreamWlr::init().unwrap();
+    /// stream
+    ///     .copy_payloads_to_clipboard(
+    ///         vec![
+    ///             ("text/html".to_string(), b"<b>hello</b>".to_vec()),
+    ///             ("text/plain".to_string(), b"hello".to_vec()),
+    ///         ],
+    ///         false,
+    ///     )
+    ///     .unwrap();
+    ///```
+    pub fn copy_payloads_to_clipboard<P: Into<Arc<[u8]>>>(
+        &mut self,
+        payloads: impl IntoIterator<Item = (String, P)>,
+        target: impl Into<WlCopyTarget>,
+    ) -> Result<(), WlClipboardListenerError> {
+        self.inner
+            .copy_to_clipboard(CopySource::from_payloads(payloads), target.into())?;
+        Ok(())
+    }
+
+    /// like copy_to_clipboard, but the data is made by the provider when someone pastes, see
+    /// [ClipboardDataProvider]
+    pub fn copy_provider_to_clipboard(
+        &mut self,
+        provider: impl ClipboardDataProvider + Send + 'static,
+        target: impl Into<WlCopyTarget>,
+    ) -> Result<(), WlClipboardListenerError> {
+        self.inner
+            .copy_to_clipboard(CopySource::from_provider(provider), target.into())?;
+        Ok(())
+    }
+
+    /// copy a [CopySource] to clipboard, like copy_to_clipboard, it lives until the selection is
+    /// taken by others
+    /// ``` rust, no_run
+    /// use wayland_clipboard_listener::{CopySource, WlClipboardCopyStreamWlr};
+    /// let mut stream = WlClipboardCopyStreamWlr::init().unwrap();
+    /// let source = CopySource::open("/tmp/large.png", vec!["image/png"]).unwrap();
+    /// stream.copy_source_to_clipboard(source, false).unwrap();
+    ///```
+    pub fn copy_source_to_clipboard(
+        &mut self,
+        source: CopySource,
+        target: impl Into<WlCopyTarget>,
+    ) -> Result<(), WlClipboardListenerError> {
+        self.inner.copy_to_clipboard(source, target.into())?;
+        Ok(())
+    }
+
+    /// like copy_to_clipboard, but it returns once the selection is set, the pastes are served in
+    /// a background thread, use the returned [CopyHandle] to wait or revoke it
+    /// ``` rust, no_run
+    /// use wayland_clipboard_listener::{CopySource, WlClipboardCopyStreamWlr};
+    /// let stream = WlClipboardCopyStreamWlr::init().unwrap();
+    /// let handle = stream
+    ///     .copy_in_background(CopySource::new(b"hello".to_vec(), vec!["text/plain"]), false)
+    ///     .unwrap();
+    /// // do other things
+    /// if handle.is_active() {
+    ///     handle.revoke().unwrap();
+    /// }
+    ///```
+    pub fn copy_in_background(
+        mut self,
+        source: CopySource,
+        target: impl Into<WlCopyTarget>,
+    ) -> Result<CopyHandle, WlClipboardListenerError> {
+        self.inner.start_copy(source, target.into())?;
+        let (reader, writer) = pipe().map_err(|_| WlClipboardListenerError::PipeError)?;
+        let thread = std::thread::spawn(move || self.inner.serve_copy(Some(reader)));
+        Ok(CopyHandle {
+            thread,
+            wakeup: writer,
+        })
+    }
+
+    /// clear the clipboard, after that, there is nothing to paste, even if
+    /// [WlClipboardPersistStreamWlr] is running
+    pub fn clear(&mut self) -> Result<(), WlClipboardListenerError> {
+        self.inner.clear(false)
+    }
+
+    /// clear the primary selection, the one pasted by the middle button of mouse
+    pub fn clear_primary(&mut self) -> Result<(), WlClipboardListenerError> {
+        self.inner.clear(true)
+    }
+}
+
+/// persist stream
+/// it keeps the selections alive when their owners exit, like wl-clip-persist, so the text copied
+/// from firefox can still be pasted after firefox is killed
+/// every mimetype of a new selection is received and kept, when the selection becomes empty, the
+/// kept data is offered again by ourselves
+/// a selection not received in 5 seconds, or larger than 64 MiB, is not kept
+#[cfg(feature = "wlr-data-control")]
+pub struct WlClipboardPersistStreamWlr {
+    inner: WlClipboardListenerStreamWlr,
+}
+
+#[cfg(feature = "wlr-data-control")]
+impl WlClipboardPersistStreamWlr {
+    /// init a persist stream
+    pub fn init() -> Result<Self, WlClipboardListenerError> {
+        Ok(Self {
+            inner: WlClipboardListenerStreamWlr::init(WlListenType::ListenOnCopy)?,
+        })
+    }
+
+    /// keep the selections of target alive, it runs a never end loop, and only returns when there
+    /// is an error
+    /// Note: a cleared selection is offered again too, as it cannot be told from an exited owner,
+    /// except the secrets marked by password managers, they are never kept, so a copy marked by
+    /// [WlClipboardCopyStreamWlr::set_secret] is not offered again when its timeout or paste limit
+    /// clears it
+    /// ``` rust, no_run
+    /// use wayland_clipboard_listener::{WlClipboardPersistStreamWlr, WlCopyTarget};
+    /// let mut stream = WlClipboardPersistStreamWlr::init().unwrap();
+    /// stream.persist(WlCopyTarget::Both).unwrap();
+    ///```
+    pub fn persist(
+        &mut self,
+        target: impl Into<WlCopyTarget>,
     ) -> Result<(), WlClipboardListenerError> {
-        self.inner.copy_to_clipboard(data, mimetypes, useprimary)
+        self.inner.keep_target = Some(target.into());
+        self.inner.keep_selections()
+    }
+}
+
+/// sync stream
+/// it syncs the clipboard and the primary selection, like the X11 tools, see [WlSyncDirection]
+/// every mimetype is synced with its data, so images and rich text are synced too
+#[cfg(feature = "wlr-data-control")]
+pub struct WlClipboardSyncStreamWlr {
+    inner: WlClipboardListenerStreamWlr,
+}
+
+#[cfg(feature = "wlr-data-control")]
+impl WlClipboardSyncStreamWlr {
+    /// init a sync stream
+    pub fn init() -> Result<Self, WlClipboardListenerError> {
+        Ok(Self {
+            inner: WlClipboardListenerStreamWlr::init(WlListenType::ListenOnCopy)?,
+        })
+    }
+
+    /// also keep the selections of target alive when syncing, see
+    /// [WlClipboardPersistStreamWlr::persist]
+    pub fn set_persist(&mut self, target: Option<WlCopyTarget>) {
+        self.inner.keep_target = target;
+    }
+
+    /// sync the selections in direction, it runs a never end loop, and only returns when there is
+    /// an error
+    /// the synced selection is offered by ourselves, so it lives until it is taken by others
+    /// ``` rust, no_run
+    /// use wayland_clipboard_listener::{WlClipboardSyncStreamWlr, WlSyncDirection};
+    /// let mut stream = WlClipboardSyncStreamWlr::init().unwrap();
+    /// stream.sync(WlSyncDirection::PrimaryToClipboard).unwrap();
+    ///```
+    pub fn sync(&mut self, direction: WlSyncDirection) -> Result<(), WlClipboardListenerError> {
+        self.inner.sync_direction = Some(direction);
+        self.inner.keep_selections()
+    }
+}
+
+/// selection stream
+/// it provides a iter of the new selections, with the data of all their mimetypes, see
+/// [ClipBoardSelection], so a clipboard history can copy them again, see [ClipboardHistory]
+/// ``` rust, no_run
+/// use wayland_clipboard_listener::{ClipboardHistory, WlClipboardSelectionStreamWlr, WlCopyTarget};
+/// let mut history = ClipboardHistory::new(100);
+/// let mut stream = WlClipboardSelectionStreamWlr::init(WlCopyTarget::Clipboard).unwrap();
+/// for selection in stream.flatten() {
+///     history.record(selection);
+/// }
+///```
+#[cfg(feature = "wlr-data-control")]
+pub struct WlClipboardSelectionStreamWlr {
+    inner: WlClipboardListenerStreamWlr,
+}
+
+#[cfg(feature = "wlr-data-control")]
+impl WlClipboardSelectionStreamWlr {
+    /// init a selection stream, to watch the selections of target
+    pub fn init(target: impl Into<WlCopyTarget>) -> Result<Self, WlClipboardListenerError> {
+        let mut inner = WlClipboardListenerStreamWlr::init(WlListenType::ListenOnCopy)?;
+        inner.watch_target = Some(target.into());
+        Ok(Self { inner })
+    }
+
+    /// also keep the selections of target alive, see [WlClipboardPersistStreamWlr::persist]
+    pub fn set_persist(&mut self, target: Option<WlCopyTarget>) {
+        self.inner.keep_target = target;
+    }
+
+    /// also sync the selections in direction, see [WlClipboardSyncStreamWlr::sync]
+    /// the synced selection is offered by ourselves, so it is not yielded again
+    pub fn set_sync(&mut self, direction: Option<WlSyncDirection>) {
+        self.inner.sync_direction = direction;
+    }
+
+    /// apply rules to the selections, the dropped ones are skipped, and are not kept alive
+    pub fn set_rules(&mut self, rules: Option<ContentRules>) {
+        self.inner.rules = rules;
+    }
+
+    /// wait for the next selection
+    /// the secrets marked by password managers, and the selections dropped by the rules are
+    /// skipped
+    pub fn get_selection(&mut self) -> Result<ClipBoardSelection, WlClipboardListenerError> {
+        self.inner.next_kept_selection()
+    }
+
+    /// get the current selection without waiting, call it before [Self::get_selection]
+    /// return None if it is empty, or skipped like in [Self::get_selection]
+    pub fn get_current_selection(
+        &mut self,
+    ) -> Result<Option<ClipBoardSelection>, WlClipboardListenerError> {
+        self.inner.current_kept_selection()
+    }
+}
+
+#[cfg(feature = "wlr-data-control")]
+impl Iterator for WlClipboardSelectionStreamWlr {
+    type Item = Result<ClipBoardSelection, WlClipboardListenerError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let data = self.get_selection();
+        if let Err(WlClipboardListenerError::DispatchError(err)) = data.as_ref() {
+            panic!("error with wayland side: {err}");
+        }
+        Some(data)
+    }
+}
+
+/// daemon stream
+/// it owns one connection, and serves the clipboard to the clients of a unix socket, so they need
+/// no wayland connection of their own, and do not fork to keep their copies alive, see
+/// [ClipboardClient]
+/// the clients get the selections, set and clear them, search the history, and subscribe to the
+/// changes, the protocol is JSON Lines, see the request methods of [ClipboardClient]
+/// the new selections are received in a thread, so the clients are served meanwhile, a text is
+/// received once even if it is offered as several mimetypes, and an image in one format
+/// ``` rust, no_run
+/// use wayland_clipboard_listener::{daemon_socket_path, HistoryStore, WlClipboardDaemonStreamWlr};
+/// let mut stream = WlClipboardDaemonStreamWlr::init(daemon_socket_path().unwrap()).unwrap();
+/// stream.set_history(Some(HistoryStore::open("/tmp/history").unwrap()));
+/// stream.run().unwrap();
+///```
+#[cfg(feature = "wlr-data-control")]
+pub struct WlClipboardDaemonStreamWlr {
+    inner: WlClipboardListenerStreamWlr,
+    server: Server,
+}
+
+#[cfg(feature = "wlr-data-control")]
+impl WlClipboardDaemonStreamWlr {
+    /// init a daemon stream listening on the socket at path, see [daemon_socket_path]
+    /// a socket left by a dead daemon is replaced, but it fails if another daemon is listening
+    /// the dir of path is created if it does not exist, it fails if the others can write it, and
+    /// the clients run by the others are refused
+    pub fn init(path: impl AsRef<Path>) -> Result<Self, WlClipboardListenerError> {
+        let path = path.as_ref();
+        let server = Server::bind(path).map_err(|e| {
+            WlClipboardListenerError::InitFailed(format!("Cannot listen on {path:?}: {e}"))
+        })?;
+        let mut inner = WlClipboardListenerStreamWlr::init(WlListenType::ListenOnCopy)?;
+        inner.watch_target = Some(WlCopyTarget::Both);
+        inner.history_mime_types = true;
+        Ok(Self { inner, server })
+    }
+
+    /// also keep the selections of target alive, see [WlClipboardPersistStreamWlr::persist]
+    pub fn set_persist(&mut self, target: Option<WlCopyTarget>) {
+        self.inner.keep_target = target;
+    }
+
+    /// apply rules to the selections, see [WlClipboardSelectionStreamWlr::set_rules]
+    pub fn set_rules(&mut self, rules: Option<ContentRules>) {
+        self.inner.rules = rules;
+    }
+
+    /// keep the clipboard in the history, the clients can search it and select the entries again
+    /// the selected texts of the primary selection are not kept
+    /// an encrypted history can be given locked, it is unlocked by `ClipboardClient::unlock`,
+    /// and nothing is kept until then
+    pub fn set_history(&mut self, history: Option<HistoryStore>) {
+        self.server.history = history;
+    }
+
+    /// the bytes of the events waiting for a slow subscriber, 4 MiB by default, beyond it the
+    /// oldest ones are dropped, and the subscriber is told by [ClipboardEvent::Lagged]
+    pub fn set_backlog(&mut self, bytes: usize) {
+        self.server.backlog = bytes;
+    }
+
+    /// serve the clients, it runs a never end loop, and only returns when there is an error
+    pub fn run(&mut self) -> Result<(), WlClipboardListenerError> {
+        if let Some(selection) = self.inner.current_kept_selection()? {
+            self.server.selection_changed(selection);
+        }
+        loop {
+            let fds = self.server.poll_fds();
+            let selection = self.inner.poll_kept_selection(&fds)?;
+            drop(fds);
+            match selection {
+                Some(KeptSelection::New(selection)) => self.server.selection_changed(selection),
+                Some(KeptSelection::Gone { primary }) => self.server.selection_gone(primary),
+                None => {}
+            }
+            for action in self.server.serve() {
+                match action {
+                    DaemonAction::Set(payloads, target) => {
+                        self.inner.set_kept_selection(payloads, target)?
+                    }
+                    DaemonAction::Clear(target) => self.inner.clear_kept_selection(target)?,
+                }
+            }
+        }
     }
 }
 
@@ -626,8 +2040,25 @@ pub struct WlClipboardListenerStreamWlr {
     pipereader: Option<os_pipe::PipeReader>,
     current_type: Option<String>,
     queue: Option<Arc<Mutex<EventQueue<Self>>>>,
-    copy_data: Option<Vec<u8>>,
-    copy_cancelled: bool,
+    copy_source: Option<CopySource>,
+    copy_sources: Vec<(zwlr_data_control_source_v1::ZwlrDataControlSourceV1, bool)>,
+    copy_timeout: Option<Duration>,
+    copy_secret: bool,
+    copy_paste_limit: Option<usize>,
+    copy_pasted: usize,
+    copy_writes: Vec<PendingWrite>,
+    #[cfg(feature = "image-convert")]
+    image_format: Option<String>,
+    keep_target: Option<WlCopyTarget>,
+    watch_target: Option<WlCopyTarget>,
+    sync_direction: Option<WlSyncDirection>,
+    selection_changed: [bool; 2],
+    kept_sources: [Option<CopySource>; 2],
+    /// the selections being received from others, see [Receiving]
+    receiving: [Option<Receiving>; 2],
+    /// only receive the mimetypes kept in a history, see [mime::history_mime_types]
+    history_mime_types: bool,
+    rules: Option<ContentRules>,
 }
 
 #[cfg(feature = "wlr-data-control")]
@@ -672,8 +2103,23 @@ impl WlClipboardListenerStreamWlr {
             pipereader: None,
             current_type: None,
             queue: None,
-            copy_data: None,
-            copy_cancelled: false,
+            copy_source: None,
+            copy_sources: Vec::new(),
+            copy_timeout: None,
+            copy_secret: false,
+            copy_paste_limit: None,
+            copy_pasted: 0,
+            copy_writes: Vec::new(),
+            #[cfg(feature = "image-convert")]
+            image_format: None,
+            keep_target: None,
+            watch_target: None,
+            sync_direction: None,
+            selection_changed: [false; 2],
+            kept_sources: [None, None],
+            receiving: [None, None],
+            history_mime_types: false,
+            rules: None,
         };
 
         event_queue.blocking_dispatch(&mut state).map_err(|e| {
@@ -698,41 +2144,463 @@ impl WlClipboardListenerStreamWlr {
     }
 
     /// copy data to stream
-    /// pass [Vec<u8>] as data
-    /// now it can just copy text
+    /// every mimetype of [CopySource] is offered, and served on demand
     /// It will always live in the background, so you need to handle it yourself
     fn copy_to_clipboard(
         &mut self,
-        data: Vec<u8>,
-        mimetypes: Vec<&str>,
-        useprimary: bool,
+        data: CopySource,
+        target: WlCopyTarget,
+    ) -> Result<CopyEnd, WlClipboardListenerError> {
+        self.start_copy(data, target)?;
+        self.serve_copy(None)
+    }
+
+    /// create sources with the mimetypes of data, and set them as the selections of target
+    /// every selection needs its own source
+    fn start_copy(
+        &mut self,
+        mut data: CopySource,
+        target: WlCopyTarget,
     ) -> Result<(), WlClipboardListenerError> {
         let eventqh = self.queue.clone().unwrap();
-        let mut event_queue = eventqh.lock().unwrap();
+        let event_queue = eventqh.lock().unwrap();
         let qh = event_queue.handle();
-        let manager = self.data_manager.as_ref().unwrap();
-        let source = manager.create_data_source(&qh, ());
-        let device = self.data_device.as_ref().unwrap();
 
-        for mimetype in mimetypes {
-            source.offer(mimetype.to_string());
+        if self.copy_secret {
+            data.set_secret();
         }
+        let mime_types = data.mime_types();
+        for &useprimary in target.primaries() {
+            self.offer_selection(&qh, &mime_types, useprimary);
+        }
+        event_queue
+            .flush()
+            .map_err(|e| WlClipboardListenerError::QueueError(e.to_string()))?;
 
-        if useprimary {
-            device.set_primary_selection(Some(&source));
-        } else {
-            device.set_selection(Some(&source));
+        self.copy_source = Some(data);
+        self.copy_pasted = 0;
+        Ok(())
+    }
+
+    /// serve the pastes until all the sources are cancelled, and the pastes are all written
+    /// If copy_timeout is set, the selections will be cleared when it is reached
+    /// If copy_paste_limit is set, the selections will be cleared after that many pastes
+    /// If something is written to wakeup, the selections will be cleared too
+    fn serve_copy(
+        &mut self,
+        mut wakeup: Option<PipeReader>,
+    ) -> Result<CopyEnd, WlClipboardListenerError> {
+        let eventqh = self.queue.clone().unwrap();
+        let mut event_queue = eventqh.lock().unwrap();
+        let deadline = self.copy_timeout.map(|timeout| Instant::now() + timeout);
+        let mut end = CopyEnd::Replaced;
+        let mut cleared = false;
+        while !self.copy_sources.is_empty() || !self.copy_writes.is_empty() {
+            if !cleared && !self.copy_sources.is_empty() {
+                let mut clear = None;
+                if deadline.is_some_and(|deadline| deadline <= Instant::now()) {
+                    clear = Some(CopyEnd::Timeout);
+                }
+                if self.paste_limit_reached() {
+                    clear = Some(CopyEnd::Pasted);
+                }
+                if let Some(reader) = wakeup.as_mut() {
+                    match eventloop::check_wakeup(reader) {
+                        Wakeup::Nothing => {}
+                        Wakeup::Woken => clear = Some(CopyEnd::Revoked),
+                        // the handle is dropped, nobody can revoke it anymore
+                        Wakeup::Closed => wakeup = None,
+                    }
+                }
+                if let Some(reason) = clear {
+                    // clear the selections ourselves, then wait for the cancelled events of our
+                    // sources
+                    let primaries: Vec<bool> = self
+                        .copy_sources
+                        .iter()
+                        .map(|(_, useprimary)| *useprimary)
+                        .collect();
+                    for useprimary in primaries {
+                        self.clear_selection(useprimary);
+                    }
+                    cleared = true;
+                    wakeup = None;
+                    end = reason;
+                }
+            }
+            let timeout = deadline
+                .filter(|_| !cleared)
+                .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            let extra: Vec<(BorrowedFd, PollFlags)> = wakeup
+                .iter()
+                .map(|reader| (reader.as_fd(), PollFlags::POLLIN))
+                .collect();
+            self.dispatch_copy(&mut event_queue, timeout, &extra)?;
+        }
+        self.copy_source = None;
+        Ok(end)
+    }
+
+    /// dispatch the events, and write the pastes whose paste side is ready
+    /// it waits for at most timeout, or until one of extra is ready for its flags
+    fn dispatch_copy(
+        &mut self,
+        event_queue: &mut EventQueue<Self>,
+        timeout: Option<Duration>,
+        extra: &[(BorrowedFd, PollFlags)],
+    ) -> Result<(), WlClipboardListenerError> {
+        // wake up in time to drop the pastes that take nothing
+        let timeout = match copy::writes_deadline(&self.copy_writes) {
+            Some(deadline) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                Some(timeout.map_or(left, |timeout| timeout.min(left)))
+            }
+            None => timeout,
+        };
+        // take the writes and the receivings out, so their fds can be polled while dispatching
+        let mut writes = std::mem::take(&mut self.copy_writes);
+        let receiving = std::mem::take(&mut self.receiving);
+        let fds: Vec<(BorrowedFd, PollFlags)> = extra
+            .iter()
+            .copied()
+            .chain(writes.iter().map(PendingWrite::poll_fd))
+            .chain(receiving.iter().flatten().map(Receiving::poll_fd))
+            .collect();
+        let dispatched = eventloop::dispatch_timeout(event_queue, self, timeout, &fds);
+        drop(fds);
+        writes.append(&mut self.copy_writes);
+        self.copy_writes = writes;
+        self.receiving = receiving;
+        dispatched?;
+        copy::flush_writes(&mut self.copy_writes);
+        Ok(())
+    }
+
+    /// keep the selections of keep_target alive, see [WlClipboardPersistStreamWlr::persist], and
+    /// sync them in sync_direction, see [WlClipboardSyncStreamWlr::sync]
+    /// it only returns when there is an error
+    fn keep_selections(&mut self) -> Result<(), WlClipboardListenerError> {
+        loop {
+            self.next_kept_selection()?;
+        }
+    }
+
+    /// run the keep loop until a new selection offered by others is received
+    fn next_kept_selection(&mut self) -> Result<ClipBoardSelection, WlClipboardListenerError> {
+        let eventqh = self.queue.clone().unwrap();
+        let mut event_queue = eventqh.lock().unwrap();
+        loop {
+            if let Some(KeptSelection::New(selection)) = self.take_kept_selection(&event_queue)? {
+                return Ok(selection);
+            }
+            self.dispatch_copy(&mut event_queue, None, &[])?;
+        }
+    }
+
+    /// the current selection, the compositor sends it when the device is created, so a roundtrip
+    /// is enough to get it, and then it is received
+    fn current_kept_selection(
+        &mut self,
+    ) -> Result<Option<ClipBoardSelection>, WlClipboardListenerError> {
+        let eventqh = self.queue.clone().unwrap();
+        let mut event_queue = eventqh.lock().unwrap();
+        event_queue.roundtrip(self)?;
+        loop {
+            match self.take_kept_selection(&event_queue)? {
+                Some(KeptSelection::New(selection)) => return Ok(Some(selection)),
+                Some(KeptSelection::Gone { .. }) => continue,
+                None => {}
+            }
+            if self.receiving.iter().all(Option::is_none) {
+                return Ok(None);
+            }
+            self.dispatch_copy(&mut event_queue, None, &[])?;
+        }
+    }
+
+    /// like next_kept_selection, but it also tells when a selection is gone, and returns None when
+    /// one of extra is ready for its flags, so the caller can handle it between the selections
+    fn poll_kept_selection(
+        &mut self,
+        extra: &[(BorrowedFd, PollFlags)],
+    ) -> Result<Option<KeptSelection>, WlClipboardListenerError> {
+        let eventqh = self.queue.clone().unwrap();
+        let mut event_queue = eventqh.lock().unwrap();
+        if let Some(selection) = self.take_kept_selection(&event_queue)? {
+            return Ok(Some(selection));
+        }
+        self.dispatch_copy(&mut event_queue, None, extra)?;
+        self.take_kept_selection(&event_queue)
+    }
+
+    /// offer payloads as the selections of target by ourselves, they are served like the kept
+    /// selections, so the keep loop serves them until they are taken by others
+    fn set_kept_selection(
+        &mut self,
+        payloads: Payloads,
+        target: WlCopyTarget,
+    ) -> Result<(), WlClipboardListenerError> {
+        let eventqh = self.queue.clone().unwrap();
+        let event_queue = eventqh.lock().unwrap();
+        for &useprimary in target.primaries() {
+            let source = CopySource::from_payloads(payloads.clone());
+            let mime_types = source.mime_types();
+            self.kept_sources[usize::from(useprimary)] = Some(source);
+            self.offer_selection(&event_queue.handle(), &mime_types, useprimary);
         }
+        event_queue
+            .flush()
+            .map_err(|e| WlClipboardListenerError::QueueError(e.to_string()))
+    }
+
+    /// clear the selections of target, and forget the kept ones, so they are not offered again
+    fn clear_kept_selection(
+        &mut self,
+        target: WlCopyTarget,
+    ) -> Result<(), WlClipboardListenerError> {
+        let eventqh = self.queue.clone().unwrap();
+        let event_queue = eventqh.lock().unwrap();
+        for &useprimary in target.primaries() {
+            self.kept_sources[usize::from(useprimary)] = None;
+            self.clear_selection(useprimary);
+        }
+        event_queue
+            .flush()
+            .map_err(|e| WlClipboardListenerError::QueueError(e.to_string()))
+    }
+
+    /// update the changed selections, and return the first new one offered by others once it is
+    /// received, or the first one gone
+    fn take_kept_selection(
+        &mut self,
+        event_queue: &EventQueue<Self>,
+    ) -> Result<Option<KeptSelection>, WlClipboardListenerError> {
+        for useprimary in [false, true] {
+            let index = usize::from(useprimary);
+            if std::mem::take(&mut self.selection_changed[index])
+                && self.watches(useprimary)
+                && self.update_kept_selection(event_queue, useprimary)?
+            {
+                return Ok(Some(KeptSelection::Gone {
+                    primary: useprimary,
+                }));
+            }
+            let Some(payloads) = self.receiving[index]
+                .as_ref()
+                .and_then(Receiving::try_result)
+            else {
+                continue;
+            };
+            self.receiving[index] = None;
+            let gone = KeptSelection::Gone {
+                primary: useprimary,
+            };
+            let Some(payloads) = self.kept_selection_received(event_queue, useprimary, payloads)?
+            else {
+                return Ok(Some(gone));
+            };
+            let Some((payloads, marks)) = self.apply_rules(payloads) else {
+                // like the secrets, the dropped content should not outlive its owner
+                self.kept_sources[index] = None;
+                return Ok(Some(gone));
+            };
+            return Ok(Some(KeptSelection::New(ClipBoardSelection {
+                primary: useprimary,
+                payloads,
+                marks,
+            })));
+        }
+        Ok(None)
+    }
+
+    /// if the selections are received by the keep loop
+    fn keeping(&self) -> bool {
+        self.keep_target.is_some() || self.sync_direction.is_some() || self.watch_target.is_some()
+    }
 
-        self.copy_data = Some(data);
-        while !self.copy_cancelled {
+    /// if the selection of useprimary is watched, kept or synced
+    fn watches(&self, useprimary: bool) -> bool {
+        [self.watch_target, self.keep_target]
+            .iter()
+            .flatten()
+            .any(|target| target.contains(useprimary))
+            || self.syncs_from(useprimary)
+    }
+
+    fn syncs_from(&self, useprimary: bool) -> bool {
+        self.sync_direction
+            .is_some_and(|direction| direction.syncs_from(useprimary))
+    }
+
+    /// start receiving the new selection, see [Self::kept_selection_received], or offer the kept
+    /// one again if the selection becomes empty
+    /// return true if the selection is gone, it is empty or skipped, and nothing is kept of it
+    fn update_kept_selection(
+        &mut self,
+        event_queue: &EventQueue<Self>,
+        useprimary: bool,
+    ) -> Result<bool, WlClipboardListenerError> {
+        let index = usize::from(useprimary);
+        // the selection is offered by ourselves, ignoring it also stops syncing back and forth
+        if self
+            .copy_sources
+            .iter()
+            .any(|(_, primary)| *primary == useprimary)
+        {
+            self.receiving[index] = None;
+            return Ok(false);
+        }
+        let offer = if useprimary {
+            self.primary_selection_offer.clone()
+        } else {
+            self.selection_offer.clone()
+        };
+        let Some(offer) = offer else {
+            // the owner has exited, or the selection is cleared
+            if !self.keeps(useprimary) {
+                self.receiving[index] = None;
+                return Ok(true);
+            }
+            // the selection being received is offered again once it is received
+            if self.receiving[index].is_some() {
+                return Ok(false);
+            }
+            let Some(kept) = &self.kept_sources[index] else {
+                return Ok(true);
+            };
+            let mime_types = kept.mime_types();
+            self.offer_selection(&event_queue.handle(), &mime_types, useprimary);
             event_queue
-                .blocking_dispatch(self)
+                .flush()
                 .map_err(|e| WlClipboardListenerError::QueueError(e.to_string()))?;
+            return Ok(false);
+        };
+        // the kept selection is replaced, even if the new one is not received
+        self.kept_sources[index] = None;
+        self.receiving[index] = None;
+        let mime_types = self.selection_mime_types(useprimary);
+        // secrets marked by password managers should not outlive their owners
+        if mime_types.iter().any(|mimetype| mimetype == PASSWORD_HINT) {
+            return Ok(true);
         }
-        self.copy_data = None;
-        self.copy_cancelled = false;
-        Ok(())
+        let (mime_types, aliases) = if self.history_mime_types {
+            mime::history_mime_types(&mime_types)
+        } else {
+            (mime_types, Vec::new())
+        };
+        self.receiving[index] = Some(Self::receive_offer(
+            event_queue,
+            &offer,
+            mime_types,
+            aliases,
+        )?);
+        Ok(false)
+    }
+
+    /// keep the received selection, and sync it
+    /// return its data, if it is received
+    fn kept_selection_received(
+        &mut self,
+        event_queue: &EventQueue<Self>,
+        useprimary: bool,
+        payloads: Option<Payloads>,
+    ) -> Result<Option<Payloads>, WlClipboardListenerError> {
+        let Some(payloads) = payloads.filter(|payloads| !payloads.is_empty()) else {
+            return Ok(None);
+        };
+        let source = CopySource::from_payloads(payloads.clone());
+        let mime_types = source.mime_types();
+        self.kept_sources[usize::from(useprimary)] = Some(source);
+        let owner_exited = if useprimary {
+            self.primary_selection_offer.is_none()
+        } else {
+            self.selection_offer.is_none()
+        };
+        if owner_exited && self.keeps(useprimary) {
+            self.offer_selection(&event_queue.handle(), &mime_types, useprimary);
+        }
+
+        if self.syncs_from(useprimary) {
+            // the other selection offers every mimetype of this one, with the same data
+            self.kept_sources[usize::from(!useprimary)] =
+                Some(CopySource::from_payloads(payloads.clone()));
+            self.offer_selection(&event_queue.handle(), &mime_types, !useprimary);
+        }
+        event_queue
+            .flush()
+            .map_err(|e| WlClipboardListenerError::QueueError(e.to_string()))?;
+        Ok(Some(payloads))
+    }
+
+    fn keeps(&self, useprimary: bool) -> bool {
+        self.keep_target
+            .is_some_and(|target| target.contains(useprimary))
+    }
+
+    /// the mimetypes of the current selection
+    fn selection_mime_types(&self, useprimary: bool) -> Vec<String> {
+        if !useprimary {
+            return self.mime_types.clone();
+        }
+        self.primary_selection_offer
+            .as_ref()
+            .and_then(|offer| self.offer_mime_types.get(&offer.id().protocol_id()))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// called when the compositor sends a new selection, a secret is forgotten at once, so it is
+    /// not offered again even if the selection is cleared before the keep loop sees it, like a
+    /// copy marked by [WlClipboardCopyStreamWlr::set_secret] when its timeout is reached
+    fn selection_replaced(&mut self, useprimary: bool) {
+        self.selection_changed[usize::from(useprimary)] = true;
+        if self
+            .selection_mime_types(useprimary)
+            .iter()
+            .any(|mimetype| mimetype == PASSWORD_HINT)
+        {
+            self.kept_sources[usize::from(useprimary)] = None;
+        }
+    }
+
+    /// ask the owner of the offer for the data of every mimetype, it is read in a thread, see
+    /// [Receiving]
+    fn receive_offer(
+        event_queue: &EventQueue<Self>,
+        offer: &zwlr_data_control_offer_v1::ZwlrDataControlOfferV1,
+        mime_types: Vec<String>,
+        aliases: Vec<String>,
+    ) -> Result<Receiving, WlClipboardListenerError> {
+        let mut pipes = Vec::new();
+        for mime_type in mime_types {
+            let (read, write) = pipe().map_err(|_| WlClipboardListenerError::PipeError)?;
+            offer.receive(mime_type.clone(), write.as_fd());
+            pipes.push((mime_type, read));
+        }
+        event_queue
+            .flush()
+            .map_err(|e| WlClipboardListenerError::QueueError(e.to_string()))?;
+        Receiving::start(pipes, aliases).map_err(|_| WlClipboardListenerError::PipeError)
+    }
+
+    /// the data to serve for source, the kept selection if the source is offered to keep it
+    fn source_data(
+        &mut self,
+        source: &zwlr_data_control_source_v1::ZwlrDataControlSourceV1,
+    ) -> Option<&mut CopySource> {
+        if self.copy_source.is_some() {
+            return self.copy_source.as_mut();
+        }
+        let (_, useprimary) = self
+            .copy_sources
+            .iter()
+            .find(|(copy_source, _)| copy_source == source)?;
+        self.kept_sources[usize::from(*useprimary)].as_mut()
+    }
+
+    fn paste_limit_reached(&self) -> bool {
+        self.copy_paste_limit
+            .is_some_and(|limit| self.copy_pasted >= limit)
     }
 
     /// get data from clipboard for once
@@ -743,28 +2611,37 @@ impl WlClipboardListenerStreamWlr {
         let mut queue = queue
             .lock()
             .map_err(|e| WlClipboardListenerError::QueueError(e.to_string()))?;
-        while self.pipereader.is_none() {
-            queue.blocking_dispatch(self)?;
+        loop {
+            while self.pipereader.is_none() {
+                queue.blocking_dispatch(self)?;
+            }
+
+            // Flush the receive request so the source can start writing, but avoid
+            // a full roundtrip which can race in a newer selection and replace the
+            // pipe reader we are about to consume.
+            queue
+                .flush()
+                .map_err(|e| WlClipboardListenerError::QueueError(e.to_string()))?;
+            let mut read = self.pipereader.as_ref().unwrap();
+            let mut context = vec![];
+            read.read_to_end(&mut context)
+                .map_err(|_| WlClipboardListenerError::PipeError)?;
+            self.pipereader = None;
+            let mime_types = self.mime_types.clone();
+            self.mime_types.clear();
+            let mime_type = self.current_type.clone().unwrap();
+            #[cfg(feature = "image-convert")]
+            let (mime_type, context) = self.convert_image_format(mime_type, context);
+            let message = ClipBoardListenMessage {
+                mime_types,
+                context: ClipBoardListenContext { mime_type, context },
+                marks: Vec::new(),
+            };
+            // the dropped content is skipped, wait for the next one
+            if let Some(message) = self.apply_rules_to_message(message) {
+                return Ok(message);
+            }
         }
-
-        // Flush the receive request so the source can start writing, but avoid
-        // a full roundtrip which can race in a newer selection and replace the
-        // pipe reader we are about to consume.
-        queue
-            .flush()
-            .map_err(|e| WlClipboardListenerError::QueueError(e.to_string()))?;
-        let mut read = self.pipereader.as_ref().unwrap();
-        let mut context = vec![];
-        read.read_to_end(&mut context)
-            .map_err(|_| WlClipboardListenerError::PipeError)?;
-        self.pipereader = None;
-        let mime_types = self.mime_types.clone();
-        self.mime_types.clear();
-        let mime_type = self.current_type.clone().unwrap();
-        Ok(ClipBoardListenMessage {
-            mime_types,
-            context: ClipBoardListenContext { mime_type, context },
-        })
     }
 
     /// get data from clipboard for once
@@ -790,15 +2667,30 @@ impl WlClipboardListenerStreamWlr {
             let mime_types = self.mime_types.clone();
             self.mime_types.clear();
             let mime_type = self.current_type.clone().unwrap();
-            Ok(Some(ClipBoardListenMessage {
+            #[cfg(feature = "image-convert")]
+            let (mime_type, context) = self.convert_image_format(mime_type, context);
+            Ok(self.apply_rules_to_message(ClipBoardListenMessage {
                 mime_types,
                 context: ClipBoardListenContext { mime_type, context },
+                marks: Vec::new(),
             }))
         } else {
             Ok(None)
         }
     }
 
+    /// clear the selection, set a null source to the device
+    /// it will do a roundtrip, so when it returns, the compositor has handled it
+    fn clear(&mut self, useprimary: bool) -> Result<(), WlClipboardListenerError> {
+        let eventqh = self.queue.clone().unwrap();
+        let mut event_queue = eventqh.lock().unwrap();
+        self.clear_selection(useprimary);
+        event_queue
+            .roundtrip(self)
+            .map_err(|e| WlClipboardListenerError::QueueError(e.to_string()))?;
+        Ok(())
+    }
+
     fn device_ready(&self) -> bool {
         self.seat.is_some() && self.data_manager.is_some()
     }
@@ -811,6 +2703,90 @@ impl WlClipboardListenerStreamWlr {
         self.data_device = Some(device);
     }
 
+    fn set_device_selection(
+        device: &zwlr_data_control_device_v1::ZwlrDataControlDeviceV1,
+        source: Option<&zwlr_data_control_source_v1::ZwlrDataControlSourceV1>,
+        useprimary: bool,
+    ) {
+        if useprimary {
+            device.set_primary_selection(source);
+        } else {
+            device.set_selection(source);
+        }
+    }
+
+    /// clear the selection
+    fn clear_selection(&mut self, useprimary: bool) {
+        Self::set_device_selection(self.data_device.as_ref().unwrap(), None, useprimary);
+    }
+
+    /// create a source offering mime_types, and set it as the selection
+    fn offer_selection(
+        &mut self,
+        qh: &wayland_client::QueueHandle<Self>,
+        mime_types: &[String],
+        useprimary: bool,
+    ) {
+        let manager = self.data_manager.as_ref().unwrap();
+        let source = manager.create_data_source(qh, ());
+        for mimetype in mime_types {
+            source.offer(mimetype.clone());
+        }
+        Self::set_device_selection(
+            self.data_device.as_ref().unwrap(),
+            Some(&source),
+            useprimary,
+        );
+        self.copy_sources.push((source, useprimary));
+    }
+
+    /// if an image is going to be received, and image_format is offered too, receive it instead
+    #[cfg(feature = "image-convert")]
+    fn prefer_image_format(&self, mimetype: String) -> String {
+        match &self.image_format {
+            Some(format) if mimetype.starts_with("image/") && self.mime_types.contains(format) => {
+                format.clone()
+            }
+            _ => mimetype,
+        }
+    }
+
+    /// convert the received image to image_format, if it is set and the image is another format
+    #[cfg(feature = "image-convert")]
+    fn convert_image_format(&self, mime_type: String, context: Vec<u8>) -> (String, Vec<u8>) {
+        let Some(format) = self.image_format.as_ref() else {
+            return (mime_type, context);
+        };
+        if *format == mime_type || !image_convert::can_convert(&mime_type) {
+            return (mime_type, context);
+        }
+        match image_convert::convert_image(&context, &mime_type, format) {
+            Ok(converted) => (format.clone(), converted),
+            Err(e) => {
+                log::warn!("failed to convert {mime_type} to {format}: {e}");
+                (mime_type, context)
+            }
+        }
+    }
+
+    /// apply the rules to the received payloads, return None if they are dropped
+    fn apply_rules(&self, payloads: Payloads) -> Option<(Payloads, Vec<String>)> {
+        match &self.rules {
+            Some(rules) => rules.apply_payloads(payloads, Vec::new()),
+            None => Some((payloads, Vec::new())),
+        }
+    }
+
+    fn apply_rules_to_message(
+        &self,
+        message: ClipBoardListenMessage,
+    ) -> Option<ClipBoardListenMessage> {
+        match &self.rules {
+            Some(rules) => rules.apply_message(message),
+            None => Some(message),
+        }
+    }
+
     fn is_text(&self) -> bool {
         !self.mime_types.is_empty()
             && self.mime_types.contains(&TEXT.to_string())
@@ -0,0 +1,232 @@
+//! guess the mimetypes of the data to copy
+//! binary data is recognized by the magic bytes at the beginning, and text by being valid UTF-8
+
+/// the mimetypes offered for UTF-8 text, the X11 ones are for apps running on XWayland
+pub const TEXT_MIME_TYPES: [&str; 4] = [
+    "text/plain;charset=utf-8",
+    "text/plain",
+    "UTF8_STRING",
+    "TEXT",
+];
+
+const BINARY_MIME_TYPE: &str = "application/octet-stream";
+
+/// magic bytes at the beginning of the data
+const MAGIC: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"II*\0", "image/tiff"),
+    (b"MM\0*", "image/tiff"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (b"\xfd7zXZ\0", "application/x-xz"),
+    (b"OggS", "audio/ogg"),
+    (b"fLaC", "audio/flac"),
+    (b"\x1a\x45\xdf\xa3", "video/webm"),
+];
+
+/// guess the mimetypes of data, and return them in the order to offer
+/// data can be just the beginning of the content, like the first few KB of a file, it is treated
+/// as text if it is valid UTF-8, a character cut at the end is allowed
+/// ```rust
+/// use wayland_clipboard_listener::detect_mime_types;
+///
+/// assert_eq!(detect_mime_types(b"\x89PNG\r\n\x1a\n..."), vec!["image/png"]);
+/// assert_eq!(detect_mime_types("hello".as_bytes())[0], "text/plain;charset=utf-8");
+/// ```
+pub fn detect_mime_types(data: &[u8]) -> Vec<&'static str> {
+    if let Some(mimetype) = detect_binary(data) {
+        return vec![mimetype];
+    }
+    if !is_text(data) {
+        return vec![BINARY_MIME_TYPE];
+    }
+
+    let head = String::from_utf8_lossy(&data[..data.len().min(512)]).to_ascii_lowercase();
+    let head = head.trim_start();
+    let mut mimetypes = Vec::new();
+    if head.starts_with("<svg") || (head.starts_with("<?xml") && head.contains("<svg")) {
+        mimetypes.push("image/svg+xml");
+    } else if head.starts_with("<!doctype html") || head.starts_with("<html") {
+        mimetypes.push("text/html");
+    }
+    mimetypes.extend(TEXT_MIME_TYPES);
+    mimetypes
+}
+
+/// if mimetype matches pattern, a mimetype, or a prefix ending with "*", like "image/*"
+pub(crate) fn mime_matches(pattern: &str, mimetype: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => mimetype.starts_with(prefix),
+        None => mimetype == pattern,
+    }
+}
+
+/// the mimetypes of a selection to receive for a history, and the text aliases
+/// a text is often offered as several mimetypes, it is received once, and the other mimetypes of
+/// [TEXT_MIME_TYPES] are aliases of its data, the text in other encodings is not received
+/// an image is often offered in several formats converted on every paste, only one is received,
+/// png if it is offered, the other mimetypes are all received
+pub(crate) fn history_mime_types(offered: &[String]) -> (Vec<String>, Vec<String>) {
+    let text = TEXT_MIME_TYPES
+        .iter()
+        .find(|text| offered.iter().any(|mimetype| mimetype == *text));
+    let image = offered
+        .iter()
+        .find(|mimetype| *mimetype == "image/png")
+        .or_else(|| {
+            offered
+                .iter()
+                .find(|mimetype| mimetype.starts_with("image/"))
+        });
+    let mut mime_types = Vec::new();
+    let mut aliases = Vec::new();
+    for mimetype in offered {
+        if mime_types.contains(mimetype) || aliases.contains(mimetype) {
+            continue;
+        }
+        if let Some(text) = text {
+            if TEXT_MIME_TYPES.contains(&mimetype.as_str()) && mimetype != text {
+                aliases.push(mimetype.clone());
+                continue;
+            }
+            if ["STRING", "COMPOUND_TEXT"].contains(&mimetype.as_str()) {
+                continue;
+            }
+        }
+        if mimetype.starts_with("image/") && Some(mimetype) != image {
+            continue;
+        }
+        mime_types.push(mimetype.clone());
+    }
+    (mime_types, aliases)
+}
+
+fn detect_binary(data: &[u8]) -> Option<&'static str> {
+    if let Some((_, mimetype)) = MAGIC.iter().find(|(magic, _)| data.starts_with(magic)) {
+        return Some(mimetype);
+    }
+    // containers with the type after the size
+    if data.len() >= 12 {
+        match (&data[0..4], &data[8..12]) {
+            (b"RIFF", b"WEBP") => return Some("image/webp"),
+            (b"RIFF", b"WAVE") => return Some("audio/wav"),
+            (_, b"avif") if &data[4..8] == b"ftyp" => return Some("image/avif"),
+            (_, b"heic") if &data[4..8] == b"ftyp" => return Some("image/heic"),
+            _ => {}
+        }
+    }
+    // "BM" alone is too short, text can also start with it, so check the reserved bytes
+    if data.len() >= 14 && data.starts_with(b"BM") && data[6..10] == [0; 4] {
+        return Some("image/bmp");
+    }
+    None
+}
+
+fn is_text(data: &[u8]) -> bool {
+    let valid = match std::str::from_utf8(data) {
+        Ok(_) => true,
+        // the data is cut in the middle of a character
+        Err(e) => e.error_len().is_none(),
+    };
+    valid && !data.contains(&0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(mime_types: &[&str]) -> Vec<String> {
+        mime_types
+            .iter()
+            .map(|mimetype| mimetype.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn the_magic_bytes_are_recognized() {
+        for (magic, mimetype) in MAGIC {
+            let mut data = magic.to_vec();
+            data.extend_from_slice(b"the rest of the data");
+            assert_eq!(detect_mime_types(&data), [*mimetype]);
+        }
+        assert_eq!(detect_mime_types(b"RIFF\x24\0\0\0WEBPVP8 "), ["image/webp"]);
+        assert_eq!(detect_mime_types(b"RIFF\x24\0\0\0WAVEfmt "), ["audio/wav"]);
+        assert_eq!(detect_mime_types(b"\0\0\0\x1cftypavif"), ["image/avif"]);
+        assert_eq!(detect_mime_types(b"\0\0\0\x18ftypheic"), ["image/heic"]);
+        // the other brands and riff types are not known
+        assert_eq!(detect_mime_types(b"\0\0\0\x18ftypisom"), [BINARY_MIME_TYPE]);
+        assert_eq!(
+            detect_mime_types(b"RIFF\x24\0\0\0AVI LIST"),
+            [BINARY_MIME_TYPE]
+        );
+        // cut before the type
+        assert_eq!(detect_mime_types(b"RIFF\x24\0\0\0WEB"), [BINARY_MIME_TYPE]);
+    }
+
+    #[test]
+    fn a_bmp_has_zero_reserved_bytes() {
+        let mut bmp = b"BM\x36\0\0\0\0\0\0\0\x36\0\0\0".to_vec();
+        assert_eq!(detect_mime_types(&bmp), ["image/bmp"]);
+        bmp[6] = 1;
+        assert_eq!(detect_mime_types(&bmp), [BINARY_MIME_TYPE]);
+        assert_eq!(detect_mime_types(b"BMW is a car maker"), TEXT_MIME_TYPES);
+    }
+
+    #[test]
+    fn a_character_cut_at_the_end_is_text() {
+        let text = "café".as_bytes();
+        let cut = &text[..text.len() - 1];
+        assert_eq!(detect_mime_types(cut), TEXT_MIME_TYPES);
+        // but not in the middle
+        let broken = [cut, b" au lait"].concat();
+        assert_eq!(detect_mime_types(&broken), [BINARY_MIME_TYPE]);
+    }
+
+    #[test]
+    fn a_nul_is_binary() {
+        assert_eq!(detect_mime_types(b"hello\0world"), [BINARY_MIME_TYPE]);
+    }
+
+    #[test]
+    fn the_markup_is_offered_first() {
+        let html = detect_mime_types(b"  <!DOCTYPE html><html></html>");
+        assert_eq!(html[0], "text/html");
+        assert_eq!(html[1..], TEXT_MIME_TYPES);
+        let svg = detect_mime_types(b"<?xml version=\"1.0\"?><svg></svg>");
+        assert_eq!(svg[0], "image/svg+xml");
+    }
+
+    #[test]
+    fn a_text_is_received_once() {
+        let offered = strings(&[
+            "text/html",
+            "text/plain",
+            "UTF8_STRING",
+            "STRING",
+            "text/plain;charset=utf-8",
+            "TEXT",
+        ]);
+        let (mime_types, aliases) = history_mime_types(&offered);
+        assert_eq!(mime_types, ["text/html", "text/plain;charset=utf-8"]);
+        assert_eq!(aliases, ["text/plain", "UTF8_STRING", "TEXT"]);
+        // the other encodings are kept if there is no UTF-8 text
+        let (mime_types, aliases) = history_mime_types(&strings(&["STRING"]));
+        assert_eq!(mime_types, ["STRING"]);
+        assert!(aliases.is_empty());
+    }
+
+    #[test]
+    fn an_image_is_received_in_one_format() {
+        let offered = strings(&["image/bmp", "image/png", "image/jpeg", "text/uri-list"]);
+        let (mime_types, aliases) = history_mime_types(&offered);
+        assert_eq!(mime_types, ["image/png", "text/uri-list"]);
+        assert!(aliases.is_empty());
+        let offered = strings(&["image/bmp", "image/jpeg"]);
+        assert_eq!(history_mime_types(&offered).0, ["image/bmp"]);
+    }
+}
@@ -0,0 +1,282 @@
+//! fuzzy search over the history
+//! the text of the entries is matched with the query like fzf does, the best matches first, and
+//! the entries can be filtered by the kind of their content and the time they were copied
+
+use std::ops::Range;
+use std::time::SystemTime;
+
+use nucleo_matcher::pattern::{CaseMatching, Normalization, Pattern};
+use nucleo_matcher::{Config, Matcher, Utf32Str};
+
+use crate::history::{ClipboardHistory, HistoryEntry};
+
+/// the kind of content of a mimetype
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MimeClass {
+    /// text/*, and the X11 text types
+    Text,
+    /// image/*
+    Image,
+    /// a list of files, like text/uri-list
+    Files,
+    /// anything else
+    Other,
+}
+
+impl MimeClass {
+    pub fn of(mime_type: &str) -> Self {
+        match mime_type {
+            "text/uri-list" | "x-special/gnome-copied-files" => Self::Files,
+            "UTF8_STRING" | "STRING" | "TEXT" | "COMPOUND_TEXT" => Self::Text,
+            _ if mime_type.starts_with("text/") => Self::Text,
+            _ if mime_type.starts_with("image/") => Self::Image,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// what to search, the entries matching all of the filters are searched
+#[derive(Debug, Clone, Default)]
+pub struct HistoryQuery {
+    text: String,
+    mime_class: Option<MimeClass>,
+    since: Option<SystemTime>,
+    until: Option<SystemTime>,
+    limit: Option<usize>,
+}
+
+impl HistoryQuery {
+    /// search text, like fzf, words separated by spaces are matched one by one, and `'word`,
+    /// `^prefix`, `suffix$` and `!not` can be used
+    /// an empty text matches every entry, the newest first
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            ..Self::default()
+        }
+    }
+
+    /// only search the entries with a payload of mime_class
+    pub fn set_mime_class(&mut self, mime_class: Option<MimeClass>) {
+        self.mime_class = mime_class;
+    }
+
+    /// only search the entries copied at or after since
+    pub fn set_since(&mut self, since: Option<SystemTime>) {
+        self.since = since;
+    }
+
+    /// only search the entries copied at or before until
+    pub fn set_until(&mut self, until: Option<SystemTime>) {
+        self.until = until;
+    }
+
+    /// return at most limit matches
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+    }
+
+    fn filters(&self, entry: &HistoryEntry) -> bool {
+        self.mime_class.is_none_or(|class| {
+            entry
+                .mime_types()
+                .into_iter()
+                .any(|mimetype| MimeClass::of(mimetype) == class)
+        }) && self.since.is_none_or(|since| entry.timestamp() >= since)
+            && self.until.is_none_or(|until| entry.timestamp() <= until)
+    }
+}
+
+/// an entry matching the query
+#[derive(Debug, Clone)]
+pub struct SearchMatch<'a> {
+    pub entry: &'a HistoryEntry,
+    /// the higher the better
+    pub score: u32,
+    /// the byte ranges of [HistoryEntry::text] matching the query, to highlight
+    pub highlights: Vec<Range<usize>>,
+}
+
+/// search the history, the best matches first, and the newest first for the same score
+pub(crate) fn search<'a>(
+    history: &'a ClipboardHistory,
+    query: &HistoryQuery,
+) -> Vec<SearchMatch<'a>> {
+    let pattern = Pattern::parse(&query.text, CaseMatching::Smart, Normalization::Smart);
+    let mut matcher = Matcher::new(Config::DEFAULT);
+    let mut buf = Vec::new();
+    let mut indices = Vec::new();
+    let mut matches: Vec<SearchMatch> = history
+        .entries()
+        .filter(|entry| query.filters(entry))
+        .filter_map(|entry| {
+            if pattern.atoms.is_empty() {
+                return Some(SearchMatch {
+                    entry,
+                    score: 0,
+                    highlights: Vec::new(),
+                });
+            }
+            let text = entry.text()?;
+            indices.clear();
+            let score =
+                pattern.indices(Utf32Str::new(text, &mut buf), &mut matcher, &mut indices)?;
+            Some(SearchMatch {
+                entry,
+                score,
+                highlights: highlights(text, &mut indices),
+            })
+        })
+        .collect();
+    // the sort is stable, so the newer one stays first
+    matches.sort_by_key(|found| std::cmp::Reverse(found.score));
+    if let Some(limit) = query.limit {
+        matches.truncate(limit);
+    }
+    matches
+}
+
+/// the byte ranges of the chars at indices of text, the adjacent ones are merged
+fn highlights(text: &str, indices: &mut Vec<u32>) -> Vec<Range<usize>> {
+    indices.sort_unstable();
+    indices.dedup();
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    let mut wanted = indices.iter().peekable();
+    for (index, (start, c)) in text.char_indices().enumerate() {
+        let Some(next) = wanted.peek() else {
+            break;
+        };
+        if **next as usize != index {
+            continue;
+        }
+        wanted.next();
+        let end = start + c.len_utf8();
+        match ranges.last_mut() {
+            Some(last) if last.end == start => last.end = end,
+            _ => ranges.push(start..end),
+        }
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn insert(history: &mut ClipboardHistory, mimetype: &str, data: &str, secs: u64) {
+        let payloads = vec![(mimetype.to_string(), data.as_bytes().into())];
+        assert!(history.insert_entry(payloads, at(secs), Vec::new(), false));
+    }
+
+    fn texts(matches: &[SearchMatch]) -> Vec<String> {
+        matches
+            .iter()
+            .map(|found| String::from_utf8_lossy(&found.entry.payloads()[0].1).into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn mime_classes() {
+        assert_eq!(MimeClass::of("text/plain;charset=utf-8"), MimeClass::Text);
+        assert_eq!(MimeClass::of("UTF8_STRING"), MimeClass::Text);
+        assert_eq!(MimeClass::of("text/uri-list"), MimeClass::Files);
+        assert_eq!(
+            MimeClass::of("x-special/gnome-copied-files"),
+            MimeClass::Files
+        );
+        assert_eq!(MimeClass::of("image/png"), MimeClass::Image);
+        assert_eq!(MimeClass::of("application/pdf"), MimeClass::Other);
+    }
+
+    #[test]
+    fn highlights_are_byte_ranges() {
+        // é and ö take two bytes, 世 three
+        let text = "héllo wörld 世界";
+        let ranges = highlights(text, &mut vec![2, 1, 7, 8, 1]);
+        assert_eq!(ranges, [1..4, 8..11]);
+        assert_eq!(&text[1..4], "él");
+        assert_eq!(&text[8..11], "ör");
+        assert_eq!(highlights(text, &mut vec![12, 13]), vec![14..20]);
+        assert_eq!(&text[14..20], "世界");
+        assert!(highlights(text, &mut Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn search_highlights_multi_byte_text() {
+        let mut history = ClipboardHistory::new(10);
+        insert(&mut history, "text/plain", "héllo wörld", 1);
+        let matches = history.search(&HistoryQuery::new("'wör"));
+        assert_eq!(matches.len(), 1);
+        let text = matches[0].entry.text().unwrap();
+        let highlighted: Vec<&str> = matches[0]
+            .highlights
+            .iter()
+            .map(|range| &text[range.clone()])
+            .collect();
+        assert_eq!(highlighted, ["wör"]);
+    }
+
+    #[test]
+    fn since_and_until_include_their_ends() {
+        let mut history = ClipboardHistory::new(10);
+        insert(&mut history, "text/plain", "one", 100);
+        insert(&mut history, "text/plain", "two", 200);
+        insert(&mut history, "text/plain", "three", 300);
+
+        let mut query = HistoryQuery::new("");
+        query.set_since(Some(at(200)));
+        assert_eq!(texts(&history.search(&query)), ["three", "two"]);
+        query.set_since(None);
+        query.set_until(Some(at(200)));
+        assert_eq!(texts(&history.search(&query)), ["two", "one"]);
+        query.set_since(Some(at(150)));
+        assert_eq!(texts(&history.search(&query)), ["two"]);
+        query.set_since(Some(at(301)));
+        query.set_until(None);
+        assert!(history.search(&query).is_empty());
+    }
+
+    #[test]
+    fn mime_class_filter() {
+        let mut history = ClipboardHistory::new(10);
+        insert(&mut history, "text/plain", "notes", 1);
+        insert(&mut history, "image/png", "png", 2);
+        insert(&mut history, "text/uri-list", "file:///tmp/notes", 3);
+
+        let mut query = HistoryQuery::new("");
+        query.set_mime_class(Some(MimeClass::Image));
+        assert_eq!(texts(&history.search(&query)), ["png"]);
+        query.set_mime_class(Some(MimeClass::Files));
+        assert_eq!(texts(&history.search(&query)), ["file:///tmp/notes"]);
+        query.set_mime_class(Some(MimeClass::Other));
+        assert!(history.search(&query).is_empty());
+        // the images have no text, so only the text is searched
+        let mut query = HistoryQuery::new("notes");
+        query.set_mime_class(Some(MimeClass::Text));
+        assert_eq!(texts(&history.search(&query)), ["notes"]);
+    }
+
+    #[test]
+    fn equal_scores_keep_the_newest_first() {
+        let mut history = ClipboardHistory::new(10);
+        insert(&mut history, "text/plain", "abc 1", 1);
+        insert(&mut history, "text/plain", "xyz", 2);
+        insert(&mut history, "text/plain", "abc 2", 3);
+        insert(&mut history, "text/plain", "a_b_c", 4);
+
+        let matches = history.search(&HistoryQuery::new("abc"));
+        assert_eq!(texts(&matches), ["abc 2", "abc 1", "a_b_c"]);
+        assert_eq!(matches[0].score, matches[1].score);
+        assert!(matches[1].score > matches[2].score);
+
+        let mut query = HistoryQuery::new("abc");
+        query.set_limit(Some(1));
+        assert_eq!(texts(&history.search(&query)), ["abc 2"]);
+    }
+}
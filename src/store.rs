@@ -0,0 +1,1220 @@
+//! persistent clipboard history
+//! the history is kept in a directory, so it survives logout
+//! * `history.log` is a log of the changes to the history, one JSON record per line after a
+//!   header with the version. It is only appended, and rewritten when it grows too long compared
+//!   to the history
+//! * `blobs/` keeps the data of every mimetype, named by its sha256, so the same data is stored
+//!   once, even if it is in many entries
+//!
+//! The registers and the pinned entries are kept in the log too, and the history may hold
+//! passwords, so the directories are made `0700` and the files `0600`
+//!
+//! A blob is written to a temporary file and renamed before the record using it is appended, and
+//! every record is synced to the disk, so a crash can only cut the last record, which is dropped
+//! when the store is opened again
+//!
+//! With the `encryption` feature, the records and blobs can be encrypted, see [HistoryKey]. An
+//! encrypted store is locked when it is opened, nothing is read or written until it is unlocked
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, DirBuilder, File, OpenOptions, Permissions};
+use std::io::{self, BufRead, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use nix::fcntl::{Flock, FlockArg};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::copy::Payloads;
+#[cfg(feature = "encryption")]
+use crate::crypt::{Cipher, HistoryKey};
+use crate::history::{from_hex, is_register_name, to_hex, ClipboardHistory, HistoryEntry};
+use crate::{ClipBoardSelection, CopySource, HistoryQuery, SearchMatch};
+
+pub(crate) const CRYPT_FILE: &str = "crypt.json";
+const LOG_FILE: &str = "history.log";
+const LOCK_FILE: &str = "lock";
+const BLOB_DIR: &str = "blobs";
+const VERSION: u32 = 1;
+/// the log is compacted when it has this many more records than twice the entries
+const COMPACT_SLACK: usize = 64;
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    /// the records and blobs are encrypted
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    encrypted: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Record {
+    /// a new entry, time is in milliseconds since the epoch
+    Add {
+        id: u64,
+        time: u64,
+        payloads: Vec<BlobRef>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        marks: Vec<String>,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        pinned: bool,
+    },
+    /// an entry copied again, it is moved to the top, marks are all its marks if it has new
+    /// ones
+    Touch {
+        id: u64,
+        time: u64,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        marks: Vec<String>,
+    },
+    Remove {
+        id: u64,
+    },
+    /// remove all the entries, but the pinned ones
+    Clear,
+    Pin {
+        id: u64,
+        pinned: bool,
+    },
+    /// a selection put in the register name
+    Register {
+        name: char,
+        time: u64,
+        payloads: Vec<BlobRef>,
+    },
+    Unregister {
+        name: char,
+    },
+}
+
+/// an entry or a register read from the log, its data is not read yet
+struct Saved {
+    time: u64,
+    payloads: Vec<BlobRef>,
+    marks: Vec<String>,
+    pinned: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BlobRef {
+    mime: String,
+    blob: String,
+}
+
+/// the dir of the history, `$XDG_DATA_HOME/marine-clipboard`, or
+/// `~/.local/share/marine-clipboard` if XDG_DATA_HOME is not set
+pub fn default_history_dir() -> PathBuf {
+    std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+        .unwrap_or_default()
+        .join("marine-clipboard")
+}
+
+fn invalid_data(e: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+fn locked() -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, "the history is locked")
+}
+
+pub(crate) fn to_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since| since.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+pub(crate) fn from_millis(millis: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis)
+}
+
+/// create dir and its parents, only the user can enter them, the history may hold passwords
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    // made by an older version, or by someone else
+    if fs::metadata(dir)?.permissions().mode() & 0o077 != 0 {
+        fs::set_permissions(dir, Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
+/// create or truncate the file of path, only the user can read it
+pub(crate) fn create_private(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+}
+
+/// write data to a temporary file, and rename it to path, so path is never half written
+pub(crate) fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = create_private(&tmp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    if let Some(parent) = path.parent() {
+        File::open(parent)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// the aad of the record at index, so the records can not be moved or dropped in the middle
+/// without being noticed
+fn record_aad(index: usize) -> Vec<u8> {
+    format!("record {index}").into_bytes()
+}
+
+/// how the records and blobs are written, encrypted or not
+#[derive(Default)]
+struct Seal {
+    #[cfg(feature = "encryption")]
+    cipher: Option<Cipher>,
+}
+
+impl Seal {
+    fn encrypted(&self) -> bool {
+        #[cfg(feature = "encryption")]
+        return self.cipher.is_some();
+        #[cfg(not(feature = "encryption"))]
+        false
+    }
+
+    fn header(&self) -> Vec<u8> {
+        let header = Header {
+            version: VERSION,
+            encrypted: self.encrypted(),
+        };
+        let mut header = serde_json::to_vec(&header).unwrap();
+        header.push(b'\n');
+        header
+    }
+
+    /// the line of record in the log, without the newline, index is its position after the
+    /// header
+    fn seal_record(&self, record: &Record, index: usize) -> io::Result<Vec<u8>> {
+        let json = serde_json::to_vec(record).map_err(io::Error::other)?;
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher {
+            let sealed = cipher.encrypt(&json, &record_aad(index));
+            return Ok(to_hex(&sealed).into_bytes());
+        }
+        let _ = index;
+        Ok(json)
+    }
+
+    /// read the line at index of the log, encrypted is from the header of the log
+    fn open_record(&self, line: &[u8], index: usize, encrypted: bool) -> io::Result<Record> {
+        let json = if encrypted {
+            let sealed = std::str::from_utf8(line)
+                .ok()
+                .and_then(from_hex)
+                .ok_or_else(|| invalid_data("bad encrypted record"))?;
+            self.decrypt(&sealed, &record_aad(index))?
+        } else {
+            line.to_vec()
+        };
+        serde_json::from_slice(&json).map_err(invalid_data)
+    }
+
+    /// the name of the blob of data, its sha256, or a keyed hash if it is encrypted
+    fn blob_name(&self, data: &[u8]) -> String {
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher {
+            return cipher.blob_name(data);
+        }
+        to_hex(&Sha256::digest(data))
+    }
+
+    fn seal_blob(&self, name: &str, data: &[u8]) -> Vec<u8> {
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher {
+            return cipher.encrypt(data, name.as_bytes());
+        }
+        let _ = name;
+        data.to_vec()
+    }
+
+    fn open_blob(&self, name: &str, data: Vec<u8>, encrypted: bool) -> io::Result<Vec<u8>> {
+        if encrypted {
+            self.decrypt(&data, name.as_bytes())
+        } else {
+            Ok(data)
+        }
+    }
+
+    fn decrypt(&self, data: &[u8], aad: &[u8]) -> io::Result<Vec<u8>> {
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher {
+            return cipher.decrypt(data, aad);
+        }
+        let _ = (data, aad);
+        Err(locked())
+    }
+}
+
+/// a [ClipboardHistory] kept on the disk
+/// the entries are loaded into memory when it is opened, and every change is written to the disk
+/// before the method returns
+/// ``` rust, no_run
+/// use wayland_clipboard_listener::{HistoryStore, WlClipboardSelectionStream, WlCopyTarget};
+/// use std::time::Duration;
+///
+/// let mut store = HistoryStore::open("/tmp/clipboard-history").unwrap();
+/// store.set_max_entries(Some(1000));
+/// store.set_max_age(Some(Duration::from_secs(7 * 24 * 60 * 60)));
+/// let mut stream = WlClipboardSelectionStream::init(WlCopyTarget::Clipboard).unwrap();
+/// for selection in stream.flatten() {
+///     store.record(selection).unwrap();
+/// }
+///```
+pub struct HistoryStore {
+    dir: PathBuf,
+    log: File,
+    log_records: usize,
+    history: ClipboardHistory,
+    max_entries: Option<usize>,
+    max_bytes: Option<usize>,
+    max_age: Option<Duration>,
+    seal: Seal,
+    encrypted: bool,
+    locked: bool,
+    _lock: Flock<File>,
+}
+
+impl HistoryStore {
+    /// open the store in dir, it is created if it does not exist
+    /// only one store can open the same dir at a time, it fails with WouldBlock if it is opened by
+    /// another one
+    /// an encrypted store is opened locked, see [HistoryStore::is_locked]
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        create_private_dir(&dir)?;
+        create_private_dir(&dir.join(BLOB_DIR))?;
+        let lock = create_private(&dir.join(LOCK_FILE))?;
+        let lock = Flock::lock(lock, FlockArg::LockExclusiveNonblock)
+            .map_err(|(_, errno)| io::Error::from(errno))?;
+
+        let encrypted = dir.join(CRYPT_FILE).exists();
+        if encrypted && cfg!(not(feature = "encryption")) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the history is encrypted, it needs the encryption feature",
+            ));
+        }
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(dir.join(LOG_FILE))?;
+        let mut store = Self {
+            dir,
+            log,
+            log_records: 0,
+            history: ClipboardHistory::new(usize::MAX),
+            max_entries: None,
+            max_bytes: None,
+            max_age: None,
+            seal: Seal::default(),
+            encrypted,
+            locked: encrypted,
+            _lock: lock,
+        };
+        if !store.locked {
+            store.reload()?;
+        }
+        Ok(store)
+    }
+
+    /// read the history from the disk
+    /// the log is rewritten if it is not written the way of seal, when the encryption was turned on
+    /// but not finished
+    fn reload(&mut self) -> io::Result<()> {
+        let path = self.dir.join(LOG_FILE);
+        match Self::load(&self.dir, &self.seal)? {
+            Some((history, log_records, encrypted)) => {
+                self.history = history;
+                self.log_records = log_records;
+                if encrypted != self.seal.encrypted() {
+                    return self.compact();
+                }
+            }
+            None => {
+                write_atomic(&path, &self.seal.header())?;
+                self.history = ClipboardHistory::new(usize::MAX);
+                self.log_records = 0;
+            }
+        }
+        self.log = OpenOptions::new().append(true).open(&path)?;
+        self.compact_if_needed()
+    }
+
+    /// replay the log, return None if there is no log yet
+    /// also return the number of records, and if they are encrypted
+    fn load(dir: &Path, seal: &Seal) -> io::Result<Option<(ClipboardHistory, usize, bool)>> {
+        let path = dir.join(LOG_FILE);
+        let content = match fs::read(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        // a record cut by a crash has no newline at the end
+        let complete = content
+            .iter()
+            .rposition(|&byte| byte == b'\n')
+            .map_or(0, |index| index + 1);
+        if complete < content.len() {
+            log::warn!("dropping the incomplete record at the end of {path:?}");
+            OpenOptions::new()
+                .write(true)
+                .open(&path)?
+                .set_len(complete as u64)?;
+        }
+
+        let mut lines = content[..complete]
+            .split(|&byte| byte == b'\n')
+            .filter(|line| !line.is_empty());
+        let Some(header) = lines.next() else {
+            return Ok(None);
+        };
+        let header: Header = serde_json::from_slice(header).map_err(invalid_data)?;
+        if header.version != VERSION {
+            return Err(invalid_data(format!(
+                "unsupported history version {}",
+                header.version
+            )));
+        }
+        if header.encrypted && !seal.encrypted() {
+            return Err(invalid_data(format!(
+                "the history is encrypted, but {CRYPT_FILE} is lost"
+            )));
+        }
+
+        // the ids from the oldest to the newest
+        let mut order: Vec<u64> = Vec::new();
+        let mut entries: HashMap<u64, Saved> = HashMap::new();
+        let mut registers: BTreeMap<char, Saved> = BTreeMap::new();
+        let mut records = 0;
+        for line in lines {
+            let record = seal.open_record(line, records, header.encrypted);
+            records += 1;
+            let record = match record {
+                Ok(record) => record,
+                Err(e) => {
+                    log::warn!("skipping a bad record in {path:?}: {e}");
+                    continue;
+                }
+            };
+            match record {
+                Record::Add {
+                    id,
+                    time,
+                    payloads,
+                    marks,
+                    pinned,
+                } => {
+                    order.retain(|old| *old != id);
+                    order.push(id);
+                    entries.insert(
+                        id,
+                        Saved {
+                            time,
+                            payloads,
+                            marks,
+                            pinned,
+                        },
+                    );
+                }
+                Record::Touch { id, time, marks } => {
+                    if let Some(entry) = entries.get_mut(&id) {
+                        entry.time = time;
+                        if !marks.is_empty() {
+                            entry.marks = marks;
+                        }
+                        order.retain(|old| *old != id);
+                        order.push(id);
+                    }
+                }
+                Record::Remove { id } => {
+                    entries.remove(&id);
+                    order.retain(|old| *old != id);
+                }
+                Record::Clear => {
+                    entries.retain(|_, entry| entry.pinned);
+                    order.retain(|id| entries.contains_key(id));
+                }
+                Record::Pin { id, pinned } => {
+                    if let Some(entry) = entries.get_mut(&id) {
+                        entry.pinned = pinned;
+                    }
+                }
+                Record::Register {
+                    name,
+                    time,
+                    payloads,
+                } => {
+                    registers.insert(
+                        name,
+                        Saved {
+                            time,
+                            payloads,
+                            marks: Vec::new(),
+                            pinned: false,
+                        },
+                    );
+                }
+                Record::Unregister { name } => {
+                    registers.remove(&name);
+                }
+            }
+        }
+
+        let mut history = ClipboardHistory::new(usize::MAX);
+        let mut blobs: HashMap<String, Arc<[u8]>> = HashMap::new();
+        // the data of the blobs, a blob in many entries is read once
+        let mut read_payloads = |refs: Vec<BlobRef>| -> io::Result<Payloads> {
+            let mut payloads = Vec::with_capacity(refs.len());
+            for BlobRef { mime, blob } in refs {
+                let data = match blobs.get(&blob) {
+                    Some(data) => data.clone(),
+                    None => {
+                        let data = fs::read(dir.join(BLOB_DIR).join(&blob))
+                            .and_then(|data| seal.open_blob(&blob, data, header.encrypted))
+                            .map_err(|e| io::Error::new(e.kind(), format!("blob {blob}: {e}")))?;
+                        let data: Arc<[u8]> = data.into();
+                        blobs.insert(blob, data.clone());
+                        data
+                    }
+                };
+                payloads.push((mime, data));
+            }
+            Ok(payloads)
+        };
+        for id in order {
+            let saved = entries.remove(&id).unwrap();
+            match read_payloads(saved.payloads) {
+                Ok(payloads) => history.restore(HistoryEntry::restore(
+                    id,
+                    payloads,
+                    from_millis(saved.time),
+                    saved.marks,
+                    saved.pinned,
+                )),
+                Err(e) => log::warn!("skipping the entry {id}, it is lost: {e}"),
+            }
+        }
+        for (name, saved) in registers {
+            match read_payloads(saved.payloads) {
+                Ok(payloads) => history.restore_register(
+                    name,
+                    HistoryEntry::restore(0, payloads, from_millis(saved.time), Vec::new(), false),
+                ),
+                Err(e) => log::warn!("skipping the register {name}, it is lost: {e}"),
+            }
+        }
+        Ok(Some((history, records, header.encrypted)))
+    }
+
+    /// if the store is encrypted, and the key is not given yet, or it is locked again
+    /// nothing can be read or written when it is locked
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted
+    }
+
+    /// encrypt the store with key, the records and blobs are rewritten encrypted, and the
+    /// unencrypted ones are deleted, but it may still be found on the disk by forensic tools
+    #[cfg(feature = "encryption")]
+    pub fn encrypt(&mut self, key: &HistoryKey) -> io::Result<()> {
+        if self.encrypted {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "the history is encrypted already",
+            ));
+        }
+        self.seal.cipher = Some(Cipher::create(&self.dir, key)?);
+        self.encrypted = true;
+        self.compact()
+    }
+
+    /// unlock an encrypted store with key, and read the history
+    /// it fails with PermissionDenied if the key is wrong
+    #[cfg(feature = "encryption")]
+    pub fn unlock(&mut self, key: &HistoryKey) -> io::Result<()> {
+        if !self.locked {
+            return Ok(());
+        }
+        self.seal.cipher = Some(Cipher::unlock(&self.dir, key)?);
+        if let Err(e) = self.reload() {
+            self.seal = Seal::default();
+            self.history = ClipboardHistory::new(usize::MAX);
+            return Err(e);
+        }
+        self.locked = false;
+        Ok(())
+    }
+
+    /// lock an encrypted store, the key and the history are dropped from memory
+    #[cfg(feature = "encryption")]
+    pub fn lock(&mut self) {
+        if self.encrypted {
+            self.seal = Seal::default();
+            self.history = ClipboardHistory::new(usize::MAX);
+            self.locked = true;
+        }
+    }
+
+    fn check_unlocked(&self) -> io::Result<()> {
+        if self.locked {
+            return Err(locked());
+        }
+        Ok(())
+    }
+
+    /// the entries in memory, the newest first, it is empty when the store is locked
+    pub fn history(&self) -> &ClipboardHistory {
+        &self.history
+    }
+
+    /// fuzzy search the entries, see [ClipboardHistory::search]
+    pub fn search(&self, query: &HistoryQuery) -> Vec<SearchMatch<'_>> {
+        self.history.search(query)
+    }
+
+    /// set the max count of entries, it is applied on the next push, or by [HistoryStore::prune]
+    pub fn set_max_entries(&mut self, max_entries: Option<usize>) {
+        self.max_entries = max_entries;
+    }
+
+    /// set the max size of all the entries, the newest one is always kept, it is applied on the
+    /// next push, or by [HistoryStore::prune]
+    pub fn set_max_bytes(&mut self, max_bytes: Option<usize>) {
+        self.max_bytes = max_bytes;
+    }
+
+    /// set the max age of the entries, since they were copied the last time, it is applied on the
+    /// next push, or by [HistoryStore::prune]
+    pub fn set_max_age(&mut self, max_age: Option<Duration>) {
+        self.max_age = max_age;
+    }
+
+    /// add a selection to the top, see [ClipboardHistory::push]
+    pub fn push<P: Into<Arc<[u8]>>>(
+        &mut self,
+        payloads: impl IntoIterator<Item = (String, P)>,
+    ) -> io::Result<Option<u64>> {
+        self.add(payloads, Vec::new())
+    }
+
+    fn add<P: Into<Arc<[u8]>>>(
+        &mut self,
+        payloads: impl IntoIterator<Item = (String, P)>,
+        marks: Vec<String>,
+    ) -> io::Result<Option<u64>> {
+        self.check_unlocked()?;
+        let len = self.history.len();
+        let new_marks = !marks.is_empty();
+        let Some(id) = self.history.push_marked(payloads, marks) else {
+            return Ok(None);
+        };
+        let entry = self.history.latest().unwrap();
+        let time = to_millis(entry.timestamp());
+        if self.history.len() == len {
+            let marks = if new_marks {
+                entry.marks().to_vec()
+            } else {
+                Vec::new()
+            };
+            self.append(&Record::Touch { id, time, marks })?;
+        } else {
+            let payloads = match self.write_blobs(entry) {
+                Ok(payloads) => payloads,
+                Err(e) => {
+                    self.history.remove(id);
+                    return Err(e);
+                }
+            };
+            let marks = entry.marks().to_vec();
+            self.append(&Record::Add {
+                id,
+                time,
+                payloads,
+                marks,
+                pinned: false,
+            })?;
+        }
+        self.prune()?;
+        Ok(Some(id))
+    }
+
+    /// add a selection from [crate::WlClipboardSelectionStream], see [ClipboardHistory::push]
+    pub fn record(&mut self, selection: ClipBoardSelection) -> io::Result<Option<u64>> {
+        self.add(selection.payloads, selection.marks)
+    }
+
+    /// move the entry to the top, and return a source to copy it again, see
+    /// [ClipboardHistory::select]
+    pub fn select(&mut self, id: u64) -> io::Result<Option<CopySource>> {
+        self.check_unlocked()?;
+        let Some(source) = self.history.select(id) else {
+            return Ok(None);
+        };
+        let time = to_millis(self.history.latest().unwrap().timestamp());
+        self.append(&Record::Touch {
+            id,
+            time,
+            marks: Vec::new(),
+        })?;
+        Ok(Some(source))
+    }
+
+    /// remove the entry, and delete its blobs not used by another entry or a register
+    pub fn remove(&mut self, id: u64) -> io::Result<Option<HistoryEntry>> {
+        self.check_unlocked()?;
+        let Some(entry) = self.history.remove(id) else {
+            return Ok(None);
+        };
+        self.append(&Record::Remove { id })?;
+        self.delete_unused_blobs(&entry)?;
+        self.compact_if_needed()?;
+        Ok(Some(entry))
+    }
+
+    /// remove all the entries but the pinned ones, and delete their data from the disk
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.check_unlocked()?;
+        self.history.clear();
+        self.append(&Record::Clear)?;
+        self.compact()
+    }
+
+    /// pin or unpin the entry, see [ClipboardHistory::pin]
+    /// return false if it is not in the history
+    pub fn pin(&mut self, id: u64, pinned: bool) -> io::Result<bool> {
+        self.check_unlocked()?;
+        if !self.history.pin(id, pinned) {
+            return Ok(false);
+        }
+        self.append(&Record::Pin { id, pinned })?;
+        // the entry unpinned may be out of the limits
+        self.prune()?;
+        Ok(true)
+    }
+
+    /// put a selection in the register name, "a" to "z", see [ClipboardHistory::set_register]
+    /// an error of kind InvalidInput is returned if name is not a register, or payloads is empty
+    /// ``` rust, no_run
+    /// use wayland_clipboard_listener::{HistoryStore, WlClipboardCopyStream, WlClipboardSelectionStream, WlCopyTarget};
+    ///
+    /// let mut store = HistoryStore::open("/tmp/history").unwrap();
+    /// // save the current selection in the register a
+    /// let mut stream = WlClipboardSelectionStream::init(WlCopyTarget::Clipboard).unwrap();
+    /// if let Some(selection) = stream.get_current_selection().unwrap() {
+    ///     store.save_register('a', selection).unwrap();
+    /// }
+    /// // and copy it again later
+    /// if let Some(register) = store.history().register('a') {
+    ///     let mut stream = WlClipboardCopyStream::init().unwrap();
+    ///     stream.copy_source_to_clipboard(register.source(), WlCopyTarget::Clipboard).unwrap();
+    /// }
+    /// ```
+    pub fn set_register<P: Into<Arc<[u8]>>>(
+        &mut self,
+        name: char,
+        payloads: impl IntoIterator<Item = (String, P)>,
+    ) -> io::Result<()> {
+        self.check_unlocked()?;
+        if !is_register_name(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{name} is not a register, they are a to z"),
+            ));
+        }
+        if !self.history.set_register(name, payloads) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a register can not be empty",
+            ));
+        }
+        let register = self.history.register(name).unwrap();
+        let time = to_millis(register.timestamp());
+        let payloads = self.write_blobs(register)?;
+        self.append(&Record::Register {
+            name,
+            time,
+            payloads,
+        })?;
+        self.compact_if_needed()
+    }
+
+    /// put a selection from [crate::WlClipboardSelectionStream] in the register name
+    pub fn save_register(&mut self, name: char, selection: ClipBoardSelection) -> io::Result<()> {
+        self.set_register(name, selection.payloads)
+    }
+
+    /// empty the register name
+    pub fn remove_register(&mut self, name: char) -> io::Result<Option<HistoryEntry>> {
+        self.check_unlocked()?;
+        let Some(register) = self.history.remove_register(name) else {
+            return Ok(None);
+        };
+        self.append(&Record::Unregister { name })?;
+        self.compact_if_needed()?;
+        Ok(Some(register))
+    }
+
+    /// write the history, see [ClipboardHistory::export]
+    pub fn export(&self, writer: impl Write) -> io::Result<usize> {
+        self.check_unlocked()?;
+        self.history.export(writer)
+    }
+
+    /// read an export into the history, see [ClipboardHistory::import]
+    pub fn import(&mut self, reader: impl BufRead) -> io::Result<usize> {
+        self.check_unlocked()?;
+        let count = self.history.import(reader)?;
+        self.save_imported()?;
+        Ok(count)
+    }
+
+    /// read the database of cliphist into the history, see [ClipboardHistory::import_cliphist]
+    pub fn import_cliphist(&mut self, path: impl AsRef<Path>) -> io::Result<usize> {
+        self.check_unlocked()?;
+        let count = self.history.import_cliphist(path)?;
+        self.save_imported()?;
+        Ok(count)
+    }
+
+    /// the imported entries are not on the top, so the log is rewritten in the new order
+    fn save_imported(&mut self) -> io::Result<()> {
+        self.prune()?;
+        self.compact()
+    }
+
+    /// remove the oldest entries, until the history is in the limits
+    /// the pinned entries are not counted, and never removed
+    pub fn prune(&mut self) -> io::Result<()> {
+        self.check_unlocked()?;
+        let now = SystemTime::now();
+        while let Some(oldest) = self.history.oldest() {
+            let unpinned = || self.history.entries().filter(|entry| !entry.pinned());
+            let len = unpinned().count();
+            let too_many = self.max_entries.is_some_and(|max| len > max);
+            let too_large = self.max_bytes.is_some_and(|max| {
+                len > 1 && unpinned().map(HistoryEntry::size).sum::<usize>() > max
+            });
+            let too_old = self.max_age.is_some_and(|max| {
+                now.duration_since(oldest.timestamp())
+                    .is_ok_and(|age| age > max)
+            });
+            if !(too_many || too_large || too_old) {
+                break;
+            }
+            let id = oldest.id();
+            let entry = self.history.remove(id).unwrap();
+            self.append(&Record::Remove { id })?;
+            self.delete_unused_blobs(&entry)?;
+        }
+        self.compact_if_needed()
+    }
+
+    /// rewrite the log with only the entries in the history, and delete the blobs not used
+    /// anymore
+    pub fn compact(&mut self) -> io::Result<()> {
+        self.check_unlocked()?;
+        let mut content = self.seal.header();
+        let mut used = HashSet::new();
+        let mut index = 0;
+        // the oldest first, as they are replayed
+        let entries: Vec<&HistoryEntry> = self.history.entries().collect();
+        for entry in entries.iter().rev() {
+            let payloads = self.write_blobs(entry)?;
+            used.extend(payloads.iter().map(|blob| blob.blob.clone()));
+            let record = Record::Add {
+                id: entry.id(),
+                time: to_millis(entry.timestamp()),
+                payloads,
+                marks: entry.marks().to_vec(),
+                pinned: entry.pinned(),
+            };
+            content.extend(self.seal.seal_record(&record, index)?);
+            content.push(b'\n');
+            index += 1;
+        }
+        let registers: Vec<(char, &HistoryEntry)> = self.history.registers().collect();
+        for (name, register) in &registers {
+            let payloads = self.write_blobs(register)?;
+            used.extend(payloads.iter().map(|blob| blob.blob.clone()));
+            let record = Record::Register {
+                name: *name,
+                time: to_millis(register.timestamp()),
+                payloads,
+            };
+            content.extend(self.seal.seal_record(&record, index)?);
+            content.push(b'\n');
+            index += 1;
+        }
+        let path = self.dir.join(LOG_FILE);
+        write_atomic(&path, &content)?;
+        self.log = OpenOptions::new().append(true).open(&path)?;
+        self.log_records = index;
+
+        for blob in fs::read_dir(self.dir.join(BLOB_DIR))? {
+            let blob = blob?;
+            if !used.contains(blob.file_name().to_string_lossy().as_ref()) {
+                fs::remove_file(blob.path())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn compact_if_needed(&mut self) -> io::Result<()> {
+        if !self.locked && self.log_records > self.history.len() * 2 + COMPACT_SLACK {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    fn append(&mut self, record: &Record) -> io::Result<()> {
+        let mut line = self.seal.seal_record(record, self.log_records)?;
+        line.push(b'\n');
+        self.log.write_all(&line)?;
+        self.log.sync_data()?;
+        self.log_records += 1;
+        Ok(())
+    }
+
+    /// delete the blobs of an entry removed from the history, if no other entry or register has
+    /// the same data
+    /// it is called after the record removing it is written, so a crash never leaves a record
+    /// without its blobs
+    fn delete_unused_blobs(&self, removed: &HistoryEntry) -> io::Result<()> {
+        let kept = || {
+            self.history
+                .entries()
+                .chain(self.history.registers().map(|(_, register)| register))
+                .flat_map(|entry| entry.payloads().iter().map(|(_, data)| data))
+        };
+        let mut deleted = HashSet::new();
+        for (_, data) in removed.payloads() {
+            let blob = self.seal.blob_name(data);
+            if deleted.contains(&blob) || kept().any(|other| other == data) {
+                continue;
+            }
+            match fs::remove_file(self.dir.join(BLOB_DIR).join(&blob)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+            deleted.insert(blob);
+        }
+        Ok(())
+    }
+
+    /// write the data of every mimetype of entry, if they are not there yet
+    fn write_blobs(&self, entry: &HistoryEntry) -> io::Result<Vec<BlobRef>> {
+        entry
+            .payloads()
+            .iter()
+            .map(|(mime, data)| {
+                let blob = self.seal.blob_name(data);
+                let path = self.dir.join(BLOB_DIR).join(&blob);
+                if !path.exists() {
+                    write_atomic(&path, &self.seal.seal_blob(&blob, data))?;
+                }
+                Ok(BlobRef {
+                    mime: mime.clone(),
+                    blob,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    fn text(text: &str) -> Vec<(String, Vec<u8>)> {
+        vec![("text/plain".to_string(), text.as_bytes().to_vec())]
+    }
+
+    fn texts(store: &HistoryStore) -> Vec<(&str, bool)> {
+        store
+            .history()
+            .entries()
+            .map(|entry| (entry.text().unwrap(), entry.pinned()))
+            .collect()
+    }
+
+    fn blobs(dir: &Path) -> usize {
+        fs::read_dir(dir.join(BLOB_DIR)).unwrap().count()
+    }
+
+    #[test]
+    fn only_the_user_can_read_the_store() {
+        let tmp = TempDir::new();
+        let dir = tmp.path().join("history");
+        let mut store = HistoryStore::open(&dir).unwrap();
+        store
+            .push(vec![("text/plain".to_string(), b"secret".to_vec())])
+            .unwrap();
+        assert_eq!(mode(&dir), 0o700);
+        assert_eq!(mode(&dir.join(BLOB_DIR)), 0o700);
+        assert_eq!(mode(&dir.join(LOG_FILE)), 0o600);
+        assert_eq!(mode(&dir.join(LOCK_FILE)), 0o600);
+        for blob in fs::read_dir(dir.join(BLOB_DIR)).unwrap() {
+            assert_eq!(mode(&blob.unwrap().path()), 0o600);
+        }
+    }
+
+    #[test]
+    fn open_makes_an_old_store_private() {
+        let tmp = TempDir::new();
+        fs::set_permissions(tmp.path(), Permissions::from_mode(0o755)).unwrap();
+        HistoryStore::open(tmp.path()).unwrap();
+        assert_eq!(mode(tmp.path()), 0o700);
+    }
+
+    #[test]
+    fn a_register_can_not_be_empty() {
+        let tmp = TempDir::new();
+        let mut store = HistoryStore::open(tmp.path()).unwrap();
+        let hello = vec![("text/plain".to_string(), b"hello".to_vec())];
+        store.set_register('a', hello.clone()).unwrap();
+        let kind = |result: io::Result<()>| result.unwrap_err().kind();
+        assert_eq!(
+            kind(store.set_register('a', Vec::<(String, Vec<u8>)>::new())),
+            io::ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            kind(store.set_register('A', hello)),
+            io::ErrorKind::InvalidInput
+        );
+        // the register is kept
+        assert_eq!(store.history().register('a').unwrap().text(), Some("hello"));
+    }
+
+    #[test]
+    fn the_log_is_replayed_when_reopened() {
+        let tmp = TempDir::new();
+        let mut store = HistoryStore::open(tmp.path()).unwrap();
+        let a = store.push(text("a")).unwrap().unwrap();
+        let b = store.push(text("b")).unwrap().unwrap();
+        let c = store.push(text("c")).unwrap().unwrap();
+        store.pin(b, true).unwrap();
+        store.select(a).unwrap().unwrap();
+        store.remove(c).unwrap().unwrap();
+        store.set_register('r', text("register")).unwrap();
+        // the marks of a selection copied again are added
+        store
+            .record(ClipBoardSelection {
+                primary: false,
+                payloads: vec![("text/plain".to_string(), b"b".as_slice().into())],
+                marks: vec!["mark".to_string()],
+            })
+            .unwrap();
+        store.select(a).unwrap().unwrap();
+        drop(store);
+
+        let mut store = HistoryStore::open(tmp.path()).unwrap();
+        assert_eq!(texts(&store), [("a", false), ("b", true)]);
+        assert_eq!(store.history().get(b).unwrap().marks(), ["mark"]);
+        let ids: Vec<u64> = store.history().entries().map(HistoryEntry::id).collect();
+        assert_eq!(ids, [a, b]);
+        assert_eq!(
+            store.history().register('r').unwrap().text(),
+            Some("register")
+        );
+        // a crash after the clear record, before the log is compacted
+        store.push(text("d")).unwrap();
+        store.history.clear();
+        store.append(&Record::Clear).unwrap();
+        drop(store);
+
+        let mut store = HistoryStore::open(tmp.path()).unwrap();
+        assert_eq!(texts(&store), [("b", true)]);
+        store.pin(b, false).unwrap();
+        drop(store);
+        let store = HistoryStore::open(tmp.path()).unwrap();
+        assert_eq!(texts(&store), [("b", false)]);
+    }
+
+    #[test]
+    fn a_record_cut_by_a_crash_is_dropped() {
+        let tmp = TempDir::new();
+        let mut store = HistoryStore::open(tmp.path()).unwrap();
+        store.push(text("a")).unwrap();
+        store.log.write_all(br#"{"op":"add","id":9,"ti"#).unwrap();
+        drop(store);
+
+        let mut store = HistoryStore::open(tmp.path()).unwrap();
+        assert_eq!(texts(&store), [("a", false)]);
+        let log = fs::read(tmp.path().join(LOG_FILE)).unwrap();
+        assert_eq!(log.last(), Some(&b'\n'));
+        // the next record is not appended to the cut one
+        store.push(text("b")).unwrap();
+        drop(store);
+        let store = HistoryStore::open(tmp.path()).unwrap();
+        assert_eq!(texts(&store), [("b", false), ("a", false)]);
+    }
+
+    #[test]
+    fn compact_deletes_the_unused_blobs() {
+        let tmp = TempDir::new();
+        let mut store = HistoryStore::open(tmp.path()).unwrap();
+        store.push(text("a")).unwrap();
+        store.push(text("b")).unwrap();
+        // a blob written before a crash, its record was never appended
+        let orphan = tmp.path().join(BLOB_DIR).join("orphan");
+        fs::write(&orphan, b"lost").unwrap();
+        store.compact().unwrap();
+        assert!(!orphan.exists());
+        assert_eq!(blobs(tmp.path()), 2);
+        drop(store);
+        let store = HistoryStore::open(tmp.path()).unwrap();
+        assert_eq!(texts(&store), [("b", false), ("a", false)]);
+    }
+
+    #[test]
+    fn remove_deletes_the_blobs_not_used_anymore() {
+        let tmp = TempDir::new();
+        let mut store = HistoryStore::open(tmp.path()).unwrap();
+        let a = store.push(text("same")).unwrap().unwrap();
+        let b = store
+            .push(vec![
+                ("text/plain".to_string(), b"other".to_vec()),
+                ("text/html".to_string(), b"same".to_vec()),
+            ])
+            .unwrap()
+            .unwrap();
+        assert_eq!(blobs(tmp.path()), 2);
+        // same is still used by b
+        store.remove(a).unwrap().unwrap();
+        assert_eq!(blobs(tmp.path()), 2);
+        // and other by the register
+        store.set_register('r', text("other")).unwrap();
+        store.remove(b).unwrap().unwrap();
+        assert_eq!(blobs(tmp.path()), 1);
+        drop(store);
+        let store = HistoryStore::open(tmp.path()).unwrap();
+        assert_eq!(store.history().register('r').unwrap().text(), Some("other"));
+    }
+
+    #[test]
+    fn prune_keeps_the_history_in_the_limits() {
+        let tmp = TempDir::new();
+        let mut store = HistoryStore::open(tmp.path()).unwrap();
+        let pinned = store.push(text("pinned")).unwrap().unwrap();
+        store.pin(pinned, true).unwrap();
+        for data in ["aaaaa", "bbbbb", "ccccc", "ddddd"] {
+            store.push(text(data)).unwrap();
+        }
+        // the pinned entries are not counted
+        store.set_max_entries(Some(3));
+        store.prune().unwrap();
+        assert_eq!(
+            texts(&store),
+            [
+                ("ddddd", false),
+                ("ccccc", false),
+                ("bbbbb", false),
+                ("pinned", true)
+            ]
+        );
+        store.set_max_bytes(Some(12));
+        store.prune().unwrap();
+        assert_eq!(
+            texts(&store),
+            [("ddddd", false), ("ccccc", false), ("pinned", true)]
+        );
+        // the blobs of the pruned entries are deleted
+        assert_eq!(blobs(tmp.path()), 3);
+
+        std::thread::sleep(Duration::from_millis(300));
+        store.set_max_age(Some(Duration::from_millis(200)));
+        store.push(text("eeeee")).unwrap();
+        assert_eq!(texts(&store), [("eeeee", false), ("pinned", true)]);
+        drop(store);
+        let store = HistoryStore::open(tmp.path()).unwrap();
+        assert_eq!(texts(&store), [("eeeee", false), ("pinned", true)]);
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn an_encrypted_store_is_unlocked_by_its_key() {
+        let passphrase = |passphrase: &str| HistoryKey::Passphrase(passphrase.to_string().into());
+        let tmp = TempDir::new();
+        let mut store = HistoryStore::open(tmp.path()).unwrap();
+        let a = store.push(text("first secret")).unwrap().unwrap();
+        store.push(text("second secret")).unwrap();
+        store.pin(a, true).unwrap();
+        store.set_register('r', text("register secret")).unwrap();
+        store.encrypt(&passphrase("correct horse")).unwrap();
+        store.push(text("third secret")).unwrap();
+        drop(store);
+
+        // nothing is written in plain text
+        let mut files = vec![tmp.path().join(LOG_FILE)];
+        for blob in fs::read_dir(tmp.path().join(BLOB_DIR)).unwrap() {
+            files.push(blob.unwrap().path());
+        }
+        for file in files {
+            let content = fs::read(&file).unwrap();
+            assert!(
+                !content.windows(6).any(|window| window == b"secret"),
+                "{file:?} is not encrypted"
+            );
+        }
+
+        let mut store = HistoryStore::open(tmp.path()).unwrap();
+        assert!(store.is_locked());
+        assert!(store.history().is_empty());
+        let e = store.push(text("while locked")).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        let e = store.unlock(&passphrase("battery staple")).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        assert!(store.is_locked());
+
+        store.unlock(&passphrase("correct horse")).unwrap();
+        assert!(!store.is_locked());
+        assert_eq!(
+            texts(&store),
+            [
+                ("third secret", false),
+                ("second secret", false),
+                ("first secret", true)
+            ]
+        );
+        assert_eq!(
+            store.history().register('r').unwrap().text(),
+            Some("register secret")
+        );
+        store.lock();
+        assert!(store.history().is_empty());
+        drop(store);
+
+        // the records swapped are not at their position anymore
+        let log = fs::read(tmp.path().join(LOG_FILE)).unwrap();
+        let mut lines: Vec<&[u8]> = log.split(|&byte| byte == b'\n').collect();
+        lines.swap(1, 2);
+        fs::write(tmp.path().join(LOG_FILE), lines.join(&b'\n')).unwrap();
+        let mut store = HistoryStore::open(tmp.path()).unwrap();
+        store.unlock(&passphrase("correct horse")).unwrap();
+        assert_eq!(texts(&store), [("third secret", false)]);
+    }
+}
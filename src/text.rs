@@ -0,0 +1,131 @@
+//! convert text to what the paste side requests
+//! we keep the text as UTF-8, but apps running on XWayland may request `STRING`, which is
+//! Latin-1, so the text is converted when it is pasted
+
+use crate::mime::TEXT_MIME_TYPES;
+
+/// the mimetypes offered by a text source, UTF-8 ones first
+pub(crate) const TEXT_SOURCE_MIME_TYPES: [&str; 7] = [
+    TEXT_MIME_TYPES[0],
+    TEXT_MIME_TYPES[1],
+    TEXT_MIME_TYPES[2],
+    TEXT_MIME_TYPES[3],
+    "STRING",
+    "text/plain;charset=iso-8859-1",
+    "text/plain;charset=us-ascii",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TextEncoding {
+    Utf8,
+    Latin1,
+    Ascii,
+}
+
+impl TextEncoding {
+    /// the encoding of the mimetype, None if it is not text
+    pub(crate) fn from_mime_type(mime_type: &str) -> Option<Self> {
+        match mime_type {
+            "UTF8_STRING" | "TEXT" | "text/plain" => return Some(Self::Utf8),
+            "STRING" => return Some(Self::Latin1),
+            _ => {}
+        }
+        let (essence, params) = mime_type.split_once(';')?;
+        if !essence.trim().eq_ignore_ascii_case("text/plain") {
+            return None;
+        }
+        let charset = params
+            .split(';')
+            .filter_map(|param| param.split_once('='))
+            .find(|(key, _)| key.trim().eq_ignore_ascii_case("charset"))
+            .map(|(_, value)| value.trim().trim_matches('"').to_ascii_lowercase())?;
+        match charset.as_str() {
+            "utf-8" | "utf8" => Some(Self::Utf8),
+            "iso-8859-1" | "latin1" | "latin-1" => Some(Self::Latin1),
+            "us-ascii" | "ascii" => Some(Self::Ascii),
+            _ => None,
+        }
+    }
+
+    /// the characters can not be encoded are replaced by `?`
+    pub(crate) fn encode(self, text: &str, crlf: bool) -> Vec<u8> {
+        let text = if crlf {
+            to_crlf(text)
+        } else {
+            text.to_string()
+        };
+        match self {
+            Self::Utf8 => text.into_bytes(),
+            Self::Latin1 => text
+                .chars()
+                .map(|c| u8::try_from(u32::from(c)).unwrap_or(b'?'))
+                .collect(),
+            Self::Ascii => text
+                .chars()
+                .map(|c| if c.is_ascii() { c as u8 } else { b'?' })
+                .collect(),
+        }
+    }
+}
+
+/// make all the line endings CRLF, the existing CRLF ones are kept, and so is a lone CR
+fn to_crlf(text: &str) -> String {
+    let mut converted = String::with_capacity(text.len() + text.len() / 32);
+    let mut last = None;
+    for c in text.chars() {
+        if c == '\n' && last != Some('\r') {
+            converted.push('\r');
+        }
+        converted.push(c);
+        last = Some(c);
+    }
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_charset_is_parsed() {
+        let cases = [
+            ("text/plain;charset=utf-8", Some(TextEncoding::Utf8)),
+            ("text/plain; charset=\"UTF-8\"", Some(TextEncoding::Utf8)),
+            (
+                "Text/Plain ; format=flowed; CHARSET = Latin1",
+                Some(TextEncoding::Latin1),
+            ),
+            ("text/plain;charset=\"us-ascii\"", Some(TextEncoding::Ascii)),
+            ("STRING", Some(TextEncoding::Latin1)),
+            ("UTF8_STRING", Some(TextEncoding::Utf8)),
+            ("text/plain;charset=utf-16", None),
+            ("text/plain;format=flowed", None),
+            ("text/html;charset=utf-8", None),
+            ("image/png", None),
+        ];
+        for (mime_type, encoding) in cases {
+            assert_eq!(
+                TextEncoding::from_mime_type(mime_type),
+                encoding,
+                "{mime_type}"
+            );
+        }
+    }
+
+    #[test]
+    fn the_characters_not_encoded_are_replaced() {
+        let text = "café 10€";
+        assert_eq!(TextEncoding::Utf8.encode(text, false), text.as_bytes());
+        assert_eq!(TextEncoding::Latin1.encode(text, false), b"caf\xe9 10?");
+        assert_eq!(TextEncoding::Ascii.encode(text, false), b"caf? 10?");
+        assert_eq!(TextEncoding::Ascii.encode("a\nb", true), b"a\r\nb");
+    }
+
+    #[test]
+    fn the_line_endings_are_crlf() {
+        assert_eq!(to_crlf("a\nb\n"), "a\r\nb\r\n");
+        assert_eq!(to_crlf("a\r\nb"), "a\r\nb");
+        assert_eq!(to_crlf("a\rb\r"), "a\rb\r");
+        assert_eq!(to_crlf("\n\n"), "\r\n\r\n");
+    }
+}
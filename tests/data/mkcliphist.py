@@ -0,0 +1,101 @@
+#!/usr/bin/env python3
+"""write the cliphist databases used by the tests of src/cliphist.rs
+
+    python3 tests/data/mkcliphist.py tests/data
+
+they are small bbolt files with 1024 bytes pages:
+- cliphist-inline.db: the bucket "b" is inlined in the root page, with "one", an empty entry, and
+  "one" again
+- cliphist-branch.db: the bucket "b" has a branch page with two leaf pages, the second one spans
+  two pages, the second meta is newer but its checksum is bad, and an inline bucket "a" comes
+  before "b"
+- cliphist-loop.db: the branch page of the bucket lists itself twice
+"""
+import os
+import struct
+import sys
+
+PAGE_SIZE = 1024
+
+
+def fnv1a(data):
+    hash = 0xCBF29CE484222325
+    for byte in data:
+        hash = ((hash ^ byte) * 0x100000001B3) & 0xFFFFFFFFFFFFFFFF
+    return hash
+
+
+def meta(id, root, txid, bad=False):
+    body = struct.pack("<IIIIQQQQQ", 0xED0CDAED, 2, PAGE_SIZE, 0, root, 0, 2, 8, txid)
+    checksum = fnv1a(body) + (1 if bad else 0)
+    page = struct.pack("<QHHI", id, 0x04, 0, 0) + body + struct.pack("<Q", checksum)
+    return page.ljust(PAGE_SIZE, b"\0")
+
+
+def leaf(id, items, overflow=0, inline=False):
+    """items are (flags, key, value)"""
+    header = struct.pack("<QHHI", id, 0x02, len(items), overflow)
+    elements = b""
+    data = b""
+    for index, (flags, key, value) in enumerate(items):
+        pos = 16 + 16 * len(items) + len(data) - (16 + 16 * index)
+        elements += struct.pack("<IIII", flags, pos, len(key), len(value))
+        data += key + value
+    page = header + elements + data
+    return page if inline else page.ljust(PAGE_SIZE * (overflow + 1), b"\0")
+
+
+def branch(id, children):
+    """children are (key, page id)"""
+    header = struct.pack("<QHHI", id, 0x01, len(children), 0)
+    elements = b""
+    data = b""
+    for index, (key, child) in enumerate(children):
+        pos = 16 + 16 * len(children) + len(data) - (16 + 16 * index)
+        elements += struct.pack("<IIQ", pos, len(key), child)
+        data += key
+    return (header + elements + data).ljust(PAGE_SIZE, b"\0")
+
+
+def freelist(id):
+    return struct.pack("<QHHI", id, 0x10, 0, 0).ljust(PAGE_SIZE, b"\0")
+
+
+def key(id):
+    return struct.pack(">Q", id)
+
+
+def bucket(root, page=b""):
+    return struct.pack("<QQ", root, 0) + page
+
+
+def main(dir):
+    inline = [
+        meta(0, 3, 1),
+        meta(1, 3, 0),
+        freelist(2),
+        leaf(3, [(1, b"b", bucket(0, leaf(0, [(0, key(1), b"one"), (0, key(2), b""), (0, key(3), b"one")], inline=True)))]),
+    ]
+    tree = [
+        meta(0, 3, 1),
+        meta(1, 3, 2, bad=True),
+        freelist(2),
+        leaf(3, [(1, b"a", bucket(0, leaf(0, [(0, b"x", b"y")], inline=True))), (1, b"b", bucket(4))]),
+        branch(4, [(key(1), 5), (key(3), 6)]),
+        leaf(5, [(0, key(1), b"first"), (0, key(2), b"\x89PNG\r\n\x1a\nxx")]),
+        leaf(6, [(0, key(3), b"third"), (0, key(4), b"B" * 1500)], overflow=1),
+    ]
+    loop = [
+        meta(0, 3, 1),
+        meta(1, 3, 0),
+        freelist(2),
+        leaf(3, [(1, b"b", bucket(4))]),
+        branch(4, [(key(1), 4), (key(2), 4)]),
+    ]
+    for name, pages in [("inline", inline), ("branch", tree), ("loop", loop)]:
+        with open(os.path.join(dir, f"cliphist-{name}.db"), "wb") as file:
+            file.write(b"".join(pages))
+
+
+if __name__ == "__main__":
+    main(sys.argv[1] if len(sys.argv) > 1 else os.path.dirname(__file__))